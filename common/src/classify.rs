@@ -0,0 +1,450 @@
+//! 运行时可改的报文分类规则
+//!
+//! 源、目的地址先各自在LPM表`SRC_NETS`、`DST_NETS`中按最长前缀归入一个网段编号，
+//! 再以（源网段，目的网段，协议，服务端口，DSCP）查哈希表`RULES`得到动作。
+//! 服务端口对hardworker与logger是目的端口，对sensor是源端口。
+//! 规则中的网段与DSCP可以是通配，查找时先精确后通配。
+//! 地址只归入最长前缀的那一个网段，较短前缀的规则对它不生效，因此同一张表中的前缀不能重叠
+
+/// `SRC_NETS`、`DST_NETS`的容量
+pub const MAX_NETS: u32 = 256;
+/// `RULES`的容量
+pub const MAX_RULES: u32 = 1024;
+/// 规则中表示任意地址的网段编号，没有命中任何前缀的地址也归入它
+pub const ANY_NET: u16 = 0;
+/// 规则中表示任意DSCP
+pub const ANY_DSCP: u8 = 0xff;
+/// `RULES`的值中标记加载器按配置写入的默认规则，其余位是动作
+pub const DEFAULT_RULE: u8 = 0x80;
+
+/// 命中规则后的动作，各程序的含义：
+///
+/// * hardworker：`Capture`上报并回复ACK或转发，`Forward`只转发给logger
+/// * logger与sensor：`Capture`与`Forward`都做原有的地址还原
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Debug))]
+pub enum Action {
+    Capture = 1,
+    Forward = 2,
+    /// 原样交给协议栈
+    Pass = 3,
+    Drop = 4,
+}
+
+impl Action {
+    /// 从`RULES`的值解析，忽略默认规则标记
+    #[inline(always)]
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value & !DEFAULT_RULE {
+            1 => Some(Self::Capture),
+            2 => Some(Self::Forward),
+            3 => Some(Self::Pass),
+            4 => Some(Self::Drop),
+            _ => None,
+        }
+    }
+}
+
+/// [`lookup`]的结果：动作，以及命中的规则是否要求DSCP
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Debug))]
+pub struct Match {
+    pub action: Action,
+    /// 规则写明了DSCP，即按标记TOS命中；否则只按端口命中
+    pub tos: bool,
+}
+
+/// `RULES`的键，端口按主机字节序存储
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "config", derive(Debug))]
+pub struct RuleKey {
+    pub src_net: u16,
+    pub dst_net: u16,
+    pub port: u16,
+    pub proto: u8,
+    pub dscp: u8,
+}
+
+impl RuleKey {
+    pub const fn new(src_net: u16, dst_net: u16, proto: u8, port: u16, dscp: u8) -> Self {
+        Self {
+            src_net,
+            dst_net,
+            port,
+            proto,
+            dscp,
+        }
+    }
+}
+
+/// 按报文的网段、协议、服务端口与DSCP查找动作，`get`查`RULES`
+///
+/// 依次尝试源与目的网段都精确、只有源精确、只有目的精确、都通配，每种再先精确后通配DSCP
+#[inline(always)]
+pub fn lookup(
+    src_net: u16,
+    dst_net: u16,
+    proto: u8,
+    port: u16,
+    dscp: u8,
+    get: impl Fn(&RuleKey) -> Option<u8>,
+) -> Option<Match> {
+    for (src_net, dst_net) in [
+        (src_net, dst_net),
+        (src_net, ANY_NET),
+        (ANY_NET, dst_net),
+        (ANY_NET, ANY_NET),
+    ] {
+        for dscp in [dscp, ANY_DSCP] {
+            if let Some(action) =
+                get(&RuleKey::new(src_net, dst_net, proto, port, dscp)).and_then(Action::from_u8)
+            {
+                return Some(Match {
+                    action,
+                    tos: dscp != ANY_DSCP,
+                });
+            }
+        }
+    }
+    None
+}
+
+#[cfg(feature = "config")]
+pub use rule::{Prefix, Rule};
+#[cfg(feature = "user")]
+pub use rules::Rules;
+
+#[cfg(feature = "config")]
+mod rule {
+    use std::{
+        fmt, format,
+        net::Ipv4Addr,
+        str::FromStr,
+        string::{String, ToString},
+    };
+
+    use super::Action;
+    use crate::packet::{IPPROTO_TCP, IPPROTO_UDP};
+
+    impl FromStr for Action {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "capture" => Ok(Self::Capture),
+                "forward" => Ok(Self::Forward),
+                "pass" => Ok(Self::Pass),
+                "drop" => Ok(Self::Drop),
+                _ => Err(format!("未知动作{s}，可选capture、forward、pass、drop")),
+            }
+        }
+    }
+
+    impl fmt::Display for Action {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(match self {
+                Self::Capture => "capture",
+                Self::Forward => "forward",
+                Self::Pass => "pass",
+                Self::Drop => "drop",
+            })
+        }
+    }
+
+    /// IPv4前缀，主机位必须为0
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Prefix {
+        pub addr: Ipv4Addr,
+        pub len: u8,
+    }
+
+    impl FromStr for Prefix {
+        type Err = String;
+
+        /// 接受`10.0.0.0/8`，省略长度时为/32
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let (addr, len) = s.split_once('/').unwrap_or((s, "32"));
+            let addr: Ipv4Addr = addr.parse().map_err(|_| format!("{s}不是IPv4前缀"))?;
+            let len = match len.parse::<u8>() {
+                Ok(len) if len <= 32 => len,
+                _ => return Err(format!("{s}的前缀长度必须在0到32之间")),
+            };
+            let mask = u32::MAX.checked_shl(32 - len as u32).unwrap_or(0);
+            if addr.to_bits() & !mask != 0 {
+                return Err(format!("{s}的主机位不为0"));
+            }
+            Ok(Self { addr, len })
+        }
+    }
+
+    impl Prefix {
+        /// 两个前缀是否有共同的地址，即一个包含另一个
+        pub fn overlaps(&self, other: &Prefix) -> bool {
+            let len = self.len.min(other.len) as u32;
+            let mask = u32::MAX.checked_shl(32 - len).unwrap_or(0);
+            self.addr.to_bits() & mask == other.addr.to_bits() & mask
+        }
+    }
+
+    impl fmt::Display for Prefix {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}/{}", self.addr, self.len)
+        }
+    }
+
+    /// 一条分类规则，网段或DSCP为`None`表示通配
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Rule {
+        pub src: Option<Prefix>,
+        pub dst: Option<Prefix>,
+        pub proto: u8,
+        pub port: u16,
+        pub dscp: Option<u8>,
+        pub action: Action,
+    }
+
+    impl fmt::Display for Rule {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let any = |prefix: Option<Prefix>| prefix.map_or("any".to_string(), |p| p.to_string());
+            write!(
+                f,
+                "{} -> {} {} {} dscp {} => {}",
+                any(self.src),
+                any(self.dst),
+                proto_name(self.proto),
+                self.port,
+                self.dscp.map_or("any".to_string(), |dscp| dscp.to_string()),
+                self.action
+            )
+        }
+    }
+
+    /// 解析`tcp`、`udp`或协议号
+    pub fn parse_proto(s: &str) -> Result<u8, String> {
+        match s {
+            "tcp" => Ok(IPPROTO_TCP),
+            "udp" => Ok(IPPROTO_UDP),
+            _ => s
+                .parse()
+                .map_err(|_| format!("未知协议{s}，可选tcp、udp或协议号")),
+        }
+    }
+
+    fn proto_name(proto: u8) -> String {
+        match proto {
+            IPPROTO_TCP => "tcp".to_string(),
+            IPPROTO_UDP => "udp".to_string(),
+            _ => proto.to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "config")]
+pub use rule::parse_proto;
+
+#[cfg(feature = "user")]
+mod rules {
+    use std::{collections::BTreeSet, format, path::Path, vec::Vec};
+
+    use anyhow::{anyhow, bail, Context as _};
+    use aya::maps::{lpm_trie::Key, HashMap, LpmTrie, Map, MapData};
+
+    use super::{Action, Prefix, Rule, RuleKey, ANY_DSCP, ANY_NET, DEFAULT_RULE, MAX_NETS};
+
+    /// 一个程序的三张分类表，可以来自刚加载的程序，也可以来自bpffs中的固定路径
+    pub struct Rules {
+        src_nets: LpmTrie<MapData, u32, u16>,
+        dst_nets: LpmTrie<MapData, u32, u16>,
+        rules: HashMap<MapData, RuleKey, u8>,
+    }
+
+    impl Rules {
+        pub fn new(src_nets: Map, dst_nets: Map, rules: Map) -> anyhow::Result<Self> {
+            Ok(Self {
+                src_nets: LpmTrie::try_from(src_nets)?,
+                dst_nets: LpmTrie::try_from(dst_nets)?,
+                rules: HashMap::try_from(rules)?,
+            })
+        }
+
+        /// 打开加载器固定在`dir`下的表
+        pub fn open(dir: &Path) -> anyhow::Result<Self> {
+            let open = |name: &str| {
+                let path = dir.join(name);
+                MapData::from_pin(&path)
+                    .with_context(|| format!("打开{}失败，考虑加载器未运行", path.display()))
+            };
+            Self::new(
+                Map::LpmTrie(open("SRC_NETS")?),
+                Map::LpmTrie(open("DST_NETS")?),
+                Map::HashMap(open("RULES")?),
+            )
+        }
+
+        /// 添加或覆盖一条规则，前缀与表中已有的前缀重叠时拒绝
+        pub fn insert(&mut self, rule: &Rule) -> anyhow::Result<()> {
+            self.put(rule, rule.action as u8)
+        }
+
+        /// 换上按配置生成的默认规则，返回写入的条数
+        ///
+        /// 表固定在bpffs中跨加载复用：上次写入而这次配置中没有的默认规则被删除；
+        /// 用`myapp rule`添加的规则保留，匹配条件相同时优先于默认规则
+        pub fn replace_defaults(&mut self, defaults: &[Rule]) -> anyhow::Result<usize> {
+            let existing = self.list()?;
+            for (rule, default) in &existing {
+                if *default && !defaults.contains(rule) {
+                    self.remove(rule)?;
+                }
+            }
+            let mut written = 0;
+            for rule in defaults {
+                let overridden = existing.iter().any(|(other, default)| {
+                    !default
+                        && Rule {
+                            action: rule.action,
+                            ..*other
+                        } == *rule
+                });
+                if !overridden {
+                    self.put(rule, rule.action as u8 | DEFAULT_RULE)?;
+                    written += 1;
+                }
+            }
+            Ok(written)
+        }
+
+        fn put(&mut self, rule: &Rule, value: u8) -> anyhow::Result<()> {
+            let src_net = net_id(&mut self.src_nets, rule.src)?;
+            let dst_net = net_id(&mut self.dst_nets, rule.dst)?;
+            let key = RuleKey::new(
+                src_net,
+                dst_net,
+                rule.proto,
+                rule.port,
+                rule.dscp.unwrap_or(ANY_DSCP),
+            );
+            self.rules.insert(key, value, 0)?;
+            Ok(())
+        }
+
+        /// 删除一条规则，不再被引用的网段一并删除
+        pub fn remove(&mut self, rule: &Rule) -> anyhow::Result<()> {
+            let find = |nets: &LpmTrie<MapData, u32, u16>, prefix| match prefix {
+                Some(prefix) => {
+                    find_net(nets, prefix)?.ok_or_else(|| anyhow!("没有网段{prefix}的规则"))
+                }
+                None => Ok(ANY_NET),
+            };
+            let key = RuleKey::new(
+                find(&self.src_nets, rule.src)?,
+                find(&self.dst_nets, rule.dst)?,
+                rule.proto,
+                rule.port,
+                rule.dscp.unwrap_or(ANY_DSCP),
+            );
+            self.rules
+                .remove(&key)
+                .map_err(|_| anyhow!("没有规则{rule}"))?;
+
+            let keys = self.rules.keys().collect::<Result<Vec<_>, _>>()?;
+            if let Some(prefix) = rule.src {
+                if keys.iter().all(|k| k.src_net != key.src_net) {
+                    self.src_nets.remove(&lpm_key(prefix))?;
+                }
+            }
+            if let Some(prefix) = rule.dst {
+                if keys.iter().all(|k| k.dst_net != key.dst_net) {
+                    self.dst_nets.remove(&lpm_key(prefix))?;
+                }
+            }
+            Ok(())
+        }
+
+        /// 全部规则，按表中的顺序，同时给出是否为默认规则
+        pub fn list(&self) -> anyhow::Result<Vec<(Rule, bool)>> {
+            let src_nets = nets(&self.src_nets)?;
+            let dst_nets = nets(&self.dst_nets)?;
+            let prefix = |nets: &[(Prefix, u16)], id: u16| -> anyhow::Result<Option<Prefix>> {
+                if id == ANY_NET {
+                    return Ok(None);
+                }
+                nets.iter()
+                    .find(|(_, net)| *net == id)
+                    .map(|(prefix, _)| Some(*prefix))
+                    .ok_or_else(|| anyhow!("网段编号{id}没有对应的前缀"))
+            };
+            let mut rules = Vec::new();
+            for entry in self.rules.iter() {
+                let (key, value) = entry?;
+                let Some(action) = Action::from_u8(value) else {
+                    bail!("规则的动作{value}无效");
+                };
+                let rule = Rule {
+                    src: prefix(&src_nets, key.src_net)?,
+                    dst: prefix(&dst_nets, key.dst_net)?,
+                    proto: key.proto,
+                    port: key.port,
+                    dscp: (key.dscp != ANY_DSCP).then_some(key.dscp),
+                    action,
+                };
+                rules.push((rule, value & DEFAULT_RULE != 0));
+            }
+            Ok(rules)
+        }
+    }
+
+    /// 地址保持网络字节序，LPM按字节从高位比较
+    fn lpm_key(prefix: Prefix) -> Key<u32> {
+        Key::new(prefix.len as u32, u32::from_ne_bytes(prefix.addr.octets()))
+    }
+
+    fn nets(nets: &LpmTrie<MapData, u32, u16>) -> anyhow::Result<Vec<(Prefix, u16)>> {
+        nets.iter()
+            .map(|entry| {
+                let (key, id) = entry?;
+                let prefix = Prefix {
+                    addr: key.data().to_ne_bytes().into(),
+                    len: key.prefix_len() as u8,
+                };
+                Ok((prefix, id))
+            })
+            .collect()
+    }
+
+    fn find_net(nets: &LpmTrie<MapData, u32, u16>, prefix: Prefix) -> anyhow::Result<Option<u16>> {
+        Ok(self::nets(nets)?
+            .into_iter()
+            .find(|(p, _)| *p == prefix)
+            .map(|(_, id)| id))
+    }
+
+    /// 找到前缀已有的编号，没有时分配最小的空闲编号
+    ///
+    /// 地址只归入最长前缀的网段，与已有前缀重叠的新前缀会遮住较短前缀的规则，直接拒绝
+    fn net_id(
+        nets: &mut LpmTrie<MapData, u32, u16>,
+        prefix: Option<Prefix>,
+    ) -> anyhow::Result<u16> {
+        let Some(prefix) = prefix else {
+            return Ok(ANY_NET);
+        };
+        let existing = self::nets(nets)?;
+        if let Some((_, id)) = existing.iter().find(|(p, _)| *p == prefix) {
+            return Ok(*id);
+        }
+        if let Some((other, _)) = existing.iter().find(|(p, _)| p.overlaps(&prefix)) {
+            bail!(
+                "网段{prefix}与已有的{other}重叠，地址只归入最长前缀的网段，较短前缀的规则会失效"
+            );
+        }
+        let used: BTreeSet<u16> = existing.iter().map(|(_, id)| *id).collect();
+        let id = (1..=MAX_NETS as u16)
+            .find(|id| !used.contains(id))
+            .ok_or_else(|| anyhow!("网段已满{MAX_NETS}个"))?;
+        nets.insert(&lpm_key(prefix), id, 0)?;
+        Ok(id)
+    }
+}
@@ -0,0 +1,1228 @@
+//! `const.toml`的解析与校验
+//!
+//! 构建脚本与用户态程序共用这一套规则，错误信息带上键名与行号，
+//! 一次解析会收集所有问题而不是遇到第一个就退出
+
+use std::{
+    collections::HashSet,
+    fmt, format, fs,
+    net::Ipv4Addr,
+    ops::Range,
+    path::{Path, PathBuf},
+    str::FromStr,
+    string::{String, ToString},
+    time::Duration,
+    vec::Vec,
+};
+
+use anyhow::{anyhow, bail, Context as _};
+use serde::{Deserialize, Serialize, Serializer};
+use toml::Spanned;
+
+use crate::{
+    classify::{Action, Rule},
+    packet::{IPPROTO_TCP, IPPROTO_UDP},
+    record::RecordHeader,
+    Data, Marking, Peer, Route, MAX_LOGGERS, MAX_MARK_PORTS,
+};
+
+/// IPv4与TCP的固定头部长度，对应ebpf侧的`Ipv4Hdr::LEN + TcpHdr::LEN`
+pub const HEADERS_LEN: usize = 20 + 20;
+
+/// ring buffer在每条记录前附加的头部长度
+const RINGBUF_HDR_LEN: usize = 8;
+/// `[ring]`缺省时的记录条数
+pub const DEFAULT_RING_RECORDS: u32 = 256;
+/// `ring.records`的上限
+pub const MAX_RING_RECORDS: u32 = 1 << 16;
+/// `TARGET_MAP`的字节数上限
+const MAX_RING_BYTES: usize = 1 << 30;
+/// `[udp]`中`timeout_ms`与`retries`缺省时的取值
+pub const DEFAULT_UDP_TIMEOUT_MS: u32 = 200;
+pub const DEFAULT_UDP_RETRIES: u32 = 5;
+/// `udp.timeout_ms`的上限
+const MAX_UDP_TIMEOUT_MS: u32 = 60_000;
+/// `udp.retries`的上限
+const MAX_UDP_RETRIES: u32 = 100;
+/// `[store]`中各项缺省时的取值
+pub const DEFAULT_SEGMENT_MB: u32 = 16;
+pub const DEFAULT_MAX_AGE_HOURS: u32 = 24 * 7;
+pub const DEFAULT_MAX_MB: u32 = 1024;
+/// `store.segment_mb`的上限
+const MAX_SEGMENT_MB: u32 = 1024;
+/// `store.max_age_hours`的上限，十年
+const MAX_AGE_HOURS: u32 = 24 * 365 * 10;
+
+/// 构建时由`myapp`的构建脚本把`[data]`传给ebpf程序构建脚本的环境变量，格式为`mtu,load_u64_count`
+pub const DATA_ENV: &str = "MYAPP_DATA";
+
+/// 各程序固定分类表的bpffs目录
+pub const PIN_ROOT: &str = "/sys/fs/bpf/myapp";
+/// cgroup v2的挂载点，`sensor.cgroup`必须在它之下
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// `const.toml`的JSON schema，供编辑器补全与提示
+///
+/// TOS的位规则恰好等价于64到254之间的偶数，schema中直接用范围表达
+pub const SCHEMA: &str = include_str!("schema.json");
+
+/// 解析并校验后的`const.toml`
+#[derive(Debug, Clone, Serialize)]
+pub struct Consts {
+    pub mark: Mark,
+    pub data: Data,
+    pub ring: Ring,
+    pub hardworker: Hardworker,
+    pub sensor: Sensor,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub udp: Option<Udp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<Store>,
+    #[serde(rename = "node")]
+    pub nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Sensor,
+    Hardworker,
+    Logger,
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sensor" => Ok(Role::Sensor),
+            "hardworker" => Ok(Role::Hardworker),
+            "logger" => Ok(Role::Logger),
+            _ => Err(format!("未知角色{s}，可选sensor、hardworker、logger")),
+        }
+    }
+}
+
+impl Role {
+    /// 该角色的程序固定`SRC_NETS`、`DST_NETS`、`RULES`的目录
+    pub fn pin_dir(&self) -> PathBuf {
+        Path::new(PIN_ROOT).join(self.to_string())
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::Sensor => "sensor",
+            Role::Hardworker => "hardworker",
+            Role::Logger => "logger",
+        })
+    }
+}
+
+/// 拓扑中的一个节点
+///
+/// sensor节点通过`hardworker`与`loggers`指明自己的数据发往哪个hardworker、
+/// 转发给哪组logger，其他角色的这两项为空
+#[derive(Debug, Clone, Serialize)]
+pub struct Node {
+    pub name: String,
+    pub role: Role,
+    #[serde(serialize_with = "serialize_mac")]
+    pub mac: [u8; 6],
+    pub ip: Ipv4Addr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hardworker: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub loggers: Vec<String>,
+    /// logger实际监听的端口，缺省与`mark.port`相同，logger的XDP程序把目的端口改写为它
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// 数据面程序的挂载点
+    #[serde(skip_serializing_if = "Hook::is_xdp")]
+    pub hook: Hook,
+}
+
+/// 节点数据面程序的挂载点
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Hook {
+    /// 网卡的XDP，开销最小，模式由`--xdp-mode`选择
+    #[default]
+    Xdp,
+    /// clsact的ingress，XDP表现不好的网卡上使用
+    Tc,
+}
+
+impl Hook {
+    fn is_xdp(&self) -> bool {
+        *self == Hook::Xdp
+    }
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Hook::Xdp => "xdp",
+            Hook::Tc => "tc",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Mark {
+    pub tos: u8,
+    pub port: u16,
+}
+
+/// hardworker上报记录的ring buffer
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Ring {
+    /// 按最大记录算，ring buffer至少能容纳的条数
+    pub records: u32,
+}
+
+impl Default for Ring {
+    fn default() -> Self {
+        Self {
+            records: DEFAULT_RING_RECORDS,
+        }
+    }
+}
+
+/// hardworker的行为开关
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hardworker {
+    /// 在XDP中为按序到达的数据段回复ACK，不再依赖logger的协议栈，
+    /// 负载改由用户态尽力转发给logger
+    #[serde(default)]
+    pub ack: bool,
+    /// 待上报的数据段交给用户态的路径
+    #[serde(default)]
+    pub capture: Capture,
+}
+
+/// hardworker上报负载的路径
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Capture {
+    /// XDP把负载拷贝进`TARGET_MAP`，用户态再拷贝出来
+    #[default]
+    Ring,
+    /// XDP把整帧重定向到AF_XDP socket，用户态直接在UMEM中处理并发出
+    Xsk,
+}
+
+impl fmt::Display for Capture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Capture::Ring => "ring",
+            Capture::Xsk => "xsk",
+        })
+    }
+}
+
+/// sensor的出口标记，应用不必自己设置`IP_TOS`
+///
+/// 发往本sensor的hardworker、目的端口在`ports`中或来自`cgroup`的报文被打上`mark.tos`
+#[derive(Debug, Clone, Serialize)]
+pub struct Sensor {
+    /// 缺省为`mark.port`与`udp.port`，为空且没有`cgroup`时不挂载出口程序
+    pub ports: Vec<u16>,
+    /// cgroup v2目录，其中（含子cgroup）进程发往hardworker的报文不论端口都打标记
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cgroup: Option<PathBuf>,
+}
+
+impl Sensor {
+    /// `cgroup`在cgroup v2层级中的深度，根为0，即`bpf_skb_ancestor_cgroup_id`的`ancestor_level`
+    pub fn cgroup_level(&self) -> Option<u32> {
+        let path = self.cgroup.as_ref()?;
+        let relative = path.strip_prefix(CGROUP_ROOT).ok()?;
+        Some(relative.components().count() as u32)
+    }
+}
+
+/// sensor到hardworker的可靠UDP传输，`[udp]`缺省时不启用
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Udp {
+    /// hardworker接收数据报的端口，TOS沿用`mark.tos`
+    pub port: u16,
+    /// sender等待ACK的超时
+    pub timeout_ms: u32,
+    /// 超时后的重传次数
+    pub retries: u32,
+}
+
+impl Udp {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms as u64)
+    }
+}
+
+/// logger的持久化日志存储，`[store]`缺省时日志只打印不存储
+#[derive(Debug, Clone, Serialize)]
+pub struct Store {
+    /// 段文件所在目录，不存在时自动创建
+    pub dir: PathBuf,
+    /// 单个段文件的大小上限，写满后滚动到新段
+    pub segment_mb: u32,
+    /// 只保留最近多少小时的日志，0表示不按时间清理
+    pub max_age_hours: u32,
+    /// 所有段的总大小上限，0表示不按大小清理
+    pub max_mb: u32,
+}
+
+impl Store {
+    pub fn segment_bytes(&self) -> u64 {
+        self.segment_mb as u64 * 1024 * 1024
+    }
+
+    /// 0表示不限
+    pub fn max_age(&self) -> Option<Duration> {
+        (self.max_age_hours != 0).then(|| Duration::from_secs(self.max_age_hours as u64 * 3600))
+    }
+
+    /// 0表示不限
+    pub fn max_bytes(&self) -> Option<u64> {
+        (self.max_mb != 0).then(|| self.max_mb as u64 * 1024 * 1024)
+    }
+}
+
+/// 单条配置错误
+#[derive(Debug, Clone)]
+pub struct ConfigError {
+    /// 出错的键，如`mark.tos`、`node[2].ip`
+    pub key: String,
+    /// 从1开始的行号，toml无法定位时为空
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{} (第{}行): {}", self.key, line, self.message),
+            None => write!(f, "{}: {}", self.key, self.message),
+        }
+    }
+}
+
+/// 一次解析收集到的全部错误
+#[derive(Debug, Clone)]
+pub struct ConfigErrors(pub Vec<ConfigError>);
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConsts {
+    mark: RawMark,
+    data: RawData,
+    ring: Option<RawRing>,
+    #[serde(default)]
+    hardworker: Hardworker,
+    sensor: Option<RawSensor>,
+    udp: Option<RawUdp>,
+    store: Option<RawStore>,
+    node: Vec<RawNode>,
+}
+
+/// 只取`[data]`，其余的表既不解析也不校验
+#[derive(Deserialize)]
+struct RawDataOnly {
+    data: RawData,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawNode {
+    name: Spanned<String>,
+    role: Role,
+    mac: Spanned<String>,
+    ip: Spanned<Ipv4Addr>,
+    hardworker: Option<Spanned<String>>,
+    loggers: Option<Spanned<Vec<String>>>,
+    port: Option<Spanned<i64>>,
+    hook: Option<Spanned<Hook>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawMark {
+    tos: Spanned<i64>,
+    port: Spanned<i64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawData {
+    mtu: Spanned<i64>,
+    load_u64_count: Spanned<i64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRing {
+    records: Spanned<i64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSensor {
+    ports: Option<Spanned<Vec<i64>>>,
+    cgroup: Option<Spanned<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawUdp {
+    port: Spanned<i64>,
+    timeout_ms: Option<Spanned<i64>>,
+    retries: Option<Spanned<i64>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawStore {
+    dir: Spanned<String>,
+    segment_mb: Option<Spanned<i64>>,
+    max_age_hours: Option<Spanned<i64>>,
+    max_mb: Option<Spanned<i64>>,
+}
+
+impl Data {
+    /// 构建脚本使用，只读取并校验编译时固化的`[data]`，节点与标记的改动不影响构建
+    pub fn from_path(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let src = fs::read_to_string(path)
+            .with_context(|| format!("读取配置文件{}失败", path.display()))?;
+        Self::parse(&src).with_context(|| format!("配置文件{}的[data]校验失败", path.display()))
+    }
+
+    /// 解析并校验配置内容中的`[data]`
+    pub fn parse(src: &str) -> Result<Self, ConfigErrors> {
+        let raw: RawDataOnly =
+            toml::from_str(src).map_err(|e| ConfigErrors(Vec::from([toml_error(src, &e)])))?;
+        let mut checker = Checker {
+            src,
+            errors: Vec::new(),
+        };
+        let data = checker.data(&raw.data);
+        if checker.errors.is_empty() {
+            Ok(data)
+        } else {
+            Err(ConfigErrors(checker.errors))
+        }
+    }
+
+    /// [`DATA_ENV`]的取值
+    pub fn to_env(&self) -> String {
+        format!("{},{}", self.mtu, self.load_u64_count)
+    }
+
+    /// 解析[`DATA_ENV`]，格式不对时为`None`
+    pub fn from_env(value: &str) -> Option<Self> {
+        let (mtu, load_u64_count) = value.split_once(',')?;
+        Some(Self {
+            mtu: mtu.trim().parse().ok()?,
+            load_u64_count: load_u64_count.trim().parse().ok()?,
+        })
+    }
+
+    /// 构建脚本使用，生成`DATA`常量的声明
+    pub fn declaration(&self) -> String {
+        format!(
+            "#[allow(unused)]\nconst DATA: common::Data = common::Data {{ mtu: {}, load_u64_count: {} }};\n",
+            self.mtu, self.load_u64_count
+        )
+    }
+}
+
+impl Consts {
+    /// 读取并校验配置文件
+    pub fn from_path(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let src = fs::read_to_string(path)
+            .with_context(|| format!("读取配置文件{}失败", path.display()))?;
+        Self::parse(&src).with_context(|| format!("配置文件{}校验失败", path.display()))
+    }
+
+    /// 解析并校验配置内容
+    pub fn parse(src: &str) -> Result<Self, ConfigErrors> {
+        let raw: RawConsts =
+            toml::from_str(src).map_err(|e| ConfigErrors(Vec::from([toml_error(src, &e)])))?;
+        let mut checker = Checker {
+            src,
+            errors: Vec::new(),
+        };
+
+        let nodes = checker.nodes(&raw.node);
+        checker.hooks(&raw.node, &raw.hardworker);
+        let tos = checker.tos(&raw.mark.tos);
+        let port = checker.port("mark.port", &raw.mark.port);
+        let data = checker.data(&raw.data);
+        let ring = raw
+            .ring
+            .as_ref()
+            .map(|ring| checker.ring(ring, &data))
+            .unwrap_or_default();
+        let udp = raw.udp.as_ref().map(|udp| checker.udp(udp, port));
+        let sensor = checker.sensor(raw.sensor.as_ref(), port, udp.as_ref());
+        let store = raw.store.as_ref().map(|store| checker.store(store));
+
+        if checker.errors.is_empty() {
+            Ok(Self {
+                mark: Mark { tos, port },
+                data,
+                ring,
+                hardworker: raw.hardworker,
+                sensor,
+                udp,
+                store,
+                nodes,
+            })
+        } else {
+            Err(ConfigErrors(checker.errors))
+        }
+    }
+
+    /// 规范化输出：键顺序固定，MAC统一为小写
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("Consts只包含toml可表示的类型")
+    }
+
+    /// 单条记录在ring buffer中占用的最大字节数
+    pub fn record_size(&self) -> usize {
+        record_size(&self.data)
+    }
+
+    /// `TARGET_MAP`的字节数，内核要求是页大小的2的幂倍
+    pub fn ring_byte_size(&self, page_size: usize) -> u32 {
+        let bytes = (self.ring.records as usize * self.record_size()).max(page_size);
+        bytes.next_power_of_two().min(MAX_RING_BYTES) as u32
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Node> {
+        self.nodes.iter().find(|node| node.name == name)
+    }
+
+    /// 找到本机对应的节点
+    ///
+    /// 指定了`name`时按名字查找并确认角色，否则要求该角色只有一个节点
+    pub fn node(&self, role: Role, name: Option<&str>) -> anyhow::Result<&Node> {
+        if let Some(name) = name {
+            let node = self
+                .get(name)
+                .ok_or_else(|| anyhow!("配置中没有名为{name}的节点"))?;
+            if node.role != role {
+                bail!("节点{name}的角色是{}而不是{role}", node.role);
+            }
+            return Ok(node);
+        }
+        let mut nodes = self.nodes.iter().filter(|node| node.role == role);
+        match (nodes.next(), nodes.next()) {
+            (Some(node), None) => Ok(node),
+            (None, _) => bail!("配置中没有{role}节点"),
+            (Some(_), Some(_)) => bail!("配置中有多个{role}节点，需要用--node指定"),
+        }
+    }
+
+    /// 可能是本机的节点，按角色与名字筛选，两者都省略时为全部节点
+    pub fn candidates(&self, role: Option<Role>, name: Option<&str>) -> anyhow::Result<Vec<&Node>> {
+        if let Some(name) = name {
+            let node = self
+                .get(name)
+                .ok_or_else(|| anyhow!("配置中没有名为{name}的节点"))?;
+            match role {
+                Some(role) if node.role != role => {
+                    bail!("节点{name}的角色是{}而不是{role}", node.role)
+                }
+                _ => return Ok(std::vec![node]),
+            }
+        }
+        let nodes: Vec<&Node> = self
+            .nodes
+            .iter()
+            .filter(|node| role.is_none_or(|role| node.role == role))
+            .collect();
+        match role {
+            Some(role) if nodes.is_empty() => bail!("配置中没有{role}节点"),
+            _ => Ok(nodes),
+        }
+    }
+
+    /// sensor在可靠UDP头部中使用的id，即它在所有sensor中的序号
+    pub fn sensor_id(&self, sensor: &Node) -> u16 {
+        self.nodes
+            .iter()
+            .filter(|node| node.role == Role::Sensor)
+            .position(|node| node.name == sensor.name)
+            .unwrap_or_default() as u16
+    }
+
+    /// 生成`node`所在ebpf程序的`ROUTES`表项，键为网络字节序的源IP
+    ///
+    /// * hardworker：以发往自己的sensor的IP为键
+    /// * logger：以转发给自己的sensor的IP为键
+    /// * sensor：以自己的各个logger的IP为键，logger的回包要伪装成hardworker发出
+    pub fn routes(&self, node: &Node) -> Vec<(u32, Route)> {
+        let sensors = self.nodes.iter().filter(|s| s.role == Role::Sensor);
+        match node.role {
+            Role::Hardworker => sensors
+                .filter(|s| s.hardworker.as_deref() == Some(node.name.as_str()))
+                .map(|s| (route_key(s.ip), self.route(s)))
+                .collect(),
+            Role::Logger => sensors
+                .filter(|s| s.loggers.contains(&node.name))
+                .map(|s| (route_key(s.ip), self.route(s)))
+                .collect(),
+            Role::Sensor => node
+                .loggers
+                .iter()
+                .filter_map(|name| self.get(name))
+                .map(|logger| (route_key(logger.ip), self.route(node)))
+                .collect(),
+        }
+    }
+
+    /// 与`node`同在某个sensor的路由上、需要互相解析MAC的其他节点，按配置中的顺序
+    ///
+    /// 一个sensor的路由由它自己、它的hardworker与它的logger组组成
+    pub fn peers(&self, node: &Node) -> Vec<&Node> {
+        let groups: Vec<Vec<&str>> = self
+            .nodes
+            .iter()
+            .filter(|s| s.role == Role::Sensor)
+            .map(|s| {
+                core::iter::once(s.name.as_str())
+                    .chain(s.hardworker.as_deref())
+                    .chain(s.loggers.iter().map(String::as_str))
+                    .collect()
+            })
+            .filter(|group: &Vec<&str>| group.contains(&node.name.as_str()))
+            .collect();
+        self.nodes
+            .iter()
+            .filter(|peer| {
+                peer.name != node.name
+                    && groups
+                        .iter()
+                        .any(|group| group.contains(&peer.name.as_str()))
+            })
+            .collect()
+    }
+
+    /// sensor出口程序的标记规则，见[`Sensor`]
+    ///
+    /// `cgroup`为`sensor.cgroup`的cgroup id与深度，由调用方从文件系统读出
+    pub fn marking(&self, sensor: &Node, cgroup: Option<(u64, u32)>) -> Marking {
+        let Some(hardworker) = sensor.hardworker.as_deref().and_then(|name| self.get(name)) else {
+            return Marking::zeroed();
+        };
+        Marking::new(
+            route_key(hardworker.ip),
+            self.mark.tos,
+            &self.sensor.ports,
+            cgroup,
+        )
+    }
+
+    /// 加载时写入`RULES`的默认规则，对应原先固定的TOS与端口匹配
+    ///
+    /// * hardworker：捕获带标记TOS发往`mark.port`的TCP段，以及发往`udp.port`的数据报
+    /// * logger：还原带标记TOS发往`mark.port`的TCP段
+    /// * sensor：还原各logger从自己的端口发回的TCP段，回包不带标记TOS
+    pub fn rules(&self, node: &Node) -> Vec<Rule> {
+        let dscp = Some(self.mark.tos >> 2);
+        let rule = |proto, port, dscp, action| Rule {
+            src: None,
+            dst: None,
+            proto,
+            port,
+            dscp,
+            action,
+        };
+        match node.role {
+            Role::Hardworker => {
+                core::iter::once(rule(IPPROTO_TCP, self.mark.port, dscp, Action::Capture))
+                    .chain(
+                        self.udp
+                            .map(|udp| rule(IPPROTO_UDP, udp.port, dscp, Action::Capture)),
+                    )
+                    .collect()
+            }
+            Role::Logger => std::vec![rule(IPPROTO_TCP, self.mark.port, dscp, Action::Forward)],
+            Role::Sensor => {
+                let mut ports: Vec<u16> = node
+                    .loggers
+                    .iter()
+                    .filter_map(|name| self.get(name))
+                    .map(|logger| self.peer(logger).port)
+                    .collect();
+                ports.sort_unstable();
+                ports.dedup();
+                ports
+                    .into_iter()
+                    .map(|port| rule(IPPROTO_TCP, port, None, Action::Forward))
+                    .collect()
+            }
+        }
+    }
+
+    fn route(&self, sensor: &Node) -> Route {
+        let mut loggers = [Peer::zeroed(); MAX_LOGGERS];
+        let mut logger_count = 0;
+        for (slot, logger) in loggers
+            .iter_mut()
+            .zip(sensor.loggers.iter().filter_map(|name| self.get(name)))
+        {
+            *slot = self.peer(logger);
+            logger_count += 1;
+        }
+        let hardworker = sensor
+            .hardworker
+            .as_deref()
+            .and_then(|name| self.get(name))
+            .map(|hardworker| self.peer(hardworker))
+            .unwrap_or(Peer::zeroed());
+        Route::new(self.peer(sensor), hardworker, loggers, logger_count)
+    }
+
+    /// 节点在路由中的地址与接收端口
+    pub fn peer(&self, node: &Node) -> Peer {
+        let port = match node.role {
+            Role::Sensor => 0,
+            Role::Hardworker => self.mark.port,
+            Role::Logger => node.port.unwrap_or(self.mark.port),
+        };
+        Peer::new(node.ip.to_bits(), node.mac, port)
+    }
+}
+
+impl From<&Consts> for crate::Config {
+    fn from(consts: &Consts) -> Self {
+        let mut config = crate::Config::zeroed();
+        if consts.hardworker.ack {
+            config.flags |= crate::Config::ACK;
+        }
+        if consts.hardworker.capture == Capture::Xsk {
+            config.flags |= crate::Config::XSK;
+        }
+        config
+    }
+}
+
+/// 含ring buffer自身的头部并按8字节对齐
+fn record_size(data: &Data) -> usize {
+    (RINGBUF_HDR_LEN + RecordHeader::LEN + data.load_u64_count * 8).next_multiple_of(8)
+}
+
+/// ebpf侧直接用报文中的地址查表，键保持网络字节序
+fn route_key(ip: Ipv4Addr) -> u32 {
+    u32::from_ne_bytes(ip.octets())
+}
+
+struct Checker<'a> {
+    src: &'a str,
+    errors: Vec<ConfigError>,
+}
+
+impl Checker<'_> {
+    fn error(&mut self, key: &str, span: Range<usize>, message: impl Into<String>) {
+        self.errors.push(ConfigError {
+            key: key.to_string(),
+            line: Some(line_of(self.src, span.start)),
+            message: message.into(),
+        });
+    }
+
+    fn mac(&mut self, key: &str, mac: &Spanned<String>) -> [u8; 6] {
+        match parse_mac(mac.get_ref()) {
+            Ok(bytes) => bytes,
+            Err(message) => {
+                self.error(key, mac.span(), message);
+                [0; 6]
+            }
+        }
+    }
+
+    fn nodes(&mut self, raw: &[RawNode]) -> Vec<Node> {
+        let mut names = HashSet::new();
+        let mut nodes: Vec<Node> = Vec::new();
+        for (i, node) in raw.iter().enumerate() {
+            let key = |field: &str| format!("node[{i}].{field}");
+            let name = node.name.get_ref();
+            if name.is_empty() {
+                self.error(&key("name"), node.name.span(), "节点名不能为空");
+            } else if !names.insert(name.as_str()) {
+                self.error(&key("name"), node.name.span(), format!("节点名{name}重复"));
+            }
+            let mac = self.mac(&key("mac"), &node.mac);
+            if let Some(other) = nodes.iter().find(|other| mac != [0; 6] && other.mac == mac) {
+                self.error(
+                    &key("mac"),
+                    node.mac.span(),
+                    format!("{}与节点{}重复", format_mac(&mac), other.name),
+                );
+            }
+            let ip = *node.ip.get_ref();
+            if let Some(other) = nodes.iter().find(|other| other.ip == ip) {
+                self.error(
+                    &key("ip"),
+                    node.ip.span(),
+                    format!("{ip}与节点{}重复", other.name),
+                );
+            }
+            nodes.push(Node {
+                name: name.clone(),
+                role: node.role,
+                mac,
+                ip,
+                hardworker: node.hardworker.as_ref().map(|h| h.get_ref().clone()),
+                loggers: node
+                    .loggers
+                    .as_ref()
+                    .map(|l| l.get_ref().clone())
+                    .unwrap_or_default(),
+                port: node.port.as_ref().map(|port| self.port(&key("port"), port)),
+                hook: node
+                    .hook
+                    .as_ref()
+                    .map(|hook| *hook.get_ref())
+                    .unwrap_or_default(),
+            });
+        }
+
+        // 名字都收集完后再检查sensor的引用
+        for (i, node) in raw.iter().enumerate() {
+            let key = |field: &str| format!("node[{i}].{field}");
+            if let Some(port) = &node.port {
+                if node.role != Role::Logger {
+                    self.error(&key("port"), port.span(), "只有logger节点可以指定port");
+                }
+            }
+            if node.role != Role::Sensor {
+                if let Some(hardworker) = &node.hardworker {
+                    self.error(
+                        &key("hardworker"),
+                        hardworker.span(),
+                        "只有sensor节点需要指定hardworker",
+                    );
+                }
+                if let Some(loggers) = &node.loggers {
+                    self.error(
+                        &key("loggers"),
+                        loggers.span(),
+                        "只有sensor节点需要指定loggers",
+                    );
+                }
+                continue;
+            }
+            match &node.hardworker {
+                Some(name) => self.reference(
+                    &nodes,
+                    &key("hardworker"),
+                    name.span(),
+                    name.get_ref(),
+                    Role::Hardworker,
+                ),
+                None => self.error(
+                    &key("name"),
+                    node.name.span(),
+                    "sensor节点必须指定hardworker",
+                ),
+            }
+            match &node.loggers {
+                Some(loggers) if (1..=MAX_LOGGERS).contains(&loggers.get_ref().len()) => {
+                    for name in loggers.get_ref() {
+                        self.reference(&nodes, &key("loggers"), loggers.span(), name, Role::Logger);
+                    }
+                }
+                Some(loggers) => self.error(
+                    &key("loggers"),
+                    loggers.span(),
+                    format!("每个sensor需要1到{MAX_LOGGERS}个logger"),
+                ),
+                None => self.error(&key("name"), node.name.span(), "sensor节点必须指定loggers"),
+            }
+        }
+        nodes
+    }
+
+    /// AF_XDP只能由XDP程序重定向，TC挂载的hardworker只能经ring buffer上报
+    fn hooks(&mut self, raw: &[RawNode], hardworker: &Hardworker) {
+        for (i, node) in raw.iter().enumerate() {
+            if let Some(hook) = &node.hook {
+                if node.role == Role::Hardworker
+                    && *hook.get_ref() == Hook::Tc
+                    && hardworker.capture == Capture::Xsk
+                {
+                    self.error(
+                        &format!("node[{i}].hook"),
+                        hook.span(),
+                        "hardworker.capture为xsk时只能挂载XDP",
+                    );
+                }
+            }
+        }
+    }
+
+    fn reference(&mut self, nodes: &[Node], key: &str, span: Range<usize>, name: &str, role: Role) {
+        match nodes.iter().find(|node| node.name == name) {
+            Some(node) if node.role == role => {}
+            Some(node) => self.error(
+                key,
+                span,
+                format!("节点{name}的角色是{}而不是{role}", node.role),
+            ),
+            None => self.error(key, span, format!("没有名为{name}的节点")),
+        }
+    }
+
+    /// 原先ebpf程序中的编译时断言
+    /// 确保TOS字段的最后一位为0符合TOS字段要求
+    /// 确保前三位不为001和000避免与已定义TOS类型冲突
+    /// tos字段前三位弃用，所以将标识为0x011xxxxx应该不会和其他包冲突
+    fn tos(&mut self, tos: &Spanned<i64>) -> u8 {
+        let Ok(value) = u8::try_from(*tos.get_ref()) else {
+            self.error("mark.tos", tos.span(), "TOS必须在0到255之间");
+            return 0;
+        };
+        if value & 0b00000001 != 0b00000000 {
+            self.error(
+                "mark.tos",
+                tos.span(),
+                format!("{value:#04x}的最低位为1，该位是必须为0的保留位"),
+            );
+        }
+        match value & 0b11100000 {
+            0b00000000 => self.error(
+                "mark.tos",
+                tos.span(),
+                format!("{value:#04x}的优先级位为000，与Routine类冲突，普通流量都会命中"),
+            ),
+            0b00100000 => self.error(
+                "mark.tos",
+                tos.span(),
+                format!("{value:#04x}的优先级位为001，与Priority类冲突"),
+            ),
+            _ => {}
+        }
+        value
+    }
+
+    fn port(&mut self, key: &str, port: &Spanned<i64>) -> u16 {
+        match u16::try_from(*port.get_ref()) {
+            Ok(value) if value != 0 => value,
+            _ => {
+                self.error(key, port.span(), "端口必须在1到65535之间");
+                0
+            }
+        }
+    }
+
+    fn data(&mut self, data: &RawData) -> Data {
+        let mtu = match usize::try_from(*data.mtu.get_ref()) {
+            Ok(mtu) if mtu > HEADERS_LEN => mtu,
+            _ => {
+                self.error(
+                    "data.mtu",
+                    data.mtu.span(),
+                    format!("mtu必须大于IPv4与TCP头部的{HEADERS_LEN}字节"),
+                );
+                0
+            }
+        };
+        let load_u64_count = match usize::try_from(*data.load_u64_count.get_ref()) {
+            Ok(count) if count > 0 => count,
+            _ => {
+                self.error(
+                    "data.load_u64_count",
+                    data.load_u64_count.span(),
+                    "load_u64_count必须为正整数",
+                );
+                0
+            }
+        };
+        if mtu > 0 && load_u64_count > 0 {
+            let len = load_u64_count.saturating_mul(8).saturating_add(HEADERS_LEN);
+            if len > mtu {
+                self.error(
+                    "data.load_u64_count",
+                    data.load_u64_count.span(),
+                    format!(
+                        "负载{}字节加上头部{HEADERS_LEN}字节共{len}字节，超过mtu {mtu}",
+                        load_u64_count * 8
+                    ),
+                );
+            }
+        }
+        Data {
+            mtu,
+            load_u64_count,
+        }
+    }
+
+    fn ring(&mut self, ring: &RawRing, data: &Data) -> Ring {
+        let records = match u32::try_from(*ring.records.get_ref()) {
+            Ok(records) if (1..=MAX_RING_RECORDS).contains(&records) => records,
+            _ => {
+                self.error(
+                    "ring.records",
+                    ring.records.span(),
+                    format!("records必须在1到{MAX_RING_RECORDS}之间"),
+                );
+                return Ring::default();
+            }
+        };
+        let bytes = (records as usize).saturating_mul(record_size(data));
+        if bytes > MAX_RING_BYTES {
+            self.error(
+                "ring.records",
+                ring.records.span(),
+                format!("{records}条记录共{bytes}字节，超过ring buffer上限{MAX_RING_BYTES}字节"),
+            );
+        }
+        Ring { records }
+    }
+
+    fn udp(&mut self, udp: &RawUdp, mark_port: u16) -> Udp {
+        let port = match u16::try_from(*udp.port.get_ref()) {
+            Ok(port) if port != 0 => port,
+            _ => {
+                self.error("udp.port", udp.port.span(), "端口必须在1到65535之间");
+                0
+            }
+        };
+        if port != 0 && port == mark_port {
+            self.error(
+                "udp.port",
+                udp.port.span(),
+                format!("{port}与mark.port相同，两种传输需要区分端口"),
+            );
+        }
+        let timeout_ms = match &udp.timeout_ms {
+            Some(timeout) => match u32::try_from(*timeout.get_ref()) {
+                Ok(ms) if (1..=MAX_UDP_TIMEOUT_MS).contains(&ms) => ms,
+                _ => {
+                    self.error(
+                        "udp.timeout_ms",
+                        timeout.span(),
+                        format!("timeout_ms必须在1到{MAX_UDP_TIMEOUT_MS}之间"),
+                    );
+                    DEFAULT_UDP_TIMEOUT_MS
+                }
+            },
+            None => DEFAULT_UDP_TIMEOUT_MS,
+        };
+        let retries = match &udp.retries {
+            Some(retries) => match u32::try_from(*retries.get_ref()) {
+                Ok(n) if n <= MAX_UDP_RETRIES => n,
+                _ => {
+                    self.error(
+                        "udp.retries",
+                        retries.span(),
+                        format!("retries必须在0到{MAX_UDP_RETRIES}之间"),
+                    );
+                    DEFAULT_UDP_RETRIES
+                }
+            },
+            None => DEFAULT_UDP_RETRIES,
+        };
+        Udp {
+            port,
+            timeout_ms,
+            retries,
+        }
+    }
+
+    fn sensor(&mut self, sensor: Option<&RawSensor>, mark_port: u16, udp: Option<&Udp>) -> Sensor {
+        let mut ports = Vec::new();
+        match sensor.and_then(|sensor| sensor.ports.as_ref()) {
+            Some(raw) => {
+                if raw.get_ref().len() > MAX_MARK_PORTS {
+                    self.error(
+                        "sensor.ports",
+                        raw.span(),
+                        format!("最多标记{MAX_MARK_PORTS}个端口"),
+                    );
+                }
+                for &port in raw.get_ref() {
+                    match u16::try_from(port) {
+                        Ok(port) if port != 0 => {
+                            if !ports.contains(&port) {
+                                ports.push(port);
+                            }
+                        }
+                        _ => self.error("sensor.ports", raw.span(), "端口必须在1到65535之间"),
+                    }
+                }
+            }
+            None => ports.extend(core::iter::once(mark_port).chain(udp.map(|udp| udp.port))),
+        }
+        let cgroup = sensor
+            .and_then(|sensor| sensor.cgroup.as_ref())
+            .map(|cgroup| {
+                let path = PathBuf::from(cgroup.get_ref());
+                match path.strip_prefix(CGROUP_ROOT) {
+                    Ok(relative) if relative.components().count() > 0 => {}
+                    Ok(_) => self.error(
+                        "sensor.cgroup",
+                        cgroup.span(),
+                        "不能是cgroup根目录，否则所有进程都会被标记",
+                    ),
+                    Err(_) => self.error(
+                        "sensor.cgroup",
+                        cgroup.span(),
+                        format!("必须是{CGROUP_ROOT}下的cgroup v2目录"),
+                    ),
+                }
+                path
+            });
+        Sensor { ports, cgroup }
+    }
+
+    fn store(&mut self, store: &RawStore) -> Store {
+        if store.dir.get_ref().trim().is_empty() {
+            self.error("store.dir", store.dir.span(), "dir不能为空");
+        }
+        let segment_mb = self.bounded(
+            "store.segment_mb",
+            &store.segment_mb,
+            1,
+            MAX_SEGMENT_MB,
+            DEFAULT_SEGMENT_MB,
+        );
+        let max_age_hours = self.bounded(
+            "store.max_age_hours",
+            &store.max_age_hours,
+            0,
+            MAX_AGE_HOURS,
+            DEFAULT_MAX_AGE_HOURS,
+        );
+        let max_mb = self.bounded("store.max_mb", &store.max_mb, 0, u32::MAX, DEFAULT_MAX_MB);
+        // 活动段不会被清理，上限小于一个段时总大小无法维持
+        if let Some(value) = &store.max_mb {
+            if max_mb != 0 && max_mb < segment_mb {
+                self.error(
+                    "store.max_mb",
+                    value.span(),
+                    format!("max_mb为{max_mb}，不能小于segment_mb的{segment_mb}"),
+                );
+            }
+        }
+        Store {
+            dir: PathBuf::from(store.dir.get_ref()),
+            segment_mb,
+            max_age_hours,
+            max_mb,
+        }
+    }
+
+    /// 可选的整数项，缺省时取`default`，超出`min..=max`时报错
+    fn bounded(
+        &mut self,
+        key: &str,
+        value: &Option<Spanned<i64>>,
+        min: u32,
+        max: u32,
+        default: u32,
+    ) -> u32 {
+        let Some(value) = value else {
+            return default;
+        };
+        match u32::try_from(*value.get_ref()) {
+            Ok(n) if (min..=max).contains(&n) => n,
+            _ => {
+                let name = key.rsplit('.').next().unwrap_or(key);
+                self.error(key, value.span(), format!("{name}必须在{min}到{max}之间"));
+                default
+            }
+        }
+    }
+}
+
+/// 解析`aa:bb:cc:dd:ee:ff`格式的MAC地址
+pub fn parse_mac(mac: &str) -> Result<[u8; 6], String> {
+    let octets = mac
+        .split(':')
+        .map(|s| match s.len() {
+            2 => u8::from_str_radix(s, 16).ok(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>();
+    let bytes: [u8; 6] = octets
+        .and_then(|octets| octets.try_into().ok())
+        .ok_or_else(|| format!("\"{mac}\"应为6组以冒号分隔的两位十六进制数"))?;
+    if bytes == [0; 6] {
+        return Err(format!("\"{mac}\"是全零地址"));
+    }
+    if bytes[0] & 0b00000001 != 0 {
+        return Err(format!("\"{mac}\"是组播地址，不能作为对端地址"));
+    }
+    Ok(bytes)
+}
+
+/// 格式化为`aa:bb:cc:dd:ee:ff`
+pub fn format_mac(mac: &[u8; 6]) -> String {
+    let [a, b, c, d, e, f] = mac;
+    format!("{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{f:02x}")
+}
+
+fn serialize_mac<S: Serializer>(mac: &[u8; 6], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format_mac(mac))
+}
+
+fn line_of(src: &str, offset: usize) -> usize {
+    src[..offset.min(src.len())].matches('\n').count() + 1
+}
+
+/// toml自身的错误只带位置，这里根据位置所在行与之前最近的表头还原出键名
+fn toml_error(src: &str, error: &toml::de::Error) -> ConfigError {
+    let Some(span) = error.span() else {
+        return ConfigError {
+            key: String::new(),
+            line: None,
+            message: error.message().to_string(),
+        };
+    };
+    let line = line_of(src, span.start);
+    let lines: Vec<&str> = src.lines().collect();
+    let mut table = None;
+    for text in lines[..line.min(lines.len())].iter().rev() {
+        let text = text.trim();
+        if text.starts_with('[') {
+            table = Some(text.trim_matches(|c| c == '[' || c == ']').trim());
+            break;
+        }
+    }
+    let key = lines
+        .get(line - 1)
+        .and_then(|text| text.split_once('='))
+        .map(|(key, _)| key.trim());
+    let key = match (table, key) {
+        (Some(table), Some(key)) => format!("{table}.{key}"),
+        (Some(table), None) => table.to_string(),
+        (None, Some(key)) => key.to_string(),
+        (None, None) => String::new(),
+    };
+    ConfigError {
+        key,
+        line: Some(line),
+        message: error.message().to_string(),
+    }
+}
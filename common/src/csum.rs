@@ -0,0 +1,48 @@
+//! RFC 1624 增量校验和更新
+//!
+//! 反码和与字节序无关，只要校验和与新旧值取自同一种字节序，结果就正确，
+//! 所以调用方可以直接传入报文中的原始字段，无需`swap_bytes`
+
+/// 把32位累加和折叠为16位反码和
+#[inline(always)]
+pub fn fold(sum: u32) -> u16 {
+    let sum = (sum & 0xFFFF) + (sum >> 16);
+    let sum = (sum & 0xFFFF) + (sum >> 16);
+    sum as u16
+}
+
+/// 16位字段从`old`改为`new`后的新校验和，端口也用它更新
+///
+/// 按RFC 1624式3计算：HC' = ~(~HC + ~m + m')，不会产生-0
+#[inline(always)]
+pub fn replace16(check: u16, old: u16, new: u16) -> u16 {
+    !fold(!check as u32 + !old as u32 + new as u32)
+}
+
+/// 32位字段（如IPv4地址）从`old`改为`new`后的新校验和
+///
+/// 高低两个16位分别参与，地址不共享高16位时也正确
+#[inline(always)]
+pub fn replace32(check: u16, old: u32, new: u32) -> u16 {
+    let sum =
+        !check as u32 + (!(old >> 16) & 0xFFFF) + (!old & 0xFFFF) + (new >> 16) + (new & 0xFFFF);
+    !fold(sum)
+}
+
+/// 对一段数据按网络字节序的16位字求和，奇数长度时末尾补零
+pub fn sum(data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    let mut sum = 0u32;
+    for chunk in &mut chunks {
+        sum += u16::from_be_bytes([chunk[0], chunk[1]]) as u32;
+    }
+    if let [last] = chunks.remainder() {
+        sum += u16::from_be_bytes([*last, 0]) as u32;
+    }
+    sum
+}
+
+/// 完整计算一段数据的校验和，结果按主机字节序返回
+pub fn checksum(data: &[u8]) -> u16 {
+    !fold(sum(data))
+}
@@ -0,0 +1,357 @@
+//! XDP与TC（clsact）两种挂载点的统一接口
+//!
+//! 三个ebpf程序的逻辑对[`Datapath`]泛型，XDP与TC入口各实例化一份，改写与上报完全相同。
+//! 两者的差别都收在这里：XDP直接改写报文并手工增量更新校验和；TC交给协议栈的skb可能带着
+//! 网卡算好的整包校验和（CHECKSUM_COMPLETE）或留给网卡填写的校验和（CHECKSUM_PARTIAL），
+//! 地址、端口与TOS改经`bpf_skb_store_bytes`与`bpf_l3/l4_csum_replace`写入，由内核一并维护。
+//! 这些helper会让之前取得的报文指针失效，调用方在`set_*`之后不能再解引用`Ipv4Packet`中的指针
+
+#![allow(clippy::result_unit_err)]
+
+#[cfg(feature = "ebpf")]
+use aya_ebpf::{
+    bindings::{
+        __sk_buff, xdp_action, BPF_F_MARK_MANGLED_0, BPF_F_PSEUDO_HDR, TC_ACT_OK, TC_ACT_SHOT,
+    },
+    helpers::gen::{
+        bpf_l3_csum_replace, bpf_l4_csum_replace, bpf_redirect, bpf_skb_change_tail,
+        bpf_skb_load_bytes, bpf_skb_pull_data, bpf_skb_store_bytes, bpf_xdp_adjust_tail,
+        bpf_xdp_get_buff_len, bpf_xdp_load_bytes,
+    },
+    programs::{TcContext, XdpContext},
+    EbpfContext,
+};
+
+#[cfg(feature = "ebpf")]
+use crate::{
+    csum,
+    packet::{
+        Cursor, Ipv4Packet, IPPROTO_UDP, IP_CHECK_OFFSET, IP_DST_OFFSET, IP_SRC_OFFSET,
+        IP_TOS_OFFSET, MAX_VLAN_DEPTH, VLAN_LEN,
+    },
+};
+
+/// 与挂载点无关的处理结果，入口函数再换成各自的返回码
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// 交给协议栈
+    Pass,
+    Drop,
+    /// 从收包的网卡发回，XDP为`XDP_TX`，TC为重定向到同一网卡的出口
+    Tx,
+    /// 已重定向到AF_XDP socket，只有XDP会出现
+    Redirect,
+    /// 处理出错
+    Aborted,
+}
+
+/// TC下直接访问前拉进线性区的字节数：以太网头、两层VLAN、带选项的IPv4头与TCP头
+#[cfg(feature = "ebpf")]
+const PULL_LEN: u32 = (14 + MAX_VLAN_DEPTH * VLAN_LEN + 60 + 60) as u32;
+
+/// 三个程序用到的、随挂载点而不同的操作
+#[cfg(feature = "ebpf")]
+pub trait Datapath: EbpfContext {
+    /// 入口函数的返回值类型
+    type Ret;
+
+    /// 只有XDP能重定向到AF_XDP socket
+    const XDP: bool;
+
+    /// 报文的起止地址，TC下先把头部拉进线性区
+    fn cursor(&self) -> Cursor;
+
+    /// 收包网卡的ifindex
+    fn ifindex(&self) -> u32;
+
+    /// 收包队列
+    fn rx_queue(&self) -> u32;
+
+    /// 从`offset`处拷贝`len`字节到`dst`，负载不在线性区时也能读到
+    ///
+    /// # Safety
+    ///
+    /// `dst`至少要有`len`字节
+    unsafe fn load_bytes(&self, offset: usize, dst: *mut u8, len: usize) -> Result<(), i64>;
+
+    /// 原地构造回复后把帧截到`len`字节，报文指针随之失效
+    fn truncate(&self, len: usize) -> Result<(), ()>;
+
+    /// 改写源地址（网络字节序），更新IP与TCP/UDP校验和
+    fn set_src(&self, cursor: &Cursor, packet: &Ipv4Packet, addr: u32) -> Result<(), ()>;
+
+    /// 改写目的地址（网络字节序），更新IP与TCP/UDP校验和
+    fn set_dst(&self, cursor: &Cursor, packet: &Ipv4Packet, addr: u32) -> Result<(), ()>;
+
+    /// 改写TCP/UDP源端口（网络字节序），更新校验和
+    fn set_src_port(&self, cursor: &Cursor, packet: &Ipv4Packet, port: u16) -> Result<(), ()>;
+
+    /// 改写TCP/UDP目的端口（网络字节序），更新校验和
+    fn set_dst_port(&self, cursor: &Cursor, packet: &Ipv4Packet, port: u16) -> Result<(), ()>;
+
+    /// 改写TOS，更新IP校验和，TOS不在伪首部中，TCP/UDP校验和不变
+    fn set_tos(&self, cursor: &Cursor, packet: &Ipv4Packet, tos: u8) -> Result<(), ()>;
+
+    /// 把处理结果换成入口函数的返回值
+    fn finish(&self, outcome: Outcome) -> Self::Ret;
+}
+
+#[cfg(feature = "ebpf")]
+impl Datapath for XdpContext {
+    type Ret = u32;
+
+    const XDP: bool = true;
+
+    #[inline(always)]
+    fn cursor(&self) -> Cursor {
+        Cursor::new(self.data(), self.data_end())
+    }
+
+    #[inline(always)]
+    fn ifindex(&self) -> u32 {
+        unsafe { (*self.ctx).ingress_ifindex }
+    }
+
+    #[inline(always)]
+    fn rx_queue(&self) -> u32 {
+        unsafe { (*self.ctx).rx_queue_index }
+    }
+
+    #[inline(always)]
+    unsafe fn load_bytes(&self, offset: usize, dst: *mut u8, len: usize) -> Result<(), i64> {
+        // 多缓冲区XDP的负载可能不在线性区，由helper拷贝
+        match bpf_xdp_load_bytes(self.ctx, offset as u32, dst as *mut _, len as u32) {
+            0 => Ok(()),
+            ret => Err(ret),
+        }
+    }
+
+    #[inline(always)]
+    fn truncate(&self, len: usize) -> Result<(), ()> {
+        let delta = len as i32 - unsafe { bpf_xdp_get_buff_len(self.ctx) } as i32;
+        match unsafe { bpf_xdp_adjust_tail(self.ctx, delta) } {
+            0 => Ok(()),
+            _ => Err(()),
+        }
+    }
+
+    #[inline(always)]
+    fn set_src(&self, cursor: &Cursor, packet: &Ipv4Packet, addr: u32) -> Result<(), ()> {
+        packet.set_src(cursor, addr)
+    }
+
+    #[inline(always)]
+    fn set_dst(&self, cursor: &Cursor, packet: &Ipv4Packet, addr: u32) -> Result<(), ()> {
+        packet.set_dst(cursor, addr)
+    }
+
+    #[inline(always)]
+    fn set_src_port(&self, cursor: &Cursor, packet: &Ipv4Packet, port: u16) -> Result<(), ()> {
+        packet.set_src_port(cursor, port)
+    }
+
+    #[inline(always)]
+    fn set_dst_port(&self, cursor: &Cursor, packet: &Ipv4Packet, port: u16) -> Result<(), ()> {
+        packet.set_dst_port(cursor, port)
+    }
+
+    #[inline(always)]
+    fn set_tos(&self, cursor: &Cursor, packet: &Ipv4Packet, tos: u8) -> Result<(), ()> {
+        // 版本/IHL与TOS组成校验和中的一个16位字
+        let word: *mut [u8; 2] = cursor.ptr_at(packet.l3_offset)?;
+        let ip = packet.ip;
+        unsafe {
+            let old = u16::from_ne_bytes(*word);
+            (*ip).tos = tos;
+            let new = u16::from_ne_bytes(*word);
+            (*ip).check = csum::replace16((*ip).check, old, new);
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn finish(&self, outcome: Outcome) -> u32 {
+        match outcome {
+            Outcome::Pass => xdp_action::XDP_PASS,
+            Outcome::Drop => xdp_action::XDP_DROP,
+            Outcome::Tx => xdp_action::XDP_TX,
+            Outcome::Redirect => xdp_action::XDP_REDIRECT,
+            Outcome::Aborted => xdp_action::XDP_ABORTED,
+        }
+    }
+}
+
+#[cfg(feature = "ebpf")]
+impl Datapath for TcContext {
+    type Ret = i32;
+
+    const XDP: bool = false;
+
+    #[inline(always)]
+    fn cursor(&self) -> Cursor {
+        let skb = self.as_ptr() as *mut __sk_buff;
+        // 失败时按现有的线性区解析，头部不完整会在边界检查中报错
+        unsafe {
+            bpf_skb_pull_data(skb, (*skb).len.min(PULL_LEN));
+        }
+        Cursor::new(self.data(), self.data_end())
+    }
+
+    #[inline(always)]
+    fn ifindex(&self) -> u32 {
+        unsafe { (*(self.as_ptr() as *const __sk_buff)).ifindex }
+    }
+
+    #[inline(always)]
+    fn rx_queue(&self) -> u32 {
+        // 收包时内核记录的是队列号加1，0表示驱动没有记录
+        unsafe { (*(self.as_ptr() as *const __sk_buff)).queue_mapping }.saturating_sub(1)
+    }
+
+    #[inline(always)]
+    unsafe fn load_bytes(&self, offset: usize, dst: *mut u8, len: usize) -> Result<(), i64> {
+        match bpf_skb_load_bytes(self.as_ptr() as _, offset as u32, dst as *mut _, len as u32) {
+            0 => Ok(()),
+            ret => Err(ret),
+        }
+    }
+
+    #[inline(always)]
+    fn truncate(&self, len: usize) -> Result<(), ()> {
+        match unsafe { bpf_skb_change_tail(self.as_ptr() as _, len as u32, 0) } {
+            0 => Ok(()),
+            _ => Err(()),
+        }
+    }
+
+    #[inline(always)]
+    fn set_src(&self, _: &Cursor, packet: &Ipv4Packet, addr: u32) -> Result<(), ()> {
+        self.set_addr(packet, IP_SRC_OFFSET, addr)
+    }
+
+    #[inline(always)]
+    fn set_dst(&self, _: &Cursor, packet: &Ipv4Packet, addr: u32) -> Result<(), ()> {
+        self.set_addr(packet, IP_DST_OFFSET, addr)
+    }
+
+    #[inline(always)]
+    fn set_src_port(&self, _: &Cursor, packet: &Ipv4Packet, port: u16) -> Result<(), ()> {
+        self.set_port(packet, 0, port)
+    }
+
+    #[inline(always)]
+    fn set_dst_port(&self, _: &Cursor, packet: &Ipv4Packet, port: u16) -> Result<(), ()> {
+        self.set_port(packet, 2, port)
+    }
+
+    #[inline(always)]
+    fn set_tos(&self, _: &Cursor, packet: &Ipv4Packet, tos: u8) -> Result<(), ()> {
+        let old: [u8; 2] = self.load(packet.l3_offset)?;
+        let new = [old[0], tos];
+        self.store(packet.l3_offset + IP_TOS_OFFSET, &tos)?;
+        self.l3_replace(
+            packet,
+            u16::from_ne_bytes(old) as u64,
+            u16::from_ne_bytes(new) as u64,
+            2,
+        )
+    }
+
+    #[inline(always)]
+    fn finish(&self, outcome: Outcome) -> i32 {
+        match outcome {
+            Outcome::Pass | Outcome::Redirect => TC_ACT_OK,
+            Outcome::Drop | Outcome::Aborted => TC_ACT_SHOT,
+            // 回复与转发都从收包的网卡发出，与XDP_TX相同
+            Outcome::Tx => unsafe { bpf_redirect(self.ifindex(), 0) as i32 },
+        }
+    }
+}
+
+/// TC下经helper读写报文，偏移越界时由helper返回错误
+#[cfg(feature = "ebpf")]
+trait Skb: EbpfContext {
+    #[inline(always)]
+    fn load<T: Copy>(&self, offset: usize) -> Result<T, ()> {
+        let mut value = core::mem::MaybeUninit::<T>::uninit();
+        let ret = unsafe {
+            bpf_skb_load_bytes(
+                self.as_ptr() as _,
+                offset as u32,
+                value.as_mut_ptr() as *mut _,
+                core::mem::size_of::<T>() as u32,
+            )
+        };
+        match ret {
+            0 => Ok(unsafe { value.assume_init() }),
+            _ => Err(()),
+        }
+    }
+
+    #[inline(always)]
+    fn store<T>(&self, offset: usize, value: &T) -> Result<(), ()> {
+        let ret = unsafe {
+            bpf_skb_store_bytes(
+                self.as_ptr() as _,
+                offset as u32,
+                value as *const T as *const _,
+                core::mem::size_of::<T>() as u32,
+                0,
+            )
+        };
+        match ret {
+            0 => Ok(()),
+            _ => Err(()),
+        }
+    }
+
+    #[inline(always)]
+    fn l3_replace(&self, packet: &Ipv4Packet, from: u64, to: u64, size: u64) -> Result<(), ()> {
+        let offset = (packet.l3_offset + IP_CHECK_OFFSET) as u32;
+        match unsafe { bpf_l3_csum_replace(self.as_ptr() as _, offset, from, to, size) } {
+            0 => Ok(()),
+            _ => Err(()),
+        }
+    }
+
+    /// UDP校验和为0表示未校验，由`BPF_F_MARK_MANGLED_0`保持为0，结果为0时写成全1
+    #[inline(always)]
+    fn l4_replace(&self, packet: &Ipv4Packet, from: u64, to: u64, flags: u64) -> Result<(), ()> {
+        let Some(offset) = packet.l4_check_offset() else {
+            return Ok(());
+        };
+        let flags = match packet.proto {
+            IPPROTO_UDP => flags | BPF_F_MARK_MANGLED_0 as u64,
+            _ => flags,
+        };
+        match unsafe { bpf_l4_csum_replace(self.as_ptr() as _, offset as u32, from, to, flags) } {
+            0 => Ok(()),
+            _ => Err(()),
+        }
+    }
+
+    /// IP头与TCP/UDP伪首部都覆盖地址
+    #[inline(always)]
+    fn set_addr(&self, packet: &Ipv4Packet, field: usize, addr: u32) -> Result<(), ()> {
+        let offset = packet.l3_offset + field;
+        let old: u32 = self.load(offset)?;
+        self.store(offset, &addr)?;
+        self.l3_replace(packet, old as u64, addr as u64, 4)?;
+        self.l4_replace(packet, old as u64, addr as u64, BPF_F_PSEUDO_HDR as u64 | 4)
+    }
+
+    /// 端口只在首个分片中，其他协议与分片返回错误
+    #[inline(always)]
+    fn set_port(&self, packet: &Ipv4Packet, field: usize, port: u16) -> Result<(), ()> {
+        if packet.l4_check_offset().is_none() {
+            return Err(());
+        }
+        let offset = packet.l4_offset + field;
+        let old: u16 = self.load(offset)?;
+        self.store(offset, &port)?;
+        self.l4_replace(packet, old as u64, port as u64, 2)
+    }
+}
+
+#[cfg(feature = "ebpf")]
+impl Skb for TcContext {}
@@ -0,0 +1,245 @@
+//! hardworker按TCP流跟踪序列号
+//!
+//! 每条流记录期望的下一个序列号、连续收到的位置与最近的确认号，据此区分按序、缺口、乱序与重传。
+//! 最多记录`MAX_HOLES`个缺口：落在缺口里的段算乱序补齐，并从缺口中扣除，之后再到的同一段算重传。
+//! 缺口表只会多估缺失的数据，不会把没收到的数据当作已收到。
+//! 序列号比较都按32位回绕处理
+
+use core::net::SocketAddrV4;
+
+use crate::packet::{TCP_ACK, TCP_FIN, TCP_SYN};
+
+/// `FLOWS`表的容量，LRU表满后淘汰最久未见的流
+pub const MAX_FLOWS: u32 = 1024;
+
+/// 每条流最多同时跟踪的缺口数
+pub const MAX_HOLES: usize = 4;
+
+/// 流的四元组，地址与端口都保持报文中的网络字节序
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "config", derive(Debug))]
+pub struct FlowKey {
+    pub src_ip: u32,
+    pub dst_ip: u32,
+    pub src_port: u16,
+    pub dst_port: u16,
+}
+
+impl FlowKey {
+    pub fn source(&self) -> SocketAddrV4 {
+        SocketAddrV4::new(u32::from_be(self.src_ip).into(), u16::from_be(self.src_port))
+    }
+
+    pub fn destination(&self) -> SocketAddrV4 {
+        SocketAddrV4::new(u32::from_be(self.dst_ip).into(), u16::from_be(self.dst_port))
+    }
+}
+
+/// 一个段相对于流状态的判定
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Debug))]
+pub enum Verdict {
+    /// 流的第一个段，或SYN重新开始的流
+    New,
+    InOrder,
+    /// 序列号跳过了一段数据
+    Gap,
+    /// 补上了之前的缺口
+    Reordered,
+    /// 数据已经见过
+    Retransmit,
+    /// 不占序列号的纯ACK
+    Empty,
+}
+
+/// 未补齐的序列号区间`[start, end)`，两者相等表示空位
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(feature = "config", derive(Debug))]
+pub struct Hole {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Hole {
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> u32 {
+        self.end.wrapping_sub(self.start)
+    }
+}
+
+/// `FLOWS`表的值，序列号按主机字节序存储
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "config", derive(Debug))]
+pub struct Flow {
+    /// 期望的下一个序列号，即见过的最大段尾
+    pub next_seq: u32,
+    /// 连续收到的数据之后的第一个序列号，有缺口时停在最早的缺口起点，用作累计确认号
+    pub rcv_nxt: u32,
+    /// 见过的最大确认号
+    pub last_ack: u32,
+    /// 未补齐的缺口，顺序不固定
+    pub holes: [Hole; MAX_HOLES],
+    pub segments: u64,
+    pub gaps: u64,
+    pub reordered: u64,
+    pub retransmits: u64,
+}
+
+impl Flow {
+    /// 以第一个段建立流
+    #[inline(always)]
+    pub fn new(seq: u32, payload_len: u32, ack: u32, flags: u8) -> Self {
+        let next_seq = seq.wrapping_add(seq_len(payload_len, flags));
+        Self {
+            next_seq,
+            rcv_nxt: next_seq,
+            last_ack: if flags & TCP_ACK != 0 { ack } else { 0 },
+            holes: [Hole::default(); MAX_HOLES],
+            segments: 1,
+            gaps: 0,
+            reordered: 0,
+            retransmits: 0,
+        }
+    }
+
+    /// 根据新到的段更新流状态并给出判定
+    #[inline(always)]
+    pub fn observe(&mut self, seq: u32, payload_len: u32, ack: u32, flags: u8) -> Verdict {
+        if flags & TCP_SYN != 0 {
+            *self = Self::new(seq, payload_len, ack, flags);
+            return Verdict::New;
+        }
+        if flags & TCP_ACK != 0 && after(ack, self.last_ack) {
+            self.last_ack = ack;
+        }
+        let len = seq_len(payload_len, flags);
+        if len == 0 {
+            return Verdict::Empty;
+        }
+        self.segments += 1;
+        let end = seq.wrapping_add(len);
+
+        if seq == self.next_seq {
+            self.next_seq = end;
+            self.rcv_nxt = self.cumulative();
+            return Verdict::InOrder;
+        }
+        if after(seq, self.next_seq) {
+            self.open(self.next_seq, seq);
+            self.next_seq = end;
+            self.gaps += 1;
+            return Verdict::Gap;
+        }
+        let filled = self.fill(seq, end);
+        // 段的末尾超出了期望值，说明带来了部分新数据
+        let extended = after(end, self.next_seq);
+        if extended {
+            self.next_seq = end;
+        }
+        self.rcv_nxt = self.cumulative();
+        if filled {
+            self.reordered += 1;
+            Verdict::Reordered
+        } else if extended {
+            Verdict::InOrder
+        } else {
+            self.retransmits += 1;
+            Verdict::Retransmit
+        }
+    }
+
+    /// 所有缺口的字节数
+    pub fn hole(&self) -> u32 {
+        let mut total = 0u32;
+        for hole in &self.holes {
+            total = total.wrapping_add(hole.len());
+        }
+        total
+    }
+
+    /// 最早的缺口起点，没有缺口时即`next_seq`
+    #[inline(always)]
+    fn cumulative(&self) -> u32 {
+        let mut rcv_nxt = self.next_seq;
+        for hole in &self.holes {
+            if !hole.is_empty() && before(hole.start, rcv_nxt) {
+                rcv_nxt = hole.start;
+            }
+        }
+        rcv_nxt
+    }
+
+    /// 记录新缺口，表满时把最靠后的缺口延伸到新缺口的末尾。
+    /// 两个缺口之间已收到的数据之后重到会再采集一次，但不会漏采
+    #[inline(always)]
+    fn open(&mut self, start: u32, end: u32) {
+        if let Some(i) = self.free() {
+            self.holes[i] = Hole { start, end };
+            return;
+        }
+        let mut last = 0;
+        for i in 1..MAX_HOLES {
+            if after(self.holes[i].end, self.holes[last].end) {
+                last = i;
+            }
+        }
+        self.holes[last].end = end;
+    }
+
+    /// 从缺口中扣除`[seq, end)`，返回是否补上了缺口中的数据
+    #[inline(always)]
+    fn fill(&mut self, seq: u32, end: u32) -> bool {
+        let mut filled = false;
+        for i in 0..MAX_HOLES {
+            let hole = self.holes[i];
+            if hole.is_empty() || !before(seq, hole.end) || !after(end, hole.start) {
+                continue;
+            }
+            filled = true;
+            if !after(seq, hole.start) {
+                // 从前部补起，可能一并补齐
+                self.holes[i].start = if before(end, hole.end) { end } else { hole.end };
+            } else if !before(end, hole.end) {
+                self.holes[i].end = seq;
+            } else if let Some(j) = self.free() {
+                // 落在中间，拆成前后两个缺口；没有空位时保留原缺口，宁可重复采集
+                self.holes[i].end = seq;
+                self.holes[j] = Hole {
+                    start: end,
+                    end: hole.end,
+                };
+            }
+        }
+        filled
+    }
+
+    #[inline(always)]
+    fn free(&self) -> Option<usize> {
+        (0..MAX_HOLES).find(|&i| self.holes[i].is_empty())
+    }
+}
+
+/// 段占用的序列号数，SYN与FIN各占一个
+#[inline(always)]
+fn seq_len(payload_len: u32, flags: u8) -> u32 {
+    payload_len + (flags & TCP_SYN != 0) as u32 + (flags & TCP_FIN != 0) as u32
+}
+
+/// `a`在`b`之后（回绕意义下）
+#[inline(always)]
+fn after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+#[inline(always)]
+fn before(a: u32, b: u32) -> bool {
+    after(b, a)
+}
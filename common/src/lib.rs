@@ -0,0 +1,275 @@
+#![no_std]
+
+#[cfg(feature = "config")]
+extern crate std;
+
+pub mod classify;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod csum;
+pub mod datapath;
+pub mod flow;
+pub mod packet;
+pub mod record;
+pub mod rudp;
+pub mod stats;
+
+/// 编译时固化的数据常量，决定ring buffer条目的大小
+///
+/// 由构建脚本从`const.toml`的`[data]`生成`DATA`常量
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Debug, serde::Serialize))]
+pub struct Data {
+    pub mtu: usize,
+    pub load_u64_count: usize,
+}
+
+/// 每个sensor最多对应的logger数量
+pub const MAX_LOGGERS: usize = 4;
+/// `ROUTES`表的容量
+pub const MAX_ROUTES: u32 = 64;
+/// sensor出口最多标记的目的端口数
+pub const MAX_MARK_PORTS: usize = 8;
+/// `XSKS`表的容量，即AF_XDP模式下最多使用的收包队列数
+pub const MAX_XSK_QUEUES: u32 = 64;
+
+/// hardworker的运行时配置，由用户态在加载ebpf程序时写入全局变量`CONFIG`
+///
+/// 匹配哪些报文由[`classify`]的规则表决定，不在这里
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "config", derive(Debug))]
+pub struct Config {
+    /// `Config::ACK`等开关
+    pub flags: u8,
+    _pad: [u8; 3],
+}
+
+impl Config {
+    /// hardworker在XDP中直接回复ACK，转发给logger变为尽力而为
+    pub const ACK: u8 = 0b00000001;
+    /// hardworker把待上报的数据段整帧重定向到AF_XDP socket，不经过`TARGET_MAP`
+    pub const XSK: u8 = 0b00000010;
+
+    /// 全零配置，仅作为ebpf全局变量的占位初始值
+    pub const fn zeroed() -> Self {
+        Self::new(0)
+    }
+
+    pub const fn new(flags: u8) -> Self {
+        Self {
+            flags,
+            _pad: [0; 3],
+        }
+    }
+
+    pub const fn ack(&self) -> bool {
+        self.flags & Self::ACK != 0
+    }
+
+    pub const fn xsk(&self) -> bool {
+        self.flags & Self::XSK != 0
+    }
+}
+
+/// sensor出口程序的标记规则，由用户态在加载时写入全局变量`MARKING`
+///
+/// 发往`ip`、目的端口在`ports`中或来自`cgroup_id`所指cgroup的报文被打上`tos`，
+/// 应用不必再设置`IP_TOS`
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "config", derive(Debug))]
+pub struct Marking {
+    /// cgroup v2的id，即目录的inode号，0表示不按cgroup标记
+    pub cgroup_id: u64,
+    /// hardworker的IP，网络字节序，与报文中的目的地址直接比较
+    pub ip: u32,
+    /// cgroup在层级中的深度，根为0
+    pub cgroup_level: u32,
+    /// TCP与UDP的目的端口，主机字节序
+    pub ports: [u16; MAX_MARK_PORTS],
+    pub port_count: u8,
+    pub tos: u8,
+    _pad: [u8; 6],
+}
+
+impl Marking {
+    /// 全零即不标记，仅作为ebpf全局变量的占位初始值
+    pub const fn zeroed() -> Self {
+        Self {
+            cgroup_id: 0,
+            ip: 0,
+            cgroup_level: 0,
+            ports: [0; MAX_MARK_PORTS],
+            port_count: 0,
+            tos: 0,
+            _pad: [0; 6],
+        }
+    }
+
+    /// 超出`MAX_MARK_PORTS`的端口被忽略，配置校验保证不会发生
+    pub fn new(ip: u32, tos: u8, ports: &[u16], cgroup: Option<(u64, u32)>) -> Self {
+        let mut marking = Self::zeroed();
+        marking.ip = ip;
+        marking.tos = tos;
+        for (slot, port) in marking.ports.iter_mut().zip(ports) {
+            *slot = *port;
+            marking.port_count += 1;
+        }
+        if let Some((id, level)) = cgroup {
+            marking.cgroup_id = id;
+            marking.cgroup_level = level;
+        }
+        marking
+    }
+
+    /// 既没有端口也没有cgroup，不需要挂载出口程序
+    pub fn is_empty(&self) -> bool {
+        self.port_count == 0 && self.cgroup_id == 0
+    }
+
+    /// 目的端口（主机字节序）是否需要标记
+    #[inline(always)]
+    pub fn port(&self, port: u16) -> bool {
+        let mut i = 0;
+        while i < MAX_MARK_PORTS {
+            if i < self.port_count as usize && self.ports[i] == port {
+                return true;
+            }
+            i += 1;
+        }
+        false
+    }
+}
+
+/// 拓扑中的一个对端，IP与端口按主机字节序存储
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "config", derive(Debug))]
+pub struct Peer {
+    pub ip: u32,
+    pub mac: [u8; 6],
+    /// 对端接收标记流量的端口：hardworker为`mark.port`，logger为它实际监听的端口，sensor为0
+    pub port: u16,
+}
+
+impl Peer {
+    pub const fn zeroed() -> Self {
+        Self::new(0, [0; 6], 0)
+    }
+
+    pub const fn new(ip: u32, mac: [u8; 6], port: u16) -> Self {
+        Self { ip, mac, port }
+    }
+}
+
+/// `ROUTES`表的值：一个sensor与它的hardworker、logger组
+///
+/// 三个程序都以报文源IP查同一种表项，各取所需的对端
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "config", derive(Debug))]
+pub struct Route {
+    pub sensor: Peer,
+    pub hardworker: Peer,
+    pub loggers: [Peer; MAX_LOGGERS],
+    pub logger_count: u32,
+}
+
+impl Route {
+    pub const fn new(
+        sensor: Peer,
+        hardworker: Peer,
+        loggers: [Peer; MAX_LOGGERS],
+        logger_count: u32,
+    ) -> Self {
+        Self {
+            sensor,
+            hardworker,
+            loggers,
+            logger_count,
+        }
+    }
+
+    /// 按源端口在logger组中选一个，同一条TCP流总是落到同一个logger
+    ///
+    /// hardworker据此转发，logger据此确认报文本该发给自己的哪个地址与端口
+    #[inline(always)]
+    pub fn logger(&self, source_port: u16) -> Option<&Peer> {
+        if self.logger_count == 0 {
+            return None;
+        }
+        self.loggers
+            .get((source_port as u32 % self.logger_count) as usize)
+    }
+
+    /// 按IP（主机字节序）在logger组中查找，sensor据此还原logger的回包
+    #[inline(always)]
+    pub fn logger_at(&self, ip: u32) -> Option<&Peer> {
+        let mut i = 0;
+        while i < MAX_LOGGERS {
+            if (i as u32) < self.logger_count && self.loggers[i].ip == ip {
+                return Some(&self.loggers[i]);
+            }
+            i += 1;
+        }
+        None
+    }
+}
+
+/// hardworker每个CPU上的`TARGET_MAP`统计，存放在per-CPU数组`RING_STATS`中
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(feature = "config", derive(Debug))]
+pub struct RingStats {
+    /// 成功写入的记录数
+    pub submitted: u64,
+    /// ring buffer已满导致丢弃的记录数
+    pub dropped: u64,
+    /// 最近一次写入后`bpf_ringbuf_query`得到的待消费字节数
+    pub avail_last: u64,
+    /// 待消费字节数的峰值
+    pub avail_max: u64,
+}
+
+impl RingStats {
+    pub const fn zeroed() -> Self {
+        Self {
+            submitted: 0,
+            dropped: 0,
+            avail_last: 0,
+            avail_max: 0,
+        }
+    }
+
+    /// 汇总各CPU的统计，计数求和，占用取最大
+    pub fn merge(&self, other: &Self) -> Self {
+        Self {
+            submitted: self.submitted + other.submitted,
+            dropped: self.dropped + other.dropped,
+            avail_last: self.avail_last.max(other.avail_last),
+            avail_max: self.avail_max.max(other.avail_max),
+        }
+    }
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for Config {}
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for Marking {}
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for Route {}
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for RingStats {}
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for stats::Stats {}
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for flow::FlowKey {}
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for flow::Flow {}
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for rudp::RudpKey {}
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for rudp::Window {}
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for classify::RuleKey {}
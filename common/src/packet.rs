@@ -0,0 +1,406 @@
+//! ebpf程序共用的报文解析
+//!
+//! 只依赖报文的起止地址，XDP与TC上下文都能使用。每次解引用前都对`end`做边界检查，
+//! 可变长度（VLAN层数、IHL、doff）都有明确上界，verifier能够推导出访问范围。
+//! 返回`Err(())`表示报文被截断或头部非法，`Ok(None)`表示不是要找的协议
+
+// 与各ebpf程序`try_*`的`Result<u32, ()>`保持一致，直接用`?`传播
+#![allow(clippy::result_unit_err)]
+
+use network_types::{eth::EthHdr, ip::Ipv4Hdr, tcp::TcpHdr, udp::UdpHdr};
+
+use crate::csum;
+
+pub const ETH_P_IP: u16 = 0x0800;
+pub const ETH_P_8021Q: u16 = 0x8100;
+pub const ETH_P_8021AD: u16 = 0x88A8;
+pub const IPPROTO_TCP: u8 = 6;
+pub const IPPROTO_UDP: u8 = 17;
+
+/// TCP头第13字节的标志位
+pub const TCP_FIN: u8 = 0x01;
+pub const TCP_SYN: u8 = 0x02;
+pub const TCP_RST: u8 = 0x04;
+pub const TCP_PSH: u8 = 0x08;
+pub const TCP_ACK: u8 = 0x10;
+/// 原地构造回复时使用的TTL
+const REPLY_TTL: u8 = 64;
+/// IPv4不分片标志
+const IP_DF: u16 = 0x4000;
+/// 各字段在IPv4头中的偏移
+pub const IP_TOS_OFFSET: usize = 1;
+pub const IP_CHECK_OFFSET: usize = 10;
+pub const IP_SRC_OFFSET: usize = 12;
+pub const IP_DST_OFFSET: usize = 16;
+/// 校验和字段在TCP、UDP头中的偏移
+const TCP_CHECK_OFFSET: usize = 16;
+const UDP_CHECK_OFFSET: usize = 6;
+
+/// VLAN标签长度
+pub const VLAN_LEN: usize = 4;
+/// 最多解析的VLAN标签层数，两层即QinQ
+pub const MAX_VLAN_DEPTH: usize = 2;
+
+/// 报文的起止地址
+#[derive(Clone, Copy)]
+pub struct Cursor {
+    start: usize,
+    end: usize,
+}
+
+impl Cursor {
+    #[inline(always)]
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 取`offset`处的`T`，整个`T`都必须落在报文内
+    #[inline(always)]
+    pub fn ptr_at<T>(&self, offset: usize) -> Result<*mut T, ()> {
+        let len = ::core::mem::size_of::<T>();
+        if self.start + offset + len > self.end {
+            return Err(());
+        }
+        Ok((self.start + offset) as *mut T)
+    }
+
+    /// 读取`offset`处的大端`u16`，报文内容未必对齐，逐字节读
+    #[inline(always)]
+    fn be16(&self, offset: usize) -> Result<u16, ()> {
+        let bytes: *const [u8; 2] = self.ptr_at(offset)?;
+        Ok(u16::from_be_bytes(unsafe { *bytes }))
+    }
+
+    #[inline(always)]
+    fn u8(&self, offset: usize) -> Result<u8, ()> {
+        let byte: *const u8 = self.ptr_at(offset)?;
+        Ok(unsafe { *byte })
+    }
+
+    /// 解析以太网头、最多两层VLAN标签与IPv4头
+    ///
+    /// EtherType不是IPv4时返回`Ok(None)`
+    #[inline(always)]
+    pub fn ipv4(&self) -> Result<Option<Ipv4Packet>, ()> {
+        let eth: *mut EthHdr = self.ptr_at(0)?;
+        let mut ether_type = self.be16(EthHdr::LEN - 2)?;
+        let mut offset = EthHdr::LEN;
+        for _ in 0..MAX_VLAN_DEPTH {
+            if ether_type != ETH_P_8021Q && ether_type != ETH_P_8021AD {
+                break;
+            }
+            // 标签的后两个字节是内层EtherType
+            ether_type = self.be16(offset + 2)?;
+            offset += VLAN_LEN;
+        }
+        if ether_type != ETH_P_IP {
+            return Ok(None);
+        }
+
+        let ip: *mut Ipv4Hdr = self.ptr_at(offset)?;
+        let version_ihl = self.u8(offset)?;
+        if version_ihl >> 4 != 4 {
+            return Err(());
+        }
+        let ihl = ((version_ihl & 0x0F) as usize) * 4;
+        if ihl < Ipv4Hdr::LEN {
+            return Err(());
+        }
+        // 总长度可以超出线性区（TC下的非线性skb），只要求不小于头部
+        let tot_len = self.be16(offset + 2)? as usize;
+        if tot_len < ihl {
+            return Err(());
+        }
+        let frag_off = self.be16(offset + 6)?;
+        let proto = self.u8(offset + 9)?;
+
+        Ok(Some(Ipv4Packet {
+            eth,
+            ip,
+            l3_offset: offset,
+            l4_offset: offset + ihl,
+            l4_len: tot_len - ihl,
+            proto,
+            // 只有首个分片（偏移为0）带传输层头
+            first_fragment: frag_off & 0x1FFF == 0,
+        }))
+    }
+}
+
+/// 解析出的IPv4报文
+#[derive(Clone, Copy)]
+pub struct Ipv4Packet {
+    pub eth: *mut EthHdr,
+    pub ip: *mut Ipv4Hdr,
+    /// IPv4头的偏移，已跳过VLAN标签
+    pub l3_offset: usize,
+    /// 传输层头的偏移，已按IHL跳过IP选项
+    pub l4_offset: usize,
+    /// 按IPv4总长度算出的传输层长度，不含以太网尾部填充
+    pub l4_len: usize,
+    pub proto: u8,
+    pub first_fragment: bool,
+}
+
+impl Ipv4Packet {
+    /// 交换MAC与IP地址，改写为长度`l4_len`的传输层发回对端，重算IP校验和
+    ///
+    /// 返回新的源、目的地址（报文中的原始值）。IP头带选项时不做任何修改并返回`None`
+    #[inline(always)]
+    pub fn reverse(&self, l4_len: usize) -> Option<(u32, u32)> {
+        if self.l4_offset != self.l3_offset + Ipv4Hdr::LEN {
+            return None;
+        }
+        let (eth, ip) = (self.eth, self.ip);
+        unsafe {
+            core::mem::swap(&mut (*eth).src_addr, &mut (*eth).dst_addr);
+            let (src, dst) = ((*ip).dst_addr, (*ip).src_addr);
+            (*ip).src_addr = src;
+            (*ip).dst_addr = dst;
+            (*ip).tot_len = ((Ipv4Hdr::LEN + l4_len) as u16).to_be();
+            (*ip).frag_off = IP_DF.to_be();
+            (*ip).ttl = REPLY_TTL;
+            (*ip).check = 0;
+            (*ip).check = csum::checksum(&*(ip as *const [u8; Ipv4Hdr::LEN])).to_be();
+            Some((src, dst))
+        }
+    }
+
+    /// 读取TCP或UDP的源、目的端口（主机字节序），其他协议与非首个分片返回`Ok(None)`
+    ///
+    /// 分类规则在完整解析传输层之前按端口查找
+    #[inline(always)]
+    pub fn ports(&self, cursor: &Cursor) -> Result<Option<(u16, u16)>, ()> {
+        if (self.proto != IPPROTO_TCP && self.proto != IPPROTO_UDP) || !self.first_fragment {
+            return Ok(None);
+        }
+        let source = cursor.be16(self.l4_offset)?;
+        let dest = cursor.be16(self.l4_offset + 2)?;
+        Ok(Some((source, dest)))
+    }
+
+    /// 改写源地址（网络字节序），增量更新IP与TCP/UDP校验和
+    #[inline(always)]
+    pub fn set_src(&self, cursor: &Cursor, addr: u32) -> Result<(), ()> {
+        let old = unsafe { (*self.ip).src_addr };
+        unsafe { (*self.ip).src_addr = addr };
+        self.readdressed(cursor, old, addr)
+    }
+
+    /// 改写目的地址（网络字节序），增量更新IP与TCP/UDP校验和
+    #[inline(always)]
+    pub fn set_dst(&self, cursor: &Cursor, addr: u32) -> Result<(), ()> {
+        let old = unsafe { (*self.ip).dst_addr };
+        unsafe { (*self.ip).dst_addr = addr };
+        self.readdressed(cursor, old, addr)
+    }
+
+    /// 改写TCP/UDP源端口（网络字节序），增量更新校验和
+    #[inline(always)]
+    pub fn set_src_port(&self, cursor: &Cursor, port: u16) -> Result<(), ()> {
+        self.set_port(cursor, 0, port)
+    }
+
+    /// 改写TCP/UDP目的端口（网络字节序），增量更新校验和
+    #[inline(always)]
+    pub fn set_dst_port(&self, cursor: &Cursor, port: u16) -> Result<(), ()> {
+        self.set_port(cursor, 2, port)
+    }
+
+    /// TCP/UDP校验和字段的偏移，其他协议与非首个分片返回`None`
+    #[inline(always)]
+    pub fn l4_check_offset(&self) -> Option<usize> {
+        if !self.first_fragment {
+            return None;
+        }
+        match self.proto {
+            IPPROTO_TCP => Some(self.l4_offset + TCP_CHECK_OFFSET),
+            IPPROTO_UDP => Some(self.l4_offset + UDP_CHECK_OFFSET),
+            _ => None,
+        }
+    }
+
+    /// 端口只在首个分片中，其他协议与分片返回错误
+    #[inline(always)]
+    fn set_port(&self, cursor: &Cursor, offset: usize, port: u16) -> Result<(), ()> {
+        if (self.proto != IPPROTO_TCP && self.proto != IPPROTO_UDP) || !self.first_fragment {
+            return Err(());
+        }
+        let field: *mut u16 = cursor.ptr_at(self.l4_offset + offset)?;
+        let old = unsafe { *field };
+        unsafe { *field = port };
+        self.update_l4_check(cursor, |check| csum::replace16(check, old, port))
+    }
+
+    /// IP头与TCP/UDP伪首部都覆盖地址
+    #[inline(always)]
+    fn readdressed(&self, cursor: &Cursor, old: u32, new: u32) -> Result<(), ()> {
+        let ip = self.ip;
+        unsafe { (*ip).check = csum::replace32((*ip).check, old, new) };
+        if !self.first_fragment {
+            return Ok(());
+        }
+        self.update_l4_check(cursor, |check| csum::replace32(check, old, new))
+    }
+
+    /// 增量更新TCP/UDP校验和，UDP校验和为0表示未校验，保持为0
+    #[inline(always)]
+    fn update_l4_check(&self, cursor: &Cursor, update: impl FnOnce(u16) -> u16) -> Result<(), ()> {
+        match self.proto {
+            IPPROTO_TCP => {
+                let check: *mut u16 = cursor.ptr_at(self.l4_offset + TCP_CHECK_OFFSET)?;
+                unsafe { *check = update(*check) };
+            }
+            IPPROTO_UDP => {
+                let check: *mut u16 = cursor.ptr_at(self.l4_offset + UDP_CHECK_OFFSET)?;
+                unsafe {
+                    if *check != 0 {
+                        // 结果为0时按RFC 768写成全1
+                        *check = match update(*check) {
+                            0 => 0xFFFF,
+                            check => check,
+                        };
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// 解析UDP头，协议不是UDP或不是首个分片时返回`Ok(None)`
+    #[inline(always)]
+    pub fn udp(&self, cursor: &Cursor) -> Result<Option<UdpDatagram>, ()> {
+        if self.proto != IPPROTO_UDP || !self.first_fragment {
+            return Ok(None);
+        }
+        let hdr: *mut UdpHdr = cursor.ptr_at(self.l4_offset)?;
+        let len = cursor.be16(self.l4_offset + 4)? as usize;
+        if len < UdpHdr::LEN || len > self.l4_len {
+            return Err(());
+        }
+        Ok(Some(UdpDatagram {
+            hdr,
+            offset: self.l4_offset,
+            payload_offset: self.l4_offset + UdpHdr::LEN,
+            payload_len: len - UdpHdr::LEN,
+        }))
+    }
+
+    /// 解析TCP头，协议不是TCP或不是首个分片时返回`Ok(None)`
+    #[inline(always)]
+    pub fn tcp(&self, cursor: &Cursor) -> Result<Option<TcpSegment>, ()> {
+        if self.proto != IPPROTO_TCP || !self.first_fragment {
+            return Ok(None);
+        }
+        let hdr: *mut TcpHdr = cursor.ptr_at(self.l4_offset)?;
+        let doff = ((cursor.u8(self.l4_offset + 12)? >> 4) as usize) * 4;
+        if doff < TcpHdr::LEN || doff > self.l4_len {
+            return Err(());
+        }
+        let flags = cursor.u8(self.l4_offset + 13)?;
+        Ok(Some(TcpSegment {
+            hdr,
+            flags,
+            offset: self.l4_offset,
+            payload_offset: self.l4_offset + doff,
+            payload_len: self.l4_len - doff,
+        }))
+    }
+}
+
+/// 解析出的TCP段
+#[derive(Clone, Copy)]
+pub struct TcpSegment {
+    pub hdr: *mut TcpHdr,
+    /// 第13字节的标志位，FIN为最低位
+    pub flags: u8,
+    /// TCP头的偏移
+    pub offset: usize,
+    /// 负载的偏移，已按doff跳过TCP选项
+    pub payload_offset: usize,
+    /// 按IPv4总长度算出的负载长度
+    pub payload_len: usize,
+}
+
+impl TcpSegment {
+    /// 负载切片，负载不完全在线性区（如TC下的非线性skb）时返回`None`
+    #[inline(always)]
+    pub fn payload<'a>(&self, cursor: &'a Cursor) -> Option<&'a [u8]> {
+        if self.payload_offset + self.payload_len > cursor.len() {
+            return None;
+        }
+        Some(unsafe {
+            core::slice::from_raw_parts(
+                (cursor.start + self.payload_offset) as *const u8,
+                self.payload_len,
+            )
+        })
+    }
+
+    /// 把报文原地改写为发回对端的纯ACK，返回改写后的帧长度
+    ///
+    /// 序列号取对端的确认号，确认号为`ack`，去掉TCP选项并重算校验和。
+    /// IP头带选项时返回`None`。调用方随后要把帧截到返回的长度
+    #[inline(always)]
+    pub fn into_ack(&self, packet: &Ipv4Packet, ack: u32, window: u16) -> Option<usize> {
+        let (src, dst) = packet.reverse(TcpHdr::LEN)?;
+        let hdr = self.hdr;
+        unsafe {
+            core::mem::swap(&mut (*hdr).source, &mut (*hdr).dest);
+            (*hdr).seq = (*hdr).ack_seq;
+            (*hdr).ack_seq = ack.to_be();
+            // 第12、13字节：doff为5即没有选项，只置ACK
+            let doff_flags = (hdr as *mut u8).add(12) as *mut [u8; 2];
+            *doff_flags = [((TcpHdr::LEN / 4) as u8) << 4, TCP_ACK];
+            (*hdr).window = window.to_be();
+            (*hdr).urg_ptr = 0;
+            (*hdr).check = 0;
+            let [s0, s1, s2, s3] = src.to_ne_bytes();
+            let [d0, d1, d2, d3] = dst.to_ne_bytes();
+            let [l0, l1] = (TcpHdr::LEN as u16).to_be_bytes();
+            let pseudo = [s0, s1, s2, s3, d0, d1, d2, d3, 0, IPPROTO_TCP, l0, l1];
+            let sum = csum::sum(&pseudo) + csum::sum(&*(hdr as *const [u8; TcpHdr::LEN]));
+            (*hdr).check = (!csum::fold(sum)).to_be();
+        }
+        Some(self.offset + TcpHdr::LEN)
+    }
+}
+
+/// 解析出的UDP数据报
+#[derive(Clone, Copy)]
+pub struct UdpDatagram {
+    pub hdr: *mut UdpHdr,
+    /// UDP头的偏移
+    pub offset: usize,
+    pub payload_offset: usize,
+    /// 按UDP长度字段算出的负载长度
+    pub payload_len: usize,
+}
+
+impl UdpDatagram {
+    /// 把报文原地改写为发回对端的数据报，只保留负载的前`len`字节，返回改写后的帧长度
+    ///
+    /// 校验和置0，IPv4下表示不校验。IP头带选项时返回`None`
+    #[inline(always)]
+    pub fn into_reply(&self, packet: &Ipv4Packet, len: usize) -> Option<usize> {
+        packet.reverse(UdpHdr::LEN + len)?;
+        let hdr = self.hdr;
+        unsafe {
+            core::mem::swap(&mut (*hdr).source, &mut (*hdr).dest);
+            (*hdr).len = ((UdpHdr::LEN + len) as u16).to_be();
+            (*hdr).check = 0;
+        }
+        Some(self.payload_offset + len)
+    }
+}
@@ -0,0 +1,129 @@
+//! hardworker经`TARGET_MAP`上报的变长记录
+//!
+//! 每条记录是定长的[`RecordHeader`]紧跟`len`字节负载，ebpf侧按实际负载长度输出，
+//! 用户态用[`Record::decode`]解析。各字段都按主机字节序存储
+
+use core::{fmt, net::SocketAddrV4};
+
+/// 记录头，描述负载来自哪个报文
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "config", derive(Debug))]
+pub struct RecordHeader {
+    /// `bpf_ktime_get_ns`，自开机起的单调时间
+    pub timestamp_ns: u64,
+    pub src_ip: u32,
+    pub dst_ip: u32,
+    pub src_port: u16,
+    pub dst_port: u16,
+    /// TCP序列号
+    pub seq: u32,
+    /// 收包网卡
+    pub ifindex: u32,
+    /// 收包队列
+    pub rx_queue: u32,
+    /// 紧随记录头的负载字节数
+    pub len: u32,
+    /// 报文中的完整负载长度，大于`len`表示被截断
+    pub payload_len: u32,
+    /// IP协议号
+    pub proto: u8,
+    _pad: [u8; 7],
+}
+
+impl RecordHeader {
+    pub const LEN: usize = core::mem::size_of::<Self>();
+
+    pub const fn zeroed() -> Self {
+        Self {
+            timestamp_ns: 0,
+            src_ip: 0,
+            dst_ip: 0,
+            src_port: 0,
+            dst_port: 0,
+            seq: 0,
+            ifindex: 0,
+            rx_queue: 0,
+            len: 0,
+            payload_len: 0,
+            proto: 0,
+            _pad: [0; 7],
+        }
+    }
+
+    pub fn source(&self) -> SocketAddrV4 {
+        SocketAddrV4::new(self.src_ip.into(), self.src_port)
+    }
+
+    pub fn destination(&self) -> SocketAddrV4 {
+        SocketAddrV4::new(self.dst_ip.into(), self.dst_port)
+    }
+
+    /// 按内存布局原样取出，用于把记录头与负载分开发送
+    pub fn as_bytes(&self) -> &[u8; Self::LEN] {
+        // repr(C)且各字段之间没有隐式填充
+        unsafe { &*(self as *const Self as *const [u8; Self::LEN]) }
+    }
+
+    /// 负载是否被截断
+    pub fn truncated(&self) -> bool {
+        self.payload_len > self.len
+    }
+}
+
+/// 解析出的一条记录，负载借用自ring buffer
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "config", derive(Debug))]
+pub struct Record<'a> {
+    pub header: RecordHeader,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Record<'a> {
+    /// 从ring buffer中的一条原始记录解析
+    pub fn decode(bytes: &'a [u8]) -> Result<Self, DecodeError> {
+        if bytes.len() < RecordHeader::LEN {
+            return Err(DecodeError::Short(bytes.len()));
+        }
+        // ring buffer只保证8字节对齐，这里不依赖对齐
+        let header = unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const RecordHeader) };
+        let payload = &bytes[RecordHeader::LEN..];
+        if payload.len() != header.len as usize {
+            return Err(DecodeError::Length {
+                expected: header.len,
+                actual: payload.len(),
+            });
+        }
+        Ok(Self { header, payload })
+    }
+}
+
+/// 记录解析错误
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// 不足一个记录头
+    Short(usize),
+    /// 负载长度与记录头声明的不一致
+    Length { expected: u32, actual: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Short(len) => {
+                write!(
+                    f,
+                    "记录只有{}字节，不足记录头的{}字节",
+                    len,
+                    RecordHeader::LEN
+                )
+            }
+            DecodeError::Length { expected, actual } => {
+                write!(f, "记录头声明负载{}字节，实际有{}字节", expected, actual)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "config")]
+impl std::error::Error for DecodeError {}
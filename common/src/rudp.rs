@@ -0,0 +1,309 @@
+//! sensor到hardworker的轻量可靠UDP传输
+//!
+//! 每个数据报的UDP负载以8字节的[`RudpHdr`]开头。hardworker在XDP中按
+//! （sensor的IP，sensor id）维护一个64个序列号的去重窗口，新数据报上报为记录，
+//! 无论是否重复都把报文原地改写为只带头部的ACK发回。
+//! sender停等发送，超时未收到对应序列号的ACK就重传
+
+use core::net::Ipv4Addr;
+
+pub const RUDP_VERSION: u8 = 1;
+
+/// 头部`flags`的取值
+pub const RUDP_DATA: u8 = 0x01;
+pub const RUDP_ACK: u8 = 0x02;
+/// sender启动后的第一个数据报，hardworker据此以新的序列号重建去重窗口
+pub const RUDP_RESET: u8 = 0x04;
+
+/// 去重窗口覆盖的序列号个数
+pub const WINDOW_SIZE: u32 = 64;
+/// `RUDP_PEERS`表的容量，LRU表满后淘汰最久未见的sender
+pub const MAX_RUDP_PEERS: u32 = 256;
+
+/// UDP负载开头的头部，多字节字段按网络字节序存放
+///
+/// 全部用字节数组，报文中的偏移不保证对齐
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(Debug))]
+pub struct RudpHdr {
+    seq: [u8; 4],
+    sensor: [u8; 2],
+    pub flags: u8,
+    pub version: u8,
+}
+
+impl RudpHdr {
+    pub const LEN: usize = core::mem::size_of::<Self>();
+
+    pub const fn new(seq: u32, sensor: u16, flags: u8) -> Self {
+        Self {
+            seq: seq.to_be_bytes(),
+            sensor: sensor.to_be_bytes(),
+            flags,
+            version: RUDP_VERSION,
+        }
+    }
+
+    #[inline(always)]
+    pub fn seq(&self) -> u32 {
+        u32::from_be_bytes(self.seq)
+    }
+
+    #[inline(always)]
+    pub fn sensor(&self) -> u16 {
+        u16::from_be_bytes(self.sensor)
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let [s0, s1, s2, s3] = self.seq;
+        let [n0, n1] = self.sensor;
+        [s0, s1, s2, s3, n0, n1, self.flags, self.version]
+    }
+
+    /// 从UDP负载开头解析，长度不足或版本不符时返回`None`
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let &[s0, s1, s2, s3, n0, n1, flags, version, ..] = bytes else {
+            return None;
+        };
+        (version == RUDP_VERSION).then_some(Self {
+            seq: [s0, s1, s2, s3],
+            sensor: [n0, n1],
+            flags,
+            version,
+        })
+    }
+}
+
+/// `RUDP_PEERS`表的键，IP保持报文中的网络字节序
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "config", derive(Debug))]
+pub struct RudpKey {
+    pub ip: u32,
+    pub sensor: u16,
+    _pad: [u8; 2],
+}
+
+impl RudpKey {
+    pub const fn new(ip: u32, sensor: u16) -> Self {
+        Self {
+            ip,
+            sensor,
+            _pad: [0; 2],
+        }
+    }
+
+    pub fn source(&self) -> Ipv4Addr {
+        u32::from_be(self.ip).into()
+    }
+}
+
+/// 一个sender的去重窗口
+///
+/// `bitmap`的第i位表示`highest - i`已经收到，比窗口更旧的序列号一律按重复处理
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "config", derive(Debug))]
+pub struct Window {
+    /// 收到过的最大序列号
+    pub highest: u32,
+    _pad: u32,
+    pub bitmap: u64,
+    /// 上报的数据报数
+    pub accepted: u64,
+    /// 判为重复而只回复ACK的数据报数
+    pub duplicates: u64,
+}
+
+impl Window {
+    /// 以第一个数据报建立窗口
+    #[inline(always)]
+    pub fn new(seq: u32) -> Self {
+        Self {
+            highest: seq,
+            _pad: 0,
+            bitmap: 1,
+            accepted: 1,
+            duplicates: 0,
+        }
+    }
+
+    /// 记录序列号，第一次见到时返回true
+    #[inline(always)]
+    pub fn accept(&mut self, seq: u32) -> bool {
+        let ahead = seq.wrapping_sub(self.highest);
+        if (ahead as i32) > 0 {
+            self.bitmap = if ahead >= WINDOW_SIZE {
+                0
+            } else {
+                self.bitmap << ahead
+            } | 1;
+            self.highest = seq;
+            self.accepted += 1;
+            return true;
+        }
+        let behind = self.highest.wrapping_sub(seq);
+        if behind >= WINDOW_SIZE || self.bitmap & (1 << behind) != 0 {
+            self.duplicates += 1;
+            return false;
+        }
+        self.bitmap |= 1 << behind;
+        self.accepted += 1;
+        true
+    }
+
+    /// sender重启后从`seq`重新开始，计数保留
+    ///
+    /// 带RESET的数据报本身被重传时窗口已经从它开始，按重复处理
+    #[inline(always)]
+    pub fn reset(&mut self, seq: u32) -> bool {
+        if self.highest == seq && self.bitmap & 1 != 0 {
+            self.duplicates += 1;
+            return false;
+        }
+        *self = Self {
+            accepted: self.accepted + 1,
+            duplicates: self.duplicates,
+            ..Self::new(seq)
+        };
+        true
+    }
+}
+
+#[cfg(feature = "config")]
+pub use sender::Sender;
+
+#[cfg(feature = "config")]
+mod sender {
+    use std::{
+        io,
+        net::{SocketAddrV4, UdpSocket},
+        os::fd::AsRawFd,
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+        vec::Vec,
+    };
+
+    use super::{RudpHdr, RUDP_ACK, RUDP_DATA, RUDP_RESET};
+
+    /// sensor侧的停等sender
+    ///
+    /// 每个数据报都等到hardworker的ACK再发下一个，超时后原样重传，
+    /// 用尽重试次数时返回`TimedOut`，之后仍可继续发送
+    pub struct Sender {
+        socket: UdpSocket,
+        sensor: u16,
+        seq: u32,
+        /// 第一个数据报确认之前一直带RESET
+        flags: u8,
+        timeout: Duration,
+        retries: u32,
+        buf: Vec<u8>,
+        /// 累计的重传次数
+        pub retransmits: u64,
+    }
+
+    impl Sender {
+        /// 绑定任意本地端口并连接到hardworker，发出的报文带上标记TOS
+        pub fn connect(
+            target: SocketAddrV4,
+            tos: u8,
+            sensor: u16,
+            timeout: Duration,
+            retries: u32,
+        ) -> io::Result<Self> {
+            let socket = UdpSocket::bind("0.0.0.0:0")?;
+            let tos = tos as libc::c_int;
+            let ret = unsafe {
+                libc::setsockopt(
+                    socket.as_raw_fd(),
+                    libc::IPPROTO_IP,
+                    libc::IP_TOS,
+                    &tos as *const _ as *const libc::c_void,
+                    core::mem::size_of_val(&tos) as libc::socklen_t,
+                )
+            };
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            socket.connect(target)?;
+            // 初始序列号取时间，重启后的序列号不会落进hardworker的旧窗口
+            let seq = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|now| now.subsec_nanos() ^ now.as_secs() as u32)
+                .unwrap_or_default();
+            Ok(Self {
+                socket,
+                sensor,
+                seq,
+                flags: RUDP_DATA | RUDP_RESET,
+                timeout,
+                retries,
+                buf: Vec::new(),
+                retransmits: 0,
+            })
+        }
+
+        /// 下一个数据报的序列号
+        pub fn seq(&self) -> u32 {
+            self.seq
+        }
+
+        /// 发送一个数据报并等待确认，返回这个数据报的重传次数
+        pub fn send(&mut self, payload: &[u8]) -> io::Result<u32> {
+            self.buf.clear();
+            self.buf
+                .extend_from_slice(&RudpHdr::new(self.seq, self.sensor, self.flags).to_bytes());
+            self.buf.extend_from_slice(payload);
+
+            for attempt in 0..=self.retries {
+                if attempt > 0 {
+                    self.retransmits += 1;
+                }
+                self.socket.send(&self.buf)?;
+                if self.wait_ack()? {
+                    self.seq = self.seq.wrapping_add(1);
+                    self.flags = RUDP_DATA;
+                    return Ok(attempt);
+                }
+            }
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                std::format!("序列号{}重传{}次仍未收到ACK", self.seq, self.retries),
+            ))
+        }
+
+        /// 在超时前等到当前序列号的ACK返回true，之前序列号迟到的ACK直接丢弃
+        fn wait_ack(&self) -> io::Result<bool> {
+            let deadline = Instant::now() + self.timeout;
+            let mut ack = [0; RudpHdr::LEN];
+            loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Ok(false);
+                }
+                self.socket.set_read_timeout(Some(remaining))?;
+                let len = match self.socket.recv(&mut ack) {
+                    Ok(len) => len,
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                        ) =>
+                    {
+                        return Ok(false)
+                    }
+                    Err(e) => return Err(e),
+                };
+                if let Some(hdr) = RudpHdr::from_bytes(&ack[..len]) {
+                    if hdr.flags & RUDP_ACK != 0
+                        && hdr.seq() == self.seq
+                        && hdr.sensor() == self.sensor
+                    {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+    }
+}
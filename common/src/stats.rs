@@ -0,0 +1,136 @@
+//! 三个ebpf程序共用的per-CPU计数器
+//!
+//! 每个程序有一个单元素的per-CPU数组`STATS`，热路径上只做无锁自增，
+//! 用户态按需读取各CPU的值再用[`Stats::merge`]汇总
+
+use core::fmt;
+
+use crate::datapath::Outcome;
+
+/// TCP头第13字节中各标志位的名字，按位从低到高
+pub const TCP_FLAG_NAMES: [&str; 8] = ["FIN", "SYN", "RST", "PSH", "ACK", "URG", "ECE", "CWR"];
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(feature = "config", derive(Debug))]
+pub struct Stats {
+    /// 进入程序的报文数
+    pub seen: u64,
+    /// 命中写明DSCP的规则，即带标记TOS的报文数
+    pub tos_match: u64,
+    /// 只按端口命中规则的报文数
+    pub port_match: u64,
+    pub pass: u64,
+    pub tx: u64,
+    pub drop: u64,
+    pub redirect: u64,
+    pub aborted: u64,
+    /// 报文截断或头部非法
+    pub parse_error: u64,
+    /// 改写过地址的报文数
+    pub rewritten: u64,
+    /// 在XDP中直接回复的ACK数
+    pub acked: u64,
+    /// sensor出口打上标记TOS的报文数
+    pub marked: u64,
+    /// 命中规则的TCP段中各标志出现的次数，下标见[`TCP_FLAG_NAMES`]
+    pub tcp_flags: [u64; 8],
+}
+
+impl Stats {
+    /// 按程序的处理结果计数，XDP与TC入口共用
+    #[inline(always)]
+    pub fn outcome(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Aborted => self.aborted += 1,
+            Outcome::Drop => self.drop += 1,
+            Outcome::Pass => self.pass += 1,
+            Outcome::Tx => self.tx += 1,
+            Outcome::Redirect => self.redirect += 1,
+        }
+    }
+
+    /// 按命中的规则是否要求DSCP分别计数
+    #[inline(always)]
+    pub fn matched(&mut self, tos: bool) {
+        if tos {
+            self.tos_match += 1;
+        } else {
+            self.port_match += 1;
+        }
+    }
+
+    /// 每个置位的标志各计一次，不再只认第一个
+    #[inline(always)]
+    pub fn flags(&mut self, flags: u8) {
+        for (i, count) in self.tcp_flags.iter_mut().enumerate() {
+            if flags & (1 << i) != 0 {
+                *count += 1;
+            }
+        }
+    }
+
+    /// 汇总各CPU的计数
+    pub fn merge(&self, other: &Self) -> Self {
+        let mut tcp_flags = self.tcp_flags;
+        for (total, count) in tcp_flags.iter_mut().zip(other.tcp_flags) {
+            *total += count;
+        }
+        Self {
+            seen: self.seen + other.seen,
+            tos_match: self.tos_match + other.tos_match,
+            port_match: self.port_match + other.port_match,
+            pass: self.pass + other.pass,
+            tx: self.tx + other.tx,
+            drop: self.drop + other.drop,
+            redirect: self.redirect + other.redirect,
+            aborted: self.aborted + other.aborted,
+            parse_error: self.parse_error + other.parse_error,
+            rewritten: self.rewritten + other.rewritten,
+            acked: self.acked + other.acked,
+            marked: self.marked + other.marked,
+            tcp_flags,
+        }
+    }
+}
+
+#[cfg(feature = "user")]
+impl Stats {
+    /// 读取`STATS`并汇总所有CPU
+    pub fn read(
+        map: &aya::maps::PerCpuArray<aya::maps::MapData, Stats>,
+    ) -> Result<Self, aya::maps::MapError> {
+        let values = map.get(&0, 0)?;
+        Ok(values
+            .iter()
+            .fold(Self::default(), |total, stats| total.merge(stats)))
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "报文: {}, 命中TOS: {}, 命中端口: {}, 解析失败: {}, 改写: {}, 回复ACK: {}, 出口标记: {}",
+            self.seen,
+            self.tos_match,
+            self.port_match,
+            self.parse_error,
+            self.rewritten,
+            self.acked,
+            self.marked
+        )?;
+        writeln!(
+            f,
+            "PASS: {}, TX: {}, DROP: {}, REDIRECT: {}, ABORTED: {}",
+            self.pass, self.tx, self.drop, self.redirect, self.aborted
+        )?;
+        for (i, (name, count)) in TCP_FLAG_NAMES.iter().zip(self.tcp_flags).enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{name}: {count}")?;
+        }
+        Ok(())
+    }
+}
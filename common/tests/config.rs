@@ -0,0 +1,251 @@
+//! `const.toml`的校验：每条规则给一个最小的违例，检查报告的键与行号
+
+use common::{
+    config::{Consts, Role},
+    Data,
+};
+
+/// 三个节点的最小合法配置
+const VALID: &str = r#"[mark]
+tos = 104
+port = 12345
+
+[data]
+mtu = 1200
+load_u64_count = 128
+
+[[node]]
+name = "logger"
+role = "logger"
+mac = "02:00:00:00:00:01"
+ip = "10.0.0.1"
+
+[[node]]
+name = "hardworker"
+role = "hardworker"
+mac = "02:00:00:00:00:02"
+ip = "10.0.0.2"
+
+[[node]]
+name = "sensor"
+role = "sensor"
+mac = "02:00:00:00:00:03"
+ip = "10.0.0.3"
+hardworker = "hardworker"
+loggers = ["logger"]
+"#;
+
+/// 一个违例：在`VALID`上做一次替换，期望报告`key`，行号为`needle`最后一次出现时的末行
+struct Case {
+    from: &'static str,
+    to: &'static str,
+    key: &'static str,
+    needle: &'static str,
+}
+
+/// 替换`VALID`中的一段
+const fn replace(from: &'static str, to: &'static str, key: &'static str) -> Case {
+    Case {
+        from,
+        to,
+        key,
+        needle: to,
+    }
+}
+
+/// 在节点之前加一节，`needle`定位出错的那一行
+const fn section(to: &'static str, key: &'static str, needle: &'static str) -> Case {
+    Case {
+        from: "",
+        to,
+        key,
+        needle,
+    }
+}
+
+const CASES: &[Case] = &[
+    // 节点引用
+    replace(
+        r#"hardworker = "hardworker""#,
+        r#"hardworker = "nobody""#,
+        "node[2].hardworker",
+    ),
+    replace(
+        r#"loggers = ["logger"]"#,
+        r#"loggers = ["hardworker"]"#,
+        "node[2].loggers",
+    ),
+    replace(r#"loggers = ["logger"]"#, "loggers = []", "node[2].loggers"),
+    replace(
+        r#"ip = "10.0.0.2""#,
+        "ip = \"10.0.0.2\"\nloggers = [\"logger\"]",
+        "node[1].loggers",
+    ),
+    replace(
+        r#"ip = "10.0.0.2""#,
+        "ip = \"10.0.0.2\"\nport = 9000",
+        "node[1].port",
+    ),
+    replace(r#"name = "sensor""#, r#"name = "logger""#, "node[2].name"),
+    // 重复与非法的地址
+    replace(r#"ip = "10.0.0.3""#, r#"ip = "10.0.0.1""#, "node[2].ip"),
+    replace(
+        r#"mac = "02:00:00:00:00:03""#,
+        r#"mac = "02:00:00:00:00:01""#,
+        "node[2].mac",
+    ),
+    replace(
+        r#"mac = "02:00:00:00:00:01""#,
+        r#"mac = "02:00:00:00:01""#,
+        "node[0].mac",
+    ),
+    replace(
+        r#"mac = "02:00:00:00:00:01""#,
+        r#"mac = "01:00:00:00:00:01""#,
+        "node[0].mac",
+    ),
+    // TOS与DSCP
+    replace("tos = 104", "tos = 105", "mark.tos"),
+    replace("tos = 104", "tos = 8", "mark.tos"),
+    replace("tos = 104", "tos = 40", "mark.tos"),
+    replace("tos = 104", "tos = 256", "mark.tos"),
+    // 端口
+    replace("port = 12345", "port = 0", "mark.port"),
+    section("[udp]\nport = 12345\n", "udp.port", "port = 12345"),
+    section("[udp]\nport = 70000\n", "udp.port", "port = 70000"),
+    // MTU与负载长度
+    replace(
+        "load_u64_count = 128",
+        "load_u64_count = 146",
+        "data.load_u64_count",
+    ),
+    replace(
+        "load_u64_count = 128",
+        "load_u64_count = 0",
+        "data.load_u64_count",
+    ),
+    replace("mtu = 1200", "mtu = 40", "data.mtu"),
+    // ring buffer
+    section("[ring]\nrecords = 0\n", "ring.records", "records = 0"),
+    section(
+        "[ring]\nrecords = 65537\n",
+        "ring.records",
+        "records = 65537",
+    ),
+    // sensor的出口标记
+    section(
+        "[sensor]\nports = [1, 2, 3, 4, 5, 6, 7, 8, 9]\n",
+        "sensor.ports",
+        "ports = [1",
+    ),
+    section("[sensor]\nports = [0]\n", "sensor.ports", "ports = [0]"),
+    section(
+        "[sensor]\ncgroup = \"/tmp/sensor\"\n",
+        "sensor.cgroup",
+        "cgroup =",
+    ),
+    section(
+        "[sensor]\ncgroup = \"/sys/fs/cgroup\"\n",
+        "sensor.cgroup",
+        "cgroup =",
+    ),
+    // 挂载点
+    Case {
+        from: r#"ip = "10.0.0.2""#,
+        to: "ip = \"10.0.0.2\"\nhook = \"tc\"",
+        key: "node[1].hook",
+        needle: "hook = \"tc\"",
+    },
+    // 日志存储
+    section("[store]\ndir = \" \"\n", "store.dir", "dir ="),
+    section(
+        "[store]\ndir = \"/tmp/log\"\nsegment_mb = 0\n",
+        "store.segment_mb",
+        "segment_mb = 0",
+    ),
+    section(
+        "[store]\ndir = \"/tmp/log\"\nsegment_mb = 16\nmax_mb = 8\n",
+        "store.max_mb",
+        "max_mb = 8",
+    ),
+    section(
+        "[store]\ndir = \"/tmp/log\"\nmax_age_hours = -1\n",
+        "store.max_age_hours",
+        "max_age_hours = -1",
+    ),
+    // toml本身的错误也带键名与行号
+    replace("port = 12345", "port = 12345\ncolor = 1", "mark.color"),
+];
+
+fn apply(case: &Case) -> String {
+    let src = if case.from.is_empty() {
+        VALID.replacen("[[node]]", &format!("{}\n[[node]]", case.to), 1)
+    } else {
+        assert!(VALID.contains(case.from), "{}不在VALID中", case.from);
+        VALID.replacen(case.from, case.to, 1)
+    };
+    // 挂载点的违例需要AF_XDP上报
+    if case.key.ends_with(".hook") {
+        return src.replacen("[[node]]", "[hardworker]\ncapture = \"xsk\"\n\n[[node]]", 1);
+    }
+    src
+}
+
+fn line(src: &str, needle: &str) -> usize {
+    let offset = src.rfind(needle).expect("needle不在配置中");
+    // 多行的替换以最后一行为准
+    src[..offset].matches('\n').count() + needle.matches('\n').count() + 1
+}
+
+#[test]
+fn reports_key_and_line() {
+    for case in CASES {
+        let src = apply(case);
+        let errors = match Consts::parse(&src) {
+            Ok(_) => panic!("应当拒绝{}:\n{src}", case.key),
+            Err(errors) => errors.0,
+        };
+        let expected = line(&src, case.needle);
+        assert!(
+            errors
+                .iter()
+                .any(|e| e.key == case.key && e.line == Some(expected)),
+            "期望{}在第{expected}行，实际{errors:?}\n{src}",
+            case.key
+        );
+        // 每个违例只应报一处，不连带出无关的错误
+        assert_eq!(errors.len(), 1, "{errors:?}\n{src}");
+    }
+}
+
+#[test]
+fn accepts_valid() {
+    let consts = Consts::parse(VALID).unwrap();
+    assert_eq!(consts.mark.tos, 104);
+    assert_eq!(consts.node(Role::Sensor, None).unwrap().loggers, ["logger"]);
+    // 缺省标记mark.port
+    assert_eq!(consts.sensor.ports, [12345]);
+    assert!(consts.udp.is_none() && consts.store.is_none());
+
+    let sample = include_str!("../../const.toml");
+    let consts = Consts::parse(sample).unwrap();
+    // 规范化输出可以再次解析
+    Consts::parse(&consts.to_toml()).unwrap();
+}
+
+#[test]
+fn data_ignores_other_tables() {
+    // 构建只看[data]，节点的错误留给加载时报告
+    let src = VALID.replacen(r#"ip = "10.0.0.3""#, r#"ip = "10.0.0.1""#, 1);
+    assert!(Consts::parse(&src).is_err());
+    let data = Data::parse(&src).unwrap();
+    assert_eq!(data, Consts::parse(VALID).unwrap().data);
+    assert_eq!(Data::from_env(&data.to_env()), Some(data));
+    assert_eq!(Data::from_env("1200"), None);
+
+    let src = VALID.replacen("mtu = 1200", "mtu = 40", 1);
+    let errors = Data::parse(&src).unwrap_err().0;
+    assert_eq!(errors.len(), 1, "{errors:?}");
+    assert_eq!(errors[0].key, "data.mtu");
+    assert_eq!(errors[0].line, Some(line(&src, "mtu = 40")));
+}
@@ -0,0 +1,143 @@
+//! 增量更新与完整重算对比，覆盖ebpf中所有改写路径的用法：
+//! 直接从报文内存中读出原始字段，不做字节序转换
+
+use common::csum::{checksum, replace16, replace32};
+use proptest::prelude::*;
+
+const IP_CHECK: usize = 10;
+const IP_SRC: usize = 12;
+const IP_DST: usize = 16;
+
+fn ipv4_header(tos: u8, id: u16, ttl: u8, proto: u8, src: [u8; 4], dst: [u8; 4]) -> [u8; 20] {
+    let mut hdr = [0u8; 20];
+    hdr[0] = 0x45;
+    hdr[1] = tos;
+    hdr[2..4].copy_from_slice(&60u16.to_be_bytes());
+    hdr[4..6].copy_from_slice(&id.to_be_bytes());
+    hdr[6] = 0x40;
+    hdr[8] = ttl;
+    hdr[9] = proto;
+    hdr[IP_SRC..IP_SRC + 4].copy_from_slice(&src);
+    hdr[IP_DST..IP_DST + 4].copy_from_slice(&dst);
+    let check = checksum(&hdr);
+    hdr[IP_CHECK..IP_CHECK + 2].copy_from_slice(&check.to_be_bytes());
+    hdr
+}
+
+fn read16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_ne_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn read32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn write16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_ne_bytes());
+}
+
+/// TCP校验和覆盖伪首部，这里把伪首部拼在TCP段前面一起计算
+fn tcp_segment(src: [u8; 4], dst: [u8; 4], sport: u16, dport: u16, payload: &[u8]) -> Vec<u8> {
+    let mut segment = vec![0u8; 20];
+    segment[0..2].copy_from_slice(&sport.to_be_bytes());
+    segment[2..4].copy_from_slice(&dport.to_be_bytes());
+    segment[12] = 5 << 4;
+    segment[13] = 0x18;
+    segment.extend_from_slice(payload);
+    let check = checksum(&[&pseudo_header(src, dst, segment.len()), &segment[..]].concat());
+    segment[16..18].copy_from_slice(&check.to_be_bytes());
+    segment
+}
+
+fn pseudo_header(src: [u8; 4], dst: [u8; 4], len: usize) -> Vec<u8> {
+    let mut pseudo = Vec::with_capacity(12);
+    pseudo.extend_from_slice(&src);
+    pseudo.extend_from_slice(&dst);
+    pseudo.extend_from_slice(&[0, 6]);
+    pseudo.extend_from_slice(&(len as u16).to_be_bytes());
+    pseudo
+}
+
+proptest! {
+    #[test]
+    fn ipv4_address_rewrite(
+        tos: u8, id: u16, ttl: u8, proto: u8,
+        src: [u8; 4], dst: [u8; 4], new_dst: [u8; 4],
+    ) {
+        let mut hdr = ipv4_header(tos, id, ttl, proto, src, dst);
+        let check = replace32(read16(&hdr, IP_CHECK), read32(&hdr, IP_DST), u32::from_ne_bytes(new_dst));
+        hdr[IP_DST..IP_DST + 4].copy_from_slice(&new_dst);
+        write16(&mut hdr, IP_CHECK, check);
+
+        prop_assert_eq!(checksum(&hdr), 0);
+        let expected = ipv4_header(tos, id, ttl, proto, src, new_dst);
+        prop_assert_eq!(hdr, expected);
+    }
+
+    #[test]
+    fn tcp_pseudo_header_rewrite(
+        src: [u8; 4], dst: [u8; 4], new_src: [u8; 4],
+        sport: u16, dport: u16,
+        payload in proptest::collection::vec(any::<u8>(), 0..64),
+    ) {
+        let mut segment = tcp_segment(src, dst, sport, dport, &payload);
+        let check = replace32(read16(&segment, 16), u32::from_ne_bytes(src), u32::from_ne_bytes(new_src));
+        write16(&mut segment, 16, check);
+
+        let expected = tcp_segment(new_src, dst, sport, dport, &payload);
+        prop_assert_eq!(segment, expected);
+    }
+
+    #[test]
+    fn port_rewrite(
+        src: [u8; 4], dst: [u8; 4],
+        sport: u16, dport: u16, new_dport: u16,
+        payload in proptest::collection::vec(any::<u8>(), 0..64),
+    ) {
+        let mut segment = tcp_segment(src, dst, sport, dport, &payload);
+        let check = replace16(read16(&segment, 16), read16(&segment, 2), new_dport.to_be());
+        segment[2..4].copy_from_slice(&new_dport.to_be_bytes());
+        write16(&mut segment, 16, check);
+
+        let expected = tcp_segment(src, dst, sport, new_dport, &payload);
+        prop_assert_eq!(segment, expected);
+    }
+
+    /// 任意位置的16位字，首字固定非零以排除全零数据的±0歧义
+    #[test]
+    fn arbitrary_word_rewrite(
+        words in proptest::collection::vec(any::<u16>(), 1..32),
+        index: proptest::sample::Index,
+        new: u16,
+    ) {
+        let mut data: Vec<u8> = [0x4500u16].iter().chain(&words).flat_map(|w| w.to_be_bytes()).collect();
+        data.extend_from_slice(&[0, 0]);
+        let check_at = data.len() - 2;
+        let check = checksum(&data);
+        data[check_at..].copy_from_slice(&check.to_be_bytes());
+
+        let offset = (index.index(words.len()) + 1) * 2;
+        let check = replace16(read16(&data, check_at), read16(&data, offset), new.to_be());
+        data[offset..offset + 2].copy_from_slice(&new.to_be_bytes());
+        write16(&mut data, check_at, check);
+
+        prop_assert_eq!(checksum(&data), 0);
+        data[check_at..].copy_from_slice(&[0, 0]);
+        prop_assert_eq!(check, checksum(&data).to_be());
+    }
+}
+
+/// 原先只更新低16位的实现在跨/16网段改写时会出错，这里固定一个例子
+#[test]
+fn rewrite_across_slash16() {
+    let mut hdr = ipv4_header(0x68, 0xd829, 64, 6, [192, 168, 1, 96], [192, 168, 1, 93]);
+    let new_dst = [10, 0, 0, 79];
+    let check = replace32(
+        read16(&hdr, IP_CHECK),
+        read32(&hdr, IP_DST),
+        u32::from_ne_bytes(new_dst),
+    );
+    hdr[IP_DST..IP_DST + 4].copy_from_slice(&new_dst);
+    write16(&mut hdr, IP_CHECK, check);
+    assert_eq!(checksum(&hdr), 0);
+}
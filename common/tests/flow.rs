@@ -0,0 +1,125 @@
+//! 流跟踪的判定：按序、缺口、乱序补齐、重传与序列号回绕
+
+use common::{
+    flow::{Flow, Verdict, MAX_HOLES},
+    packet::{TCP_ACK, TCP_PSH, TCP_SYN},
+};
+
+const DATA: u8 = TCP_ACK | TCP_PSH;
+
+/// 以SYN建立流，之后的数据从`isn + 1`开始
+fn open(isn: u32) -> Flow {
+    Flow::new(isn, 0, 0, TCP_SYN)
+}
+
+fn observe(flow: &mut Flow, seq: u32, len: u32) -> Verdict {
+    flow.observe(seq, len, 0, DATA)
+}
+
+#[test]
+fn in_order() {
+    let mut flow = open(999);
+    assert!(observe(&mut flow, 1000, 100) == Verdict::InOrder);
+    assert!(observe(&mut flow, 1100, 100) == Verdict::InOrder);
+    assert_eq!(flow.next_seq, 1200);
+    assert_eq!(flow.rcv_nxt, 1200);
+    assert_eq!(flow.hole(), 0);
+    assert!(flow.observe(1200, 0, 1, TCP_ACK) == Verdict::Empty);
+}
+
+#[test]
+fn gap() {
+    let mut flow = open(999);
+    assert!(observe(&mut flow, 1000, 100) == Verdict::InOrder);
+    assert!(observe(&mut flow, 1300, 100) == Verdict::Gap);
+    assert_eq!(flow.next_seq, 1400);
+    // 确认号停在缺口起点，不能越过没收到的数据
+    assert_eq!(flow.rcv_nxt, 1100);
+    assert_eq!(flow.hole(), 200);
+    assert_eq!(flow.gaps, 1);
+}
+
+#[test]
+fn reordered_front_fill_shrinks_hole() {
+    let mut flow = open(999);
+    observe(&mut flow, 1000, 100);
+    observe(&mut flow, 1300, 100);
+    assert!(observe(&mut flow, 1100, 100) == Verdict::Reordered);
+    assert_eq!(flow.hole(), 100);
+    assert_eq!(flow.rcv_nxt, 1200);
+    // 同一段再到是重传，不能再算作补齐
+    assert!(observe(&mut flow, 1100, 100) == Verdict::Retransmit);
+    assert!(observe(&mut flow, 1200, 100) == Verdict::Reordered);
+    assert_eq!(flow.hole(), 0);
+    assert_eq!(flow.rcv_nxt, 1400);
+    assert!(observe(&mut flow, 1200, 100) == Verdict::Retransmit);
+    assert_eq!(flow.reordered, 2);
+    assert_eq!(flow.retransmits, 2);
+}
+
+#[test]
+fn reordered_back_and_middle_fill() {
+    let mut flow = open(999);
+    observe(&mut flow, 1000, 100);
+    observe(&mut flow, 1500, 100);
+    // 缺口[1100, 1500)，先补后部再补中间
+    assert!(observe(&mut flow, 1400, 100) == Verdict::Reordered);
+    assert_eq!(flow.hole(), 300);
+    assert!(observe(&mut flow, 1200, 100) == Verdict::Reordered);
+    assert_eq!(flow.hole(), 200);
+    // 中间补上的数据之前还有缺口
+    assert_eq!(flow.rcv_nxt, 1100);
+    assert!(observe(&mut flow, 1200, 100) == Verdict::Retransmit);
+    assert!(observe(&mut flow, 1300, 100) == Verdict::Reordered);
+    assert!(observe(&mut flow, 1100, 100) == Verdict::Reordered);
+    assert_eq!(flow.hole(), 0);
+    assert_eq!(flow.rcv_nxt, 1600);
+}
+
+#[test]
+fn retransmit() {
+    let mut flow = open(999);
+    observe(&mut flow, 1000, 100);
+    observe(&mut flow, 1100, 100);
+    assert!(observe(&mut flow, 1000, 100) == Verdict::Retransmit);
+    assert!(observe(&mut flow, 1050, 100) == Verdict::Retransmit);
+    // 末尾带了新数据按序处理
+    assert!(observe(&mut flow, 1150, 100) == Verdict::InOrder);
+    assert_eq!(flow.next_seq, 1250);
+}
+
+#[test]
+fn full_hole_table_never_drops_missing_data() {
+    let mut flow = open(999);
+    let mut seq = 1000;
+    for _ in 0..MAX_HOLES + 2 {
+        assert!(observe(&mut flow, seq, 100) != Verdict::Retransmit);
+        seq += 200;
+    }
+    // 每个缺口都还没补上，补齐时都应算作乱序。表满时合并的缺口多估了缺失的数据，
+    // 确认号可以落后，但不能越过没收到的数据
+    let mut seq = 1100;
+    for _ in 0..MAX_HOLES + 1 {
+        assert!(flow.rcv_nxt <= seq);
+        assert!(observe(&mut flow, seq, 100) == Verdict::Reordered);
+        seq += 200;
+    }
+}
+
+#[test]
+fn wraparound() {
+    let isn = u32::MAX - 150;
+    let mut flow = open(isn);
+    let start = isn.wrapping_add(1);
+    assert!(observe(&mut flow, start, 100) == Verdict::InOrder);
+    // 跨过0的段
+    assert!(observe(&mut flow, start.wrapping_add(100), 100) == Verdict::InOrder);
+    assert_eq!(flow.next_seq, start.wrapping_add(200));
+    assert!(observe(&mut flow, start.wrapping_add(300), 100) == Verdict::Gap);
+    assert_eq!(flow.hole(), 100);
+    assert_eq!(flow.rcv_nxt, start.wrapping_add(200));
+    assert!(observe(&mut flow, start.wrapping_add(200), 100) == Verdict::Reordered);
+    assert_eq!(flow.hole(), 0);
+    assert_eq!(flow.rcv_nxt, start.wrapping_add(400));
+    assert!(observe(&mut flow, start, 100) == Verdict::Retransmit);
+}
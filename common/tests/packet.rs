@@ -0,0 +1,141 @@
+//! 三个程序对同一条连接的地址与端口改写：sensor发往hardworker的段经hardworker与logger改写后，
+//! 应与sensor直接发给logger监听端口的段逐字节相同，logger的回包经sensor改写后应与
+//! hardworker直接回复的段相同，校验和都与完整重算一致
+
+use common::{csum::checksum, packet::Cursor};
+use proptest::prelude::*;
+
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+
+/// 以太网帧，传输层校验和为`None`时按完整内容计算，UDP可以给出`Some(0)`表示未校验
+fn frame(
+    proto: u8,
+    (src, sport): ([u8; 4], u16),
+    (dst, dport): ([u8; 4], u16),
+    payload: &[u8],
+    l4_check: Option<u16>,
+) -> Vec<u8> {
+    let mut l4 = match proto {
+        TCP => {
+            let mut hdr = vec![0u8; 20];
+            hdr[12] = 5 << 4;
+            hdr[13] = 0x18;
+            hdr
+        }
+        _ => {
+            let mut hdr = vec![0u8; 8];
+            hdr[4..6].copy_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+            hdr
+        }
+    };
+    l4[0..2].copy_from_slice(&sport.to_be_bytes());
+    l4[2..4].copy_from_slice(&dport.to_be_bytes());
+    l4.extend_from_slice(payload);
+    let check_at = if proto == TCP { 16 } else { 6 };
+    let check = l4_check.unwrap_or_else(|| {
+        let mut pseudo = Vec::with_capacity(12 + l4.len());
+        pseudo.extend_from_slice(&src);
+        pseudo.extend_from_slice(&dst);
+        pseudo.extend_from_slice(&[0, proto]);
+        pseudo.extend_from_slice(&(l4.len() as u16).to_be_bytes());
+        pseudo.extend_from_slice(&l4);
+        match checksum(&pseudo) {
+            0 if proto == UDP => 0xFFFF,
+            check => check,
+        }
+    });
+    l4[check_at..check_at + 2].copy_from_slice(&check.to_be_bytes());
+
+    let mut ip = [0u8; 20];
+    ip[0] = 0x45;
+    ip[1] = 0x68;
+    ip[2..4].copy_from_slice(&((20 + l4.len()) as u16).to_be_bytes());
+    ip[6] = 0x40;
+    ip[8] = 64;
+    ip[9] = proto;
+    ip[12..16].copy_from_slice(&src);
+    ip[16..20].copy_from_slice(&dst);
+    let check = checksum(&ip);
+    ip[10..12].copy_from_slice(&check.to_be_bytes());
+
+    let mut frame = vec![0u8; 12];
+    frame.extend_from_slice(&0x0800u16.to_be_bytes());
+    frame.extend_from_slice(&ip);
+    frame.extend_from_slice(&l4);
+    frame
+}
+
+/// 报文缓冲区，与网卡驱动一样在帧前留2字节让IP头按4字节对齐
+#[repr(C, align(4))]
+struct Buffer([u8; 256]);
+
+/// 在帧上解析出IPv4报文并执行改写
+fn rewrite(frame: &mut [u8], f: impl FnOnce(&Cursor, &common::packet::Ipv4Packet)) {
+    let mut buffer = Buffer([0; 256]);
+    let data = &mut buffer.0[2..2 + frame.len()];
+    data.copy_from_slice(frame);
+    let start = data.as_mut_ptr() as usize;
+    let cursor = Cursor::new(start, start + data.len());
+    let packet = cursor.ipv4().unwrap().unwrap();
+    f(&cursor, &packet);
+    frame.copy_from_slice(data);
+}
+
+fn ip(addr: [u8; 4]) -> u32 {
+    u32::from_ne_bytes(addr)
+}
+
+proptest! {
+    /// hardworker改写目的地址，logger再把目的端口改为自己的监听端口
+    #[test]
+    fn forward_to_logger_port(
+        sensor: [u8; 4], hardworker: [u8; 4], logger: [u8; 4],
+        sport: u16, service: u16, listen: u16, tcp: bool,
+        payload in proptest::collection::vec(any::<u8>(), 0..64),
+    ) {
+        let proto = if tcp { TCP } else { UDP };
+        let mut packet = frame(proto, (sensor, sport), (hardworker, service), &payload, None);
+        rewrite(&mut packet, |cursor, packet| {
+            packet.set_dst(cursor, ip(logger)).unwrap();
+            packet.set_dst_port(cursor, listen.to_be()).unwrap();
+        });
+
+        let expected = frame(proto, (sensor, sport), (logger, listen), &payload, None);
+        prop_assert_eq!(packet, expected);
+    }
+
+    /// sensor把logger的回包还原成来自hardworker的服务端口
+    #[test]
+    fn reply_from_hardworker(
+        sensor: [u8; 4], hardworker: [u8; 4], logger: [u8; 4],
+        sport: u16, service: u16, listen: u16,
+        payload in proptest::collection::vec(any::<u8>(), 0..64),
+    ) {
+        let mut packet = frame(TCP, (logger, listen), (sensor, sport), &payload, None);
+        rewrite(&mut packet, |cursor, packet| {
+            packet.set_src(cursor, ip(hardworker)).unwrap();
+            packet.set_src_port(cursor, service.to_be()).unwrap();
+        });
+
+        let expected = frame(TCP, (hardworker, service), (sensor, sport), &payload, None);
+        prop_assert_eq!(packet, expected);
+    }
+
+    /// 未校验的UDP数据报改写后仍为未校验
+    #[test]
+    fn udp_without_checksum(
+        src: [u8; 4], dst: [u8; 4], new_dst: [u8; 4],
+        sport: u16, dport: u16, new_dport: u16,
+        payload in proptest::collection::vec(any::<u8>(), 0..64),
+    ) {
+        let mut packet = frame(UDP, (src, sport), (dst, dport), &payload, Some(0));
+        rewrite(&mut packet, |cursor, packet| {
+            packet.set_dst(cursor, ip(new_dst)).unwrap();
+            packet.set_dst_port(cursor, new_dport.to_be()).unwrap();
+        });
+
+        let expected = frame(UDP, (src, sport), (new_dst, new_dport), &payload, Some(0));
+        prop_assert_eq!(packet, expected);
+    }
+}
@@ -0,0 +1,38 @@
+use std::{env, fs, path::Path};
+use common::{config::DATA_ENV, Data};
+use which::which;
+
+/// Building this crate has an undeclared dependency on the `bpf-linker` binary. This would be
+/// better expressed by [artifact-dependencies][bindeps] but issues such as
+/// https://github.com/rust-lang/cargo/issues/12385 make their use impractical for the time being.
+///
+/// This file implements an imperfect solution: it causes cargo to rebuild the crate whenever the
+/// mtime of `which bpf-linker` changes. Note that possibility that a new bpf-linker is added to
+/// $PATH ahead of the one used as the cache key still exists. Solving this in the general case
+/// would require rebuild-if-changed-env=PATH *and* rebuild-if-changed={every-directory-in-PATH}
+/// which would likely mean far too much cache invalidation.
+///
+/// [bindeps]: https://doc.rust-lang.org/nightly/cargo/reference/unstable.html?highlight=feature#artifact-dependencies
+
+fn main() {
+    let bpf_linker = which("bpf-linker").unwrap();
+    println!("cargo:rerun-if-changed={}", bpf_linker.to_str().unwrap());
+
+    // 填充常量
+    // MAC、IP与标记改为由用户态在加载时写入，这里只编入决定类型大小的[data]。
+    // 经myapp构建时由它的构建脚本通过环境变量传入，只有[data]变化时才重新编译
+    println!("cargo:rerun-if-env-changed={DATA_ENV}");
+    let data = match env::var(DATA_ENV) {
+        Ok(value) => Data::from_env(&value)
+            .unwrap_or_else(|| panic!("{DATA_ENV}={value}不是mtu,load_u64_count的格式")),
+        Err(_) => {
+            println!("cargo:rerun-if-changed=../../const.toml");
+            Data::from_path("../../const.toml").unwrap_or_else(|e| panic!("{e:#}"))
+        }
+    };
+
+    let out_dir = env::var_os("OUT_DIR").unwrap();
+    let dest_path = Path::new(&out_dir).join("const_gen.rs");
+
+    fs::write(&dest_path, data.declaration()).unwrap();
+}
@@ -0,0 +1,426 @@
+#![no_std]
+#![no_main]
+
+include!(concat!(env!("OUT_DIR"), "/const_gen.rs"));
+
+use aya_ebpf::{
+    bindings::BPF_F_NO_PREALLOC,
+    helpers::bpf_ktime_get_ns,
+    macros::{classifier, map, xdp},
+    maps::{lpm_trie::Key, HashMap, LpmTrie, LruHashMap, PerCpuArray, RingBuf, XskMap},
+    programs::{TcContext, XdpContext},
+};
+
+use aya_log_ebpf::{debug, error};
+use common::{
+    classify::{self, Action, Match, RuleKey, ANY_NET, MAX_NETS, MAX_RULES},
+    datapath::{Datapath, Outcome},
+    flow::{Flow, FlowKey, Verdict, MAX_FLOWS},
+    packet::{Cursor, Ipv4Packet, TcpSegment, UdpDatagram, TCP_FIN, TCP_RST, TCP_SYN},
+    record::RecordHeader,
+    rudp::{
+        RudpHdr, RudpKey, Window, MAX_RUDP_PEERS, RUDP_ACK, RUDP_DATA, RUDP_RESET, RUDP_VERSION,
+    },
+    stats::Stats,
+    Config, Peer, RingStats, Route, MAX_ROUTES, MAX_XSK_QUEUES,
+};
+use network_types::{ip::Ipv4Hdr, tcp::TcpHdr};
+
+#[xdp]
+pub fn hardworker(ctx: XdpContext) -> u32 {
+    run(&ctx)
+}
+
+/// XDP表现不好的网卡上挂到clsact的ingress，逻辑与XDP相同，发回的报文重定向到同一网卡的出口
+#[classifier]
+pub fn hardworker_tc(ctx: TcContext) -> i32 {
+    run(&ctx)
+}
+
+#[inline(always)]
+fn run<C: Datapath>(ctx: &C) -> C::Ret {
+    let outcome = try_hardworker(ctx).unwrap_or(Outcome::Aborted);
+    count(|stats| {
+        stats.seen += 1;
+        stats.outcome(outcome);
+    });
+    ctx.finish(outcome)
+}
+
+// 计划传输几个u64大小
+const DATA_SIZE: usize = DATA.load_u64_count * 8;
+const _: [(); 1] = [(); ((DATA_SIZE + Ipv4Hdr::LEN + TcpHdr::LEN) <= DATA.mtu) as usize]; // 保守负载大小
+
+/// 由用户态加载时写入
+#[no_mangle]
+static CONFIG: Config = Config::zeroed();
+
+/// 以sensor的IP（网络字节序）为键，查它对应的logger组
+#[map(name = "ROUTES")]
+static ROUTES: HashMap<u32, Route> = HashMap::with_max_entries(MAX_ROUTES, 0);
+
+/// 分类规则，固定在bpffs中由用户态随时增删，见[`classify`]
+#[map(name = "SRC_NETS")]
+static SRC_NETS: LpmTrie<u32, u16> = LpmTrie::pinned(MAX_NETS, BPF_F_NO_PREALLOC);
+
+#[map(name = "DST_NETS")]
+static DST_NETS: LpmTrie<u32, u16> = LpmTrie::pinned(MAX_NETS, BPF_F_NO_PREALLOC);
+
+#[map(name = "RULES")]
+static RULES: HashMap<RuleKey, u8> = HashMap::pinned(MAX_RULES, 0);
+
+/// 变长记录的组装区，ring buffer的reserve只接受定长，先在这里拼好再整体输出
+#[repr(C)]
+struct Scratch {
+    header: RecordHeader,
+    data: [u8; DATA_SIZE],
+}
+
+#[map(name = "SCRATCH")]
+static SCRATCH: PerCpuArray<Scratch> = PerCpuArray::with_max_entries(1, 0);
+
+/// 这里的大小只是占位，用户态按`ring.records`在加载时改写
+#[map(name = "TARGET_MAP")]
+static mut TARGET_MAP: RingBuf = RingBuf::with_byte_size(core::mem::size_of::<Scratch>() as u32, 0);
+
+/// AF_XDP模式下以收包队列为键的socket，由用户态在绑定后填入
+#[map(name = "XSKS")]
+static XSKS: XskMap = XskMap::with_max_entries(MAX_XSK_QUEUES, 0);
+
+/// 每条sensor流的序列号状态
+///
+/// 同一条流经RSS总落在同一个队列上，这里不加锁
+#[map(name = "FLOWS")]
+static FLOWS: LruHashMap<FlowKey, Flow> = LruHashMap::with_max_entries(MAX_FLOWS, 0);
+
+/// 每个可靠UDP sender的去重窗口
+#[map(name = "RUDP_PEERS")]
+static RUDP_PEERS: LruHashMap<RudpKey, Window> = LruHashMap::with_max_entries(MAX_RUDP_PEERS, 0);
+
+/// 本程序的计数器，用户态按需读取
+#[map(name = "STATS")]
+static STATS: PerCpuArray<Stats> = PerCpuArray::with_max_entries(1, 0);
+
+#[map(name = "RING_STATS")]
+static RING_STATS: PerCpuArray<RingStats> = PerCpuArray::with_max_entries(1, 0);
+
+/// XDP回复的ACK通告的窗口
+const ACK_WINDOW: u16 = u16::MAX;
+
+/// `bpf_ringbuf_query`查询待消费字节数的标志
+const BPF_RB_AVAIL_DATA: u64 = 0;
+
+fn try_hardworker<C: Datapath>(ctx: &C) -> Result<Outcome, ()> {
+    let config = config();
+
+    let cursor = ctx.cursor();
+    let Some(packet) = cursor
+        .ipv4()
+        .inspect_err(|_| count(|stats| stats.parse_error += 1))?
+    else {
+        return Ok(Outcome::Pass);
+    };
+    let ipv4hdr = packet.ip;
+    let Some((source, dest)) = packet
+        .ports(&cursor)
+        .inspect_err(|_| count(|stats| stats.parse_error += 1))?
+    else {
+        return Ok(Outcome::Pass);
+    };
+    debug!(ctx, "src port: {}, dst port: {}", source, dest);
+    let Some(Match { action, tos }) = classify(&packet, dest) else {
+        return Ok(Outcome::Pass);
+    };
+    count(|stats| stats.matched(tos));
+    let forward_only = match action {
+        Action::Capture => false,
+        Action::Forward => true,
+        Action::Pass => return Ok(Outcome::Pass),
+        Action::Drop => return Ok(Outcome::Drop),
+    };
+
+    // 不认识的sensor交给协议栈处理
+    let Some(route) = (unsafe { ROUTES.get(&(*ipv4hdr).src_addr) }) else {
+        return Ok(Outcome::Pass);
+    };
+    let logger = route.logger(source).ok_or(())?;
+
+    if let Some(udp) = packet
+        .udp(&cursor)
+        .inspect_err(|_| count(|stats| stats.parse_error += 1))?
+    {
+        if forward_only {
+            return forward(ctx, &cursor, &packet, route, logger);
+        }
+        return try_rudp(ctx, &cursor, &packet, &udp);
+    }
+
+    let Some(tcp) = packet
+        .tcp(&cursor)
+        .inspect_err(|_| count(|stats| stats.parse_error += 1))?
+    else {
+        return Ok(Outcome::Pass);
+    };
+    let tcphdr = tcp.hdr;
+    count(|stats| stats.flags(tcp.flags));
+    if forward_only {
+        return forward(ctx, &cursor, &packet, route, logger);
+    }
+
+    let (key, flow, verdict) = track(&packet, &tcp);
+    // ACK模式下由本机确认数据段，建立与关闭连接的段仍交给logger
+    let acking =
+        config.ack() && tcp.payload_len > 0 && tcp.flags & (TCP_SYN | TCP_FIN | TCP_RST) == 0;
+
+    // 只上报应用数据，负载长度由IPv4总长度与doff算出，不含以太网尾部填充。
+    // 不看PSH：一次写入拆成的多个段通常只有最后一个带PSH，ACK模式下其余的段也要记下
+    // 重传的段之前已经上报过
+    if tcp.payload_len > 0 && verdict != Verdict::Retransmit {
+        // AF_XDP模式下整帧交给用户态，由它上报并完成本来的ACK或转发。
+        // 用户态按段尾确认，只有连续收到的位置正好在段尾时才能交给它，
+        // 开出或补齐缺口的段仍走ring buffer，由这里重复确认缺口起点。
+        // 只有XDP能重定向到AF_XDP socket，配置校验保证TC挂载时不会启用
+        let seq = unsafe { u32::from_be((*tcphdr).seq) };
+        let cumulative = flow.rcv_nxt == seq.wrapping_add(tcp.payload_len as u32);
+        if C::XDP && config.xsk() && (!acking || cumulative) {
+            // 该队列上没有socket时退回ring buffer
+            if XSKS.redirect(ctx.rx_queue(), 0).is_ok() {
+                commit(&key, &flow);
+                return Ok(Outcome::Redirect);
+            }
+        }
+        let (source, dest) = unsafe { ((*tcphdr).source, (*tcphdr).dest) };
+        let written = capture(
+            ctx,
+            &packet,
+            (source, dest),
+            seq,
+            tcp.payload_offset,
+            tcp.payload_len,
+        )?;
+        // 没记下来的段不确认，也不计入流状态，sensor重传时再上报
+        if !written && acking {
+            return Ok(Outcome::Drop);
+        }
+    }
+    commit(&key, &flow);
+
+    // 报文原地改写为ACK发回sensor，不再转发。确认号是连续收到的位置，
+    // 有缺口时重复确认缺口起点，不会确认没收到的数据
+    if acking {
+        if let Some(len) = tcp.into_ack(&packet, flow.rcv_nxt, ACK_WINDOW) {
+            // 截断会使报文指针失效，放在所有改写之后
+            ctx.truncate(len)?;
+            count(|stats| stats.acked += 1);
+            return Ok(Outcome::Tx);
+        }
+    }
+
+    debug!(ctx, "forward pack with TCP checksum: 0x{:x}", unsafe {
+        (*tcphdr).check.swap_bytes()
+    });
+    forward(ctx, &cursor, &packet, route, logger)
+}
+
+/// 修改数据包发送字段，由本机传输到日志器
+#[inline(always)]
+fn forward<C: Datapath>(
+    ctx: &C,
+    cursor: &Cursor,
+    packet: &Ipv4Packet,
+    route: &Route,
+    logger: &Peer,
+) -> Result<Outcome, ()> {
+    unsafe {
+        (*packet.eth).src_addr = route.hardworker.mac;
+        (*packet.eth).dst_addr = logger.mac;
+    }
+    ctx.set_dst(cursor, packet, logger.ip.to_be())?;
+    count(|stats| stats.rewritten += 1);
+    Ok(Outcome::Tx)
+}
+
+/// 可靠UDP：新数据报上报为记录，无论是否重复都原地改写为ACK发回sender。
+/// 记录没写入时不确认，也不更新去重窗口，sender重传时再上报
+#[inline(always)]
+fn try_rudp<C: Datapath>(
+    ctx: &C,
+    cursor: &Cursor,
+    packet: &Ipv4Packet,
+    udp: &UdpDatagram,
+) -> Result<Outcome, ()> {
+    let (ip, udphdr) = (packet.ip, udp.hdr);
+    if udp.payload_len < RudpHdr::LEN {
+        count(|stats| stats.parse_error += 1);
+        return Err(());
+    }
+    let hdr: *mut RudpHdr = cursor
+        .ptr_at(udp.payload_offset)
+        .inspect_err(|_| count(|stats| stats.parse_error += 1))?;
+    let (seq, flags) = unsafe { ((*hdr).seq(), (*hdr).flags) };
+    if flags & RUDP_DATA == 0 || unsafe { (*hdr).version } != RUDP_VERSION {
+        return Ok(Outcome::Pass);
+    }
+
+    let key = RudpKey::new(unsafe { (*ip).src_addr }, unsafe { (*hdr).sensor() });
+    // 在副本上判定，记录写入后再写回
+    let (window, fresh) = match unsafe { RUDP_PEERS.get(&key) } {
+        Some(window) => {
+            let mut window = *window;
+            let fresh = if flags & RUDP_RESET != 0 {
+                window.reset(seq)
+            } else {
+                window.accept(seq)
+            };
+            (window, fresh)
+        }
+        None => (Window::new(seq), true),
+    };
+    let len = udp.payload_len - RudpHdr::LEN;
+    if fresh && len > 0 {
+        let ports = unsafe { ((*udphdr).source, (*udphdr).dest) };
+        let written = capture(
+            ctx,
+            packet,
+            ports,
+            seq,
+            udp.payload_offset + RudpHdr::LEN,
+            len,
+        )?;
+        if !written {
+            return Ok(Outcome::Drop);
+        }
+    }
+    // 表满时LRU会淘汰旧的sender，插入失败最多让之后的重传再上报一次
+    let _ = RUDP_PEERS.insert(&key, &window, 0);
+
+    // 重复的数据报说明之前的ACK丢了，照样确认
+    unsafe { (*hdr).flags = RUDP_ACK };
+    let Some(len) = udp.into_reply(packet, RudpHdr::LEN) else {
+        return Ok(Outcome::Pass);
+    };
+    ctx.truncate(len)?;
+    count(|stats| stats.acked += 1);
+    Ok(Outcome::Tx)
+}
+
+/// 把负载组装成记录写入`TARGET_MAP`，端口保持网络字节序传入，返回记录是否写入
+#[inline(always)]
+fn capture<C: Datapath>(
+    ctx: &C,
+    packet: &Ipv4Packet,
+    (source, dest): (u16, u16),
+    seq: u32,
+    payload_offset: usize,
+    payload_len: usize,
+) -> Result<bool, ()> {
+    let ip = packet.ip;
+    let scratch = SCRATCH.get_ptr_mut(0).ok_or(())?;
+    // 显式上界让verifier确认拷贝长度非零且不超过组装区
+    let len = payload_len.min(DATA_SIZE);
+    unsafe {
+        let header = &mut (*scratch).header;
+        *header = RecordHeader::zeroed();
+        header.timestamp_ns = bpf_ktime_get_ns();
+        header.src_ip = u32::from_be((*ip).src_addr);
+        header.dst_ip = u32::from_be((*ip).dst_addr);
+        header.src_port = u16::from_be(source);
+        header.dst_port = u16::from_be(dest);
+        header.seq = seq;
+        header.ifindex = ctx.ifindex();
+        header.rx_queue = ctx.rx_queue();
+        header.len = len as u32;
+        header.payload_len = payload_len as u32;
+        header.proto = packet.proto;
+        // 负载可能不在线性区（多缓冲区XDP、非线性skb），由helper拷贝
+        if let Err(ret) = ctx.load_bytes(payload_offset, (*scratch).data.as_mut_ptr(), len) {
+            error!(ctx, "load payload failed: {}", ret);
+            return Ok(false);
+        }
+        let record = core::slice::from_raw_parts(scratch as *const u8, RecordHeader::LEN + len);
+        #[allow(static_mut_refs)]
+        let ret = TARGET_MAP.output(record, 0);
+        // 满了只计数，每次都打日志会在突发时放大开销
+        if let Some(stats) = RING_STATS.get_ptr_mut(0) {
+            if ret.is_ok() {
+                (*stats).submitted += 1;
+                #[allow(static_mut_refs)]
+                let avail = TARGET_MAP.query(BPF_RB_AVAIL_DATA);
+                (*stats).avail_last = avail;
+                if avail > (*stats).avail_max {
+                    (*stats).avail_max = avail;
+                }
+            } else {
+                (*stats).dropped += 1;
+            }
+        }
+        Ok(ret.is_ok())
+    }
+}
+
+/// 在`FLOWS`中流状态的副本上判定段，确定要处理时再由[`commit`]写回
+#[inline(always)]
+fn track(packet: &Ipv4Packet, tcp: &TcpSegment) -> (FlowKey, Flow, Verdict) {
+    let (ip, hdr) = (packet.ip, tcp.hdr);
+    let key = unsafe {
+        FlowKey {
+            src_ip: (*ip).src_addr,
+            dst_ip: (*ip).dst_addr,
+            src_port: (*hdr).source,
+            dst_port: (*hdr).dest,
+        }
+    };
+    let (seq, ack) = unsafe { (u32::from_be((*hdr).seq), u32::from_be((*hdr).ack_seq)) };
+    let len = tcp.payload_len as u32;
+    match unsafe { FLOWS.get(&key) } {
+        Some(flow) => {
+            let mut flow = *flow;
+            let verdict = flow.observe(seq, len, ack, tcp.flags);
+            (key, flow, verdict)
+        }
+        None => (key, Flow::new(seq, len, ack, tcp.flags), Verdict::New),
+    }
+}
+
+#[inline(always)]
+fn commit(key: &FlowKey, flow: &Flow) {
+    // 表满时LRU会淘汰旧流，插入失败只是少跟踪一条流
+    let _ = FLOWS.insert(key, flow, 0);
+}
+
+/// 按分类规则决定报文的处理方式，服务端口为目的端口
+#[inline(always)]
+fn classify(packet: &Ipv4Packet, port: u16) -> Option<Match> {
+    let ip = packet.ip;
+    let (src, dst, tos) = unsafe { ((*ip).src_addr, (*ip).dst_addr, (*ip).tos) };
+    let net =
+        |nets: &LpmTrie<u32, u16>, addr| nets.get(&Key::new(32, addr)).copied().unwrap_or(ANY_NET);
+    classify::lookup(
+        net(&SRC_NETS, src),
+        net(&DST_NETS, dst),
+        packet.proto,
+        port,
+        tos >> 2,
+        |key| unsafe { RULES.get(key) }.copied(),
+    )
+}
+
+/// 在本CPU的计数器上计数，per-CPU数组无需原子操作
+#[inline(always)]
+fn count(f: impl FnOnce(&mut Stats)) {
+    if let Some(stats) = STATS.get_ptr_mut(0) {
+        f(unsafe { &mut *stats });
+    }
+}
+
+/// 读取运行时配置，volatile避免编译器把全零初始值常量折叠进程序
+#[inline(always)]
+fn config() -> Config {
+    unsafe { core::ptr::read_volatile(&CONFIG) }
+}
+
+#[cfg(not(test))]
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
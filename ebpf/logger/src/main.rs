@@ -0,0 +1,242 @@
+#![no_std]
+#![no_main]
+
+include!(concat!(env!("OUT_DIR"), "/const_gen.rs"));
+
+use aya_ebpf::{
+    bindings::BPF_F_NO_PREALLOC,
+    helpers::bpf_ktime_get_ns,
+    macros::{classifier, map, xdp},
+    maps::{lpm_trie::Key, HashMap, LpmTrie, PerCpuArray, RingBuf},
+    programs::{TcContext, XdpContext},
+};
+
+use aya_log_ebpf::{debug, error};
+use common::{
+    classify::{self, Action, Match, RuleKey, ANY_NET, MAX_NETS, MAX_RULES},
+    datapath::{Datapath, Outcome},
+    packet::Ipv4Packet,
+    record::RecordHeader,
+    stats::Stats,
+    RingStats, Route, MAX_ROUTES,
+};
+
+/// 每条日志记录最多保留的负载字节数
+const DATA_SIZE: usize = DATA.load_u64_count * 8;
+
+/// 以sensor的IP（网络字节序）为键，查该sensor的MAC
+#[map(name = "ROUTES")]
+static ROUTES: HashMap<u32, Route> = HashMap::with_max_entries(MAX_ROUTES, 0);
+
+/// 分类规则，固定在bpffs中由用户态随时增删，见[`classify`]
+#[map(name = "SRC_NETS")]
+static SRC_NETS: LpmTrie<u32, u16> = LpmTrie::pinned(MAX_NETS, BPF_F_NO_PREALLOC);
+
+#[map(name = "DST_NETS")]
+static DST_NETS: LpmTrie<u32, u16> = LpmTrie::pinned(MAX_NETS, BPF_F_NO_PREALLOC);
+
+#[map(name = "RULES")]
+static RULES: HashMap<RuleKey, u8> = HashMap::pinned(MAX_RULES, 0);
+
+/// 日志记录的组装区，与hardworker的记录格式相同
+#[repr(C)]
+struct Scratch {
+    header: RecordHeader,
+    data: [u8; DATA_SIZE],
+}
+
+#[map(name = "SCRATCH")]
+static SCRATCH: PerCpuArray<Scratch> = PerCpuArray::with_max_entries(1, 0);
+
+/// 这里的大小只是占位，用户态按`ring.records`在加载时改写
+#[map(name = "LOG_RING")]
+static mut LOG_RING: RingBuf = RingBuf::with_byte_size(core::mem::size_of::<Scratch>() as u32, 0);
+
+#[map(name = "RING_STATS")]
+static RING_STATS: PerCpuArray<RingStats> = PerCpuArray::with_max_entries(1, 0);
+
+/// 本程序的计数器，用户态按需读取
+#[map(name = "STATS")]
+static STATS: PerCpuArray<Stats> = PerCpuArray::with_max_entries(1, 0);
+
+#[xdp]
+pub fn logger(ctx: XdpContext) -> u32 {
+    run(&ctx)
+}
+
+/// XDP表现不好的网卡上挂到clsact的ingress，逻辑与XDP相同
+#[classifier]
+pub fn logger_tc(ctx: TcContext) -> i32 {
+    run(&ctx)
+}
+
+#[inline(always)]
+fn run<C: Datapath>(ctx: &C) -> C::Ret {
+    let outcome = try_logger(ctx).unwrap_or(Outcome::Aborted);
+    count(|stats| {
+        stats.seen += 1;
+        stats.outcome(outcome);
+    });
+    ctx.finish(outcome)
+}
+
+fn try_logger<C: Datapath>(ctx: &C) -> Result<Outcome, ()> {
+    let cursor = ctx.cursor();
+    let Some(packet) = cursor
+        .ipv4()
+        .inspect_err(|_| count(|stats| stats.parse_error += 1))?
+    else {
+        return Ok(Outcome::Pass);
+    };
+    let ipv4hdr = packet.ip;
+    let Some((source, dest)) = packet
+        .ports(&cursor)
+        .inspect_err(|_| count(|stats| stats.parse_error += 1))?
+    else {
+        return Ok(Outcome::Pass);
+    };
+    match classify(&packet, dest) {
+        None => return Ok(Outcome::Pass),
+        Some(Match { action, tos }) => {
+            count(|stats| stats.matched(tos));
+            match action {
+                Action::Capture | Action::Forward => {}
+                Action::Pass => return Ok(Outcome::Pass),
+                Action::Drop => return Ok(Outcome::Drop),
+            }
+        }
+    }
+
+    // （序列号，负载偏移，负载长度），UDP没有序列号记为0
+    let payload = if let Some(tcp) = packet
+        .tcp(&cursor)
+        .inspect_err(|_| count(|stats| stats.parse_error += 1))?
+    {
+        count(|stats| stats.flags(tcp.flags));
+        debug!(ctx, "get TCP pack with checksum {}", unsafe {
+            (*tcp.hdr).check
+        });
+        let seq = u32::from_be(unsafe { (*tcp.hdr).seq });
+        Some((seq, tcp.payload_offset, tcp.payload_len))
+    } else {
+        packet
+            .udp(&cursor)
+            .inspect_err(|_| count(|stats| stats.parse_error += 1))?
+            .map(|udp| (0, udp.payload_offset, udp.payload_len))
+    };
+
+    let Some(route) = (unsafe { ROUTES.get(&(*ipv4hdr).src_addr) }) else {
+        return Ok(Outcome::Pass);
+    };
+    // 与hardworker按同一源端口选出的logger就是本机，由它得到本机的地址与监听端口
+    let Some(logger) = route.logger(source) else {
+        return Ok(Outcome::Pass);
+    };
+
+    // 每个带负载的报文生成一条日志记录，报文本身照常交给协议栈
+    if let Some((seq, payload_offset, payload_len)) = payload {
+        if payload_len > 0 {
+            log(
+                ctx,
+                &packet,
+                (source, dest),
+                seq,
+                payload_offset,
+                payload_len,
+            )?;
+        }
+    }
+
+    // 还原成sensor直接发给本机监听端口的样子，协议栈看到的对端始终是sensor，
+    // 回包由sensor的程序再改写成来自hardworker
+    unsafe {
+        (*packet.eth).src_addr = route.sensor.mac;
+    }
+    // TC下改写之后报文指针失效，不能再读报文
+    let ip = logger.ip.to_be();
+    if unsafe { (*ipv4hdr).dst_addr } != ip {
+        ctx.set_dst(&cursor, &packet, ip)?;
+    }
+    if dest != logger.port {
+        ctx.set_dst_port(&cursor, &packet, logger.port.to_be())?;
+    }
+    count(|stats| stats.rewritten += 1);
+    Ok(Outcome::Pass)
+}
+
+/// 把负载的前`DATA_SIZE`字节连同报文信息组装成记录写入`LOG_RING`，端口按主机字节序传入
+#[inline(always)]
+fn log<C: Datapath>(
+    ctx: &C,
+    packet: &Ipv4Packet,
+    (source, dest): (u16, u16),
+    seq: u32,
+    payload_offset: usize,
+    payload_len: usize,
+) -> Result<(), ()> {
+    let ip = packet.ip;
+    let scratch = SCRATCH.get_ptr_mut(0).ok_or(())?;
+    // 显式上界让verifier确认拷贝长度非零且不超过组装区
+    let len = payload_len.min(DATA_SIZE);
+    unsafe {
+        let header = &mut (*scratch).header;
+        *header = RecordHeader::zeroed();
+        header.timestamp_ns = bpf_ktime_get_ns();
+        header.src_ip = u32::from_be((*ip).src_addr);
+        header.dst_ip = u32::from_be((*ip).dst_addr);
+        header.src_port = source;
+        header.dst_port = dest;
+        header.seq = seq;
+        header.ifindex = ctx.ifindex();
+        header.rx_queue = ctx.rx_queue();
+        header.len = len as u32;
+        header.payload_len = payload_len as u32;
+        header.proto = packet.proto;
+        if let Err(ret) = ctx.load_bytes(payload_offset, (*scratch).data.as_mut_ptr(), len) {
+            error!(ctx, "load payload failed: {}", ret);
+            return Ok(());
+        }
+        let record = core::slice::from_raw_parts(scratch as *const u8, RecordHeader::LEN + len);
+        #[allow(static_mut_refs)]
+        let ret = LOG_RING.output(record, 0);
+        if let Some(stats) = RING_STATS.get_ptr_mut(0) {
+            if ret.is_ok() {
+                (*stats).submitted += 1;
+            } else {
+                (*stats).dropped += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 按分类规则决定报文的处理方式，服务端口为目的端口
+#[inline(always)]
+fn classify(packet: &Ipv4Packet, port: u16) -> Option<Match> {
+    let ip = packet.ip;
+    let (src, dst, tos) = unsafe { ((*ip).src_addr, (*ip).dst_addr, (*ip).tos) };
+    let net =
+        |nets: &LpmTrie<u32, u16>, addr| nets.get(&Key::new(32, addr)).copied().unwrap_or(ANY_NET);
+    classify::lookup(
+        net(&SRC_NETS, src),
+        net(&DST_NETS, dst),
+        packet.proto,
+        port,
+        tos >> 2,
+        |key| unsafe { RULES.get(key) }.copied(),
+    )
+}
+
+/// 在本CPU的计数器上计数，per-CPU数组无需原子操作
+#[inline(always)]
+fn count(f: impl FnOnce(&mut Stats)) {
+    if let Some(stats) = STATS.get_ptr_mut(0) {
+        f(unsafe { &mut *stats });
+    }
+}
+
+#[cfg(not(test))]
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
@@ -0,0 +1,211 @@
+#![no_std]
+#![no_main]
+
+include!(concat!(env!("OUT_DIR"), "/const_gen.rs"));
+
+use aya_ebpf::{
+    bindings::{BPF_F_NO_PREALLOC, TC_ACT_OK},
+    helpers::gen::bpf_skb_ancestor_cgroup_id,
+    macros::{classifier, map, xdp},
+    maps::{lpm_trie::Key, HashMap, LpmTrie, PerCpuArray},
+    programs::{TcContext, XdpContext},
+    EbpfContext,
+};
+
+use aya_log_ebpf::debug;
+use common::{
+    classify::{self, Action, Match, RuleKey, ANY_NET, MAX_NETS, MAX_RULES},
+    datapath::{Datapath, Outcome},
+    packet::Ipv4Packet,
+    stats::Stats,
+    Marking, Route, MAX_ROUTES,
+};
+
+/// 出口标记规则，由用户态加载时写入
+#[no_mangle]
+static MARKING: Marking = Marking::zeroed();
+
+/// 以logger的IP（网络字节序）为键，查本sensor对应的hardworker
+#[map(name = "ROUTES")]
+static ROUTES: HashMap<u32, Route> = HashMap::with_max_entries(MAX_ROUTES, 0);
+
+/// 分类规则，固定在bpffs中由用户态随时增删，见[`classify`]
+#[map(name = "SRC_NETS")]
+static SRC_NETS: LpmTrie<u32, u16> = LpmTrie::pinned(MAX_NETS, BPF_F_NO_PREALLOC);
+
+#[map(name = "DST_NETS")]
+static DST_NETS: LpmTrie<u32, u16> = LpmTrie::pinned(MAX_NETS, BPF_F_NO_PREALLOC);
+
+#[map(name = "RULES")]
+static RULES: HashMap<RuleKey, u8> = HashMap::pinned(MAX_RULES, 0);
+
+/// 本程序的计数器，用户态按需读取
+#[map(name = "STATS")]
+static STATS: PerCpuArray<Stats> = PerCpuArray::with_max_entries(1, 0);
+
+#[xdp]
+pub fn sensor(ctx: XdpContext) -> u32 {
+    run(&ctx)
+}
+
+/// XDP表现不好的网卡上挂到clsact的ingress，逻辑与XDP相同
+#[classifier]
+pub fn sensor_tc(ctx: TcContext) -> i32 {
+    run(&ctx)
+}
+
+/// 挂到clsact的egress，为本机发往hardworker的报文打上标记TOS，应用不必设置`IP_TOS`
+///
+/// 与入口的挂载点无关，出口不做分类也不丢包，任何情况都放行
+#[classifier]
+pub fn sensor_egress(ctx: TcContext) -> i32 {
+    let _ = try_mark(&ctx);
+    TC_ACT_OK
+}
+
+#[inline(always)]
+fn run<C: Datapath>(ctx: &C) -> C::Ret {
+    let outcome = try_sensor(ctx).unwrap_or(Outcome::Aborted);
+    count(|stats| {
+        stats.seen += 1;
+        stats.outcome(outcome);
+    });
+    ctx.finish(outcome)
+}
+
+fn try_sensor<C: Datapath>(ctx: &C) -> Result<Outcome, ()> {
+    let cursor = ctx.cursor();
+    let Some(packet) = cursor
+        .ipv4()
+        .inspect_err(|_| count(|stats| stats.parse_error += 1))?
+    else {
+        return Ok(Outcome::Pass);
+    };
+    let ipv4hdr = packet.ip;
+    let Some((source, _)) = packet
+        .ports(&cursor)
+        .inspect_err(|_| count(|stats| stats.parse_error += 1))?
+    else {
+        return Ok(Outcome::Pass);
+    };
+    match classify(&packet, source) {
+        None => return Ok(Outcome::Pass),
+        Some(Match { action, tos }) => {
+            count(|stats| stats.matched(tos));
+            match action {
+                Action::Capture | Action::Forward => {}
+                Action::Pass => return Ok(Outcome::Pass),
+                Action::Drop => return Ok(Outcome::Drop),
+            }
+        }
+    }
+
+    if let Some(tcp) = packet
+        .tcp(&cursor)
+        .inspect_err(|_| count(|stats| stats.parse_error += 1))?
+    {
+        count(|stats| stats.flags(tcp.flags));
+    }
+
+    let Some(route) = (unsafe { ROUTES.get(&(*ipv4hdr).src_addr) }) else {
+        return Ok(Outcome::Pass);
+    };
+    // 只还原logger从自己的监听端口发回的段，其他端口与本组转发无关
+    let Some(logger) = route.logger_at(u32::from_be(unsafe { (*ipv4hdr).src_addr })) else {
+        return Ok(Outcome::Pass);
+    };
+    if source != logger.port {
+        return Ok(Outcome::Pass);
+    }
+
+    debug!(ctx, "pack from logger with checksum: 0x{:x}", unsafe {
+        (*ipv4hdr).check.swap_bytes()
+    });
+    // 修改mac地址从logger到hardworker
+    unsafe {
+        (*packet.eth).src_addr = route.hardworker.mac;
+    }
+    // 地址与端口从logger还原为hardworker，与本机发出连接时的对端一致
+    // TC下改写之后报文指针失效，不能再读报文
+    ctx.set_src(&cursor, &packet, route.hardworker.ip.to_be())?;
+    if source != route.hardworker.port {
+        ctx.set_src_port(&cursor, &packet, route.hardworker.port.to_be())?;
+    }
+    count(|stats| stats.rewritten += 1);
+    Ok(Outcome::Pass)
+}
+
+/// 发往hardworker、还没带标记TOS的报文，目的端口在标记端口中或来自标记cgroup时改写TOS
+#[inline(always)]
+fn try_mark(ctx: &TcContext) -> Result<(), ()> {
+    let marking = marking();
+    if marking.is_empty() {
+        return Ok(());
+    }
+    let cursor = ctx.cursor();
+    let Some(packet) = cursor.ipv4()? else {
+        return Ok(());
+    };
+    let (dst, tos) = unsafe { ((*packet.ip).dst_addr, (*packet.ip).tos) };
+    if dst != marking.ip || tos == marking.tos {
+        return Ok(());
+    }
+    // 非首个分片没有端口，只能按cgroup判断
+    let by_port = match packet.ports(&cursor)? {
+        Some((_, dest)) => marking.port(dest),
+        None => false,
+    };
+    if !by_port && !in_cgroup(ctx, &marking) {
+        return Ok(());
+    }
+    ctx.set_tos(&cursor, &packet, marking.tos)?;
+    count(|stats| stats.marked += 1);
+    Ok(())
+}
+
+/// 报文所属socket的cgroup在标记cgroup之下（含自身），内核自己发出的报文不属于任何cgroup
+#[inline(always)]
+fn in_cgroup(ctx: &TcContext, marking: &Marking) -> bool {
+    if marking.cgroup_id == 0 {
+        return false;
+    }
+    let id = unsafe { bpf_skb_ancestor_cgroup_id(ctx.as_ptr() as _, marking.cgroup_level as _) };
+    id == marking.cgroup_id
+}
+
+/// 按分类规则决定报文的处理方式，服务端口为源端口
+#[inline(always)]
+fn classify(packet: &Ipv4Packet, port: u16) -> Option<Match> {
+    let ip = packet.ip;
+    let (src, dst, tos) = unsafe { ((*ip).src_addr, (*ip).dst_addr, (*ip).tos) };
+    let net =
+        |nets: &LpmTrie<u32, u16>, addr| nets.get(&Key::new(32, addr)).copied().unwrap_or(ANY_NET);
+    classify::lookup(
+        net(&SRC_NETS, src),
+        net(&DST_NETS, dst),
+        packet.proto,
+        port,
+        tos >> 2,
+        |key| unsafe { RULES.get(key) }.copied(),
+    )
+}
+
+/// 在本CPU的计数器上计数，per-CPU数组无需原子操作
+#[inline(always)]
+fn count(f: impl FnOnce(&mut Stats)) {
+    if let Some(stats) = STATS.get_ptr_mut(0) {
+        f(unsafe { &mut *stats });
+    }
+}
+
+/// 读取标记规则，volatile避免编译器把全零初始值常量折叠进程序
+#[inline(always)]
+fn marking() -> Marking {
+    unsafe { core::ptr::read_volatile(&MARKING) }
+}
+
+#[cfg(not(test))]
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
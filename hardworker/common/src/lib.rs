@@ -1 +0,0 @@
-#![no_std]
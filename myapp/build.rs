@@ -0,0 +1,31 @@
+use anyhow::{anyhow, Context as _};
+use aya_build::cargo_metadata;
+use common::{config::DATA_ENV, Data};
+
+/// 嵌入`myapp`的三个ebpf程序
+const EBPF_PACKAGES: [&str; 3] = ["hardworker-ebpf", "logger-ebpf", "sensor-ebpf"];
+
+fn main() -> anyhow::Result<()> {
+    let cargo_metadata::Metadata { packages, .. } = cargo_metadata::MetadataCommand::new()
+        .no_deps()
+        .exec()
+        .context("MetadataCommand::exec")?;
+    let ebpf_packages = EBPF_PACKAGES
+        .iter()
+        .map(|name| {
+            packages
+                .iter()
+                .find(|package| package.name == *name)
+                .cloned()
+                .ok_or_else(|| anyhow!("{name} package not found"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    // 只有[data]编入ebpf程序，经环境变量交给它们的构建脚本，节点等其他改动不会使其重新编译。
+    // 用户态也记下同一份[data]，加载时与运行时的配置核对
+    println!("cargo:rerun-if-changed=../const.toml");
+    let data = Data::from_path("../const.toml").unwrap_or_else(|e| panic!("{e:#}"));
+    std::env::set_var(DATA_ENV, data.to_env());
+    println!("cargo:rustc-env={DATA_ENV}={}", data.to_env());
+    // 每个包的二进制以角色命名，输出到OUT_DIR下
+    aya_build::build_ebpf(ebpf_packages)
+}
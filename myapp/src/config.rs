@@ -0,0 +1,111 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use anyhow::Context as _;
+use clap::Subcommand;
+use common::config::{format_mac, Consts, SCHEMA};
+
+use crate::loader::DEFAULT_CONFIG;
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// 按ebpf程序的规则校验配置，逐条打印错误及原因
+    Check {
+        /// 要校验的配置文件，默认为/etc/myapp/const.toml
+        #[clap(short, long, default_value = DEFAULT_CONFIG)]
+        config: PathBuf,
+    },
+    /// 校验通过后输出规范化的配置
+    Normalize {
+        /// 要规范化的配置文件，默认为/etc/myapp/const.toml
+        #[clap(short, long, default_value = DEFAULT_CONFIG)]
+        config: PathBuf,
+    },
+    /// 输出配置的JSON schema，供编辑器补全与提示
+    Schema,
+}
+
+pub fn run(command: ConfigCommand) -> anyhow::Result<ExitCode> {
+    match command {
+        ConfigCommand::Check { config } => {
+            let Some(consts) = load(&config)? else {
+                return Ok(ExitCode::FAILURE);
+            };
+            println!(
+                "{}校验通过: tos = {:#04x}, port = {}, 负载{}字节, ring buffer {}条, 上报路径{}",
+                config.display(),
+                consts.mark.tos,
+                consts.mark.port,
+                consts.data.load_u64_count * 8,
+                consts.ring.records,
+                consts.hardworker.capture
+            );
+            if consts.hardworker.ack {
+                println!("  hardworker在XDP中回复ACK，负载尽力转发给logger");
+            }
+            if let Some(udp) = &consts.udp {
+                println!(
+                    "  可靠UDP: 端口{}, 超时{}ms, 重传{}次",
+                    udp.port, udp.timeout_ms, udp.retries
+                );
+            }
+            if let Some(store) = &consts.store {
+                print!(
+                    "  日志存储: {}, 每段{}MiB",
+                    store.dir.display(),
+                    store.segment_mb
+                );
+                if store.max_age_hours != 0 {
+                    print!(", 保留{}小时", store.max_age_hours);
+                }
+                if store.max_mb != 0 {
+                    print!(", 总计不超过{}MiB", store.max_mb);
+                }
+                println!();
+            }
+            for node in &consts.nodes {
+                print!(
+                    "  {} ({}): {} {}",
+                    node.name,
+                    node.role,
+                    node.ip,
+                    format_mac(&node.mac)
+                );
+                if let Some(port) = node.port {
+                    print!(" 监听{port}");
+                }
+                if let Some(hardworker) = &node.hardworker {
+                    print!(" -> {hardworker} -> [{}]", node.loggers.join(", "));
+                }
+                println!();
+            }
+        }
+        ConfigCommand::Normalize { config } => {
+            let Some(consts) = load(&config)? else {
+                return Ok(ExitCode::FAILURE);
+            };
+            print!("{}", consts.to_toml());
+        }
+        ConfigCommand::Schema => println!("{SCHEMA}"),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// 读取并校验，校验失败时把所有错误打印到stderr并返回`None`
+fn load(path: &Path) -> anyhow::Result<Option<Consts>> {
+    let src =
+        fs::read_to_string(path).with_context(|| format!("读取配置文件{}失败", path.display()))?;
+    match Consts::parse(&src) {
+        Ok(consts) => Ok(Some(consts)),
+        Err(errors) => {
+            eprintln!("{}校验失败，共{}处错误:", path.display(), errors.0.len());
+            for error in &errors.0 {
+                eprintln!("  {error}");
+            }
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! 常驻进程：为配置中的每个对端在本机网卡上写入永久邻居表项
+//!
+//! 三角中的报文由XDP改写，内核看不到完整的往返，对端的ARP表项会变为`incomplete`。
+//! 这里通过rtnetlink固定这些表项，并订阅邻居表的变更，表项被删除、
+//! 变为非永久状态或MAC被改写时告警并重新固定。
+
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    process::ExitCode,
+};
+
+use anyhow::{anyhow, bail, Context as _};
+use clap::Args;
+use common::config::{format_mac, Role};
+use futures::{StreamExt, TryStreamExt};
+use log::warn;
+use netlink_packet_core::NetlinkPayload;
+use netlink_packet_route::{
+    constants::*,
+    neighbour::{NeighbourMessage, Nla},
+    RtnlMessage,
+};
+use netlink_sys::{AsyncSocket, SocketAddr};
+use rtnetlink::{Handle, IpVersion};
+use tokio::time::{interval_at, Duration, Instant};
+
+use crate::loader::{self, LoaderArgs, Target};
+
+#[derive(Debug, Args)]
+pub struct DaemonArgs {
+    #[clap(flatten)]
+    loader: LoaderArgs,
+    /// 本机的角色：sensor、hardworker或logger，省略时按本机网卡识别
+    #[clap(short, long)]
+    role: Option<Role>,
+    /// 完整核对一次邻居表的间隔秒数，补上可能丢失的变更通知
+    #[clap(long, default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+    resync_secs: u64,
+}
+
+/// 要固定的一个对端
+struct Peer {
+    name: String,
+    ip: Ipv4Addr,
+    mac: [u8; 6],
+}
+
+/// 固定本机各对端的邻居表项，直到收到Ctrl-C或SIGTERM
+pub fn run(args: DaemonArgs) -> anyhow::Result<ExitCode> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(daemon(&args))?;
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn daemon(args: &DaemonArgs) -> anyhow::Result<()> {
+    let Target {
+        consts,
+        node,
+        iface,
+    } = args.loader.target(args.role).await?;
+    let peers: Vec<Peer> = consts
+        .peers(&node)
+        .into_iter()
+        .map(|peer| Peer {
+            name: peer.name.clone(),
+            ip: peer.ip,
+            mac: peer.mac,
+        })
+        .collect();
+    if peers.is_empty() {
+        bail!("{}在配置中没有对端，无需固定", node.name);
+    }
+    watch(&iface, args.resync_secs, &node.name, &peers).await
+}
+
+async fn watch(iface: &str, resync_secs: u64, name: &str, peers: &[Peer]) -> anyhow::Result<()> {
+    let (mut connection, handle, mut messages) =
+        rtnetlink::new_connection().context("打开rtnetlink套接字失败")?;
+    // 订阅邻居表的变更
+    connection
+        .socket_mut()
+        .socket_mut()
+        .bind(&SocketAddr::new(0, 1 << (RTNLGRP_NEIGH - 1)))
+        .context("订阅邻居表变更失败")?;
+    tokio::spawn(connection);
+
+    let index = link_index(&handle, iface).await?;
+    println!(
+        "{name}在{iface}上固定{}个对端，每{resync_secs}秒核对一次",
+        peers.len()
+    );
+    let mut repins = 0u64;
+    for peer in peers {
+        pin(&handle, index, peer).await;
+    }
+
+    let period = Duration::from_secs(resync_secs);
+    let mut resync = interval_at(Instant::now() + period, period);
+    let mut terminated = std::pin::pin!(loader::terminated());
+    loop {
+        tokio::select! {
+            result = &mut terminated => {
+                result?;
+                break;
+            }
+            _ = resync.tick() => {
+                let entries = dump(&handle, index).await?;
+                for peer in peers {
+                    let entry = entries.iter().find(|entry| entry.ip == Some(peer.ip));
+                    if let Some(problem) = problem(peer, entry) {
+                        warn!("核对时发现{} {}{problem}，重新固定", peer.name, peer.ip);
+                        pin(&handle, index, peer).await;
+                        repins += 1;
+                    }
+                }
+            }
+            message = messages.next() => {
+                let Some((message, _)) = message else {
+                    bail!("rtnetlink连接已关闭");
+                };
+                let (entry, deleted) = match message.payload {
+                    NetlinkPayload::InnerMessage(RtnlMessage::NewNeighbour(msg)) => (Entry::from(msg), false),
+                    NetlinkPayload::InnerMessage(RtnlMessage::DelNeighbour(msg)) => (Entry::from(msg), true),
+                    _ => continue,
+                };
+                if entry.index != index {
+                    continue;
+                }
+                let Some(peer) = peers.iter().find(|peer| entry.ip == Some(peer.ip)) else {
+                    continue;
+                };
+                if let Some(problem) = problem(peer, (!deleted).then_some(&entry)) {
+                    warn!("{} {}{problem}，重新固定", peer.name, peer.ip);
+                    pin(&handle, index, peer).await;
+                    repins += 1;
+                }
+            }
+        }
+    }
+    println!("退出，共重新固定{repins}次，永久表项保留");
+    Ok(())
+}
+
+/// 按名字找到网卡的索引
+async fn link_index(handle: &Handle, iface: &str) -> anyhow::Result<u32> {
+    let link = handle
+        .link()
+        .get()
+        .match_name(iface.to_string())
+        .execute()
+        .try_next()
+        .await
+        .with_context(|| format!("找不到网卡{iface}"))?
+        .ok_or_else(|| anyhow!("找不到网卡{iface}"))?;
+    Ok(link.header.index)
+}
+
+/// 写入或覆盖对端的永久表项，失败只告警，等下次核对时重试
+async fn pin(handle: &Handle, index: u32, peer: &Peer) {
+    let result = handle
+        .neighbours()
+        .add(index, IpAddr::V4(peer.ip))
+        .link_local_address(&peer.mac)
+        .state(NUD_PERMANENT)
+        .replace()
+        .execute()
+        .await;
+    match result {
+        Ok(()) => println!("固定{} {} -> {}", peer.name, peer.ip, format_mac(&peer.mac)),
+        Err(e) => warn!("固定{} {}失败: {e}", peer.name, peer.ip),
+    }
+}
+
+/// 读出网卡上全部IPv4邻居表项
+async fn dump(handle: &Handle, index: u32) -> anyhow::Result<Vec<Entry>> {
+    let entries: Vec<NeighbourMessage> = handle
+        .neighbours()
+        .get()
+        .set_family(IpVersion::V4)
+        .execute()
+        .try_collect()
+        .await
+        .context("读取邻居表失败")?;
+    Ok(entries
+        .into_iter()
+        .map(Entry::from)
+        .filter(|entry| entry.index == index)
+        .collect())
+}
+
+/// 邻居表项中关心的部分
+struct Entry {
+    index: u32,
+    state: u16,
+    ip: Option<Ipv4Addr>,
+    mac: Option<Vec<u8>>,
+}
+
+impl From<NeighbourMessage> for Entry {
+    fn from(msg: NeighbourMessage) -> Self {
+        let mut entry = Entry {
+            index: msg.header.ifindex,
+            state: msg.header.state,
+            ip: None,
+            mac: None,
+        };
+        if msg.header.family as u16 != AF_INET {
+            return entry;
+        }
+        for nla in msg.nlas {
+            match nla {
+                Nla::Destination(ip) => {
+                    entry.ip = <[u8; 4]>::try_from(ip.as_slice()).ok().map(Ipv4Addr::from)
+                }
+                Nla::LinkLocalAddress(mac) => entry.mac = Some(mac),
+                _ => {}
+            }
+        }
+        entry
+    }
+}
+
+/// 表项与对端不符的原因，缺少表项时`entry`为`None`
+fn problem(peer: &Peer, entry: Option<&Entry>) -> Option<String> {
+    let Some(entry) = entry else {
+        return Some("的表项不存在".to_string());
+    };
+    if entry.state & NUD_PERMANENT == 0 {
+        return Some(format!("的表项变为{}", state_name(entry.state)));
+    }
+    match &entry.mac {
+        Some(mac) => match <[u8; 6]>::try_from(mac.as_slice()) {
+            Ok(mac) if mac == peer.mac => None,
+            Ok(mac) => Some(format!("的MAC被改为{}", format_mac(&mac))),
+            Err(_) => Some(format!("的MAC长度为{}字节", mac.len())),
+        },
+        None => Some("的表项没有MAC".to_string()),
+    }
+}
+
+/// 与`ip neigh`相同的状态名
+fn state_name(state: u16) -> &'static str {
+    match state {
+        NUD_INCOMPLETE => "INCOMPLETE",
+        NUD_REACHABLE => "REACHABLE",
+        NUD_STALE => "STALE",
+        NUD_DELAY => "DELAY",
+        NUD_PROBE => "PROBE",
+        NUD_FAILED => "FAILED",
+        NUD_NOARP => "NOARP",
+        NUD_NONE => "NONE",
+        _ => "未知状态",
+    }
+}
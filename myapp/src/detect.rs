@@ -0,0 +1,167 @@
+//! 按本机网卡识别节点
+//!
+//! 通过rtnetlink列出本机网卡的MAC与IPv4地址，与配置中各节点的`mac`、`ip`比对，
+//! 恰好匹配到一个节点与网卡时才采用，否则要求用`--role`、`--node`或`--iface`指定。
+
+use std::net::Ipv4Addr;
+
+use anyhow::{bail, Context as _};
+use common::config::{format_mac, Consts, Node, Role};
+use futures::TryStreamExt;
+use log::warn;
+use netlink_packet_route::{
+    address::nlas::Nla as AddressNla, constants::AF_INET, link::nlas::Nla as LinkNla, LinkMessage,
+};
+
+/// 本机的一个网卡
+#[derive(Debug)]
+pub struct Link {
+    pub name: String,
+    pub index: u32,
+    pub mac: Option<[u8; 6]>,
+    pub ips: Vec<Ipv4Addr>,
+}
+
+impl Link {
+    /// 网卡的MAC或任一IPv4地址与节点相同
+    fn matches(&self, node: &Node) -> bool {
+        self.mac == Some(node.mac) || self.ips.contains(&node.ip)
+    }
+}
+
+impl From<LinkMessage> for Link {
+    fn from(msg: LinkMessage) -> Self {
+        let mut link = Link {
+            name: String::new(),
+            index: msg.header.index,
+            mac: None,
+            ips: Vec::new(),
+        };
+        for nla in msg.nlas {
+            match nla {
+                LinkNla::IfName(name) => link.name = name,
+                LinkNla::Address(mac) => link.mac = <[u8; 6]>::try_from(mac.as_slice()).ok(),
+                _ => {}
+            }
+        }
+        link
+    }
+}
+
+/// 列出本机全部网卡及其IPv4地址
+pub async fn links() -> anyhow::Result<Vec<Link>> {
+    let (connection, handle, _) = rtnetlink::new_connection().context("打开rtnetlink套接字失败")?;
+    tokio::spawn(connection);
+
+    let mut links: Vec<Link> = handle
+        .link()
+        .get()
+        .execute()
+        .map_ok(Link::from)
+        .try_collect()
+        .await
+        .context("读取网卡列表失败")?;
+    let mut addresses = handle.address().get().execute();
+    while let Some(msg) = addresses.try_next().await.context("读取网卡地址失败")? {
+        if msg.header.family as u16 != AF_INET {
+            continue;
+        }
+        let Some(link) = links.iter_mut().find(|link| link.index == msg.header.index) else {
+            continue;
+        };
+        for nla in msg.nlas {
+            if let AddressNla::Local(ip) = nla {
+                if let Ok(ip) = <[u8; 4]>::try_from(ip.as_slice()) {
+                    link.ips.push(Ipv4Addr::from(ip));
+                }
+            }
+        }
+    }
+    Ok(links)
+}
+
+/// 确定本机对应的节点与要挂载的网卡
+///
+/// 指定了网卡且筛选后只剩一个节点时直接采用，网卡与节点不符只告警，地址可能在bond或网桥等
+/// 其他设备上；否则在（指定的）网卡中找MAC或IP与节点相同的，要求恰好一对
+pub async fn detect(
+    consts: &Consts,
+    role: Option<Role>,
+    name: Option<&str>,
+    iface: Option<&str>,
+) -> anyhow::Result<(Node, String)> {
+    let nodes = consts.candidates(role, name)?;
+    if let (Some(iface), [node]) = (iface, nodes.as_slice()) {
+        if let Ok(links) = links().await {
+            if let Some(link) = links.iter().find(|link| link.name == iface) {
+                if !link.matches(node) {
+                    warn!(
+                        "网卡{iface} ({})与节点{}的MAC {}和IP {}都不符，仍按指定挂载",
+                        link.mac.as_ref().map_or("无MAC".to_string(), format_mac),
+                        node.name,
+                        format_mac(&node.mac),
+                        node.ip
+                    );
+                }
+            }
+        }
+        return Ok(((*node).clone(), iface.to_string()));
+    }
+
+    let links = links().await?;
+    let links: Vec<&Link> = links
+        .iter()
+        .filter(|link| iface.is_none_or(|iface| link.name == iface))
+        .collect();
+    if let (Some(iface), true) = (iface, links.is_empty()) {
+        bail!("找不到网卡{iface}");
+    }
+    let matches: Vec<(&Node, &Link)> = nodes
+        .iter()
+        .flat_map(|node| {
+            links
+                .iter()
+                .filter(|link| link.matches(node))
+                .map(move |link| (*node, *link))
+        })
+        .collect();
+    match matches.as_slice() {
+        [(node, link)] => {
+            println!(
+                "按{} ({})识别为节点{} ({})",
+                link.name,
+                link.mac.as_ref().map_or("无MAC".to_string(), format_mac),
+                node.name,
+                node.role
+            );
+            Ok(((*node).clone(), link.name.clone()))
+        }
+        [] => {
+            // 只提示还没给出的选项
+            let mut missing = Vec::new();
+            if nodes.len() > 1 {
+                missing.push(if role.is_some() {
+                    "--node"
+                } else {
+                    "--role或--node"
+                });
+            }
+            if iface.is_none() {
+                missing.push("--iface");
+            }
+            bail!(
+                "本机网卡与配置中的{}都不匹配，需要用{}指定",
+                role.map_or("节点".to_string(), |role| format!("{role}节点")),
+                missing.join("与")
+            )
+        }
+        _ => bail!(
+            "本机匹配到多个节点: {}，需要用--role、--node或--iface指定",
+            matches
+                .iter()
+                .map(|(node, link)| format!("{}@{}", node.name, link.name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
@@ -0,0 +1,576 @@
+use std::{
+    net::{SocketAddrV4, UdpSocket},
+    os::fd::AsRawFd,
+    process::ExitCode,
+    sync::Arc,
+};
+
+use anyhow::Context as _;
+use aya::maps::{HashMap, MapData, PerCpuArray, RingBuf, XskMap};
+use clap::Args;
+use common::{
+    config::Role,
+    csum,
+    flow::{Flow, FlowKey},
+    packet::{Cursor, IPPROTO_TCP, TCP_FIN, TCP_RST, TCP_SYN},
+    record::{Record, RecordHeader},
+    rudp::{RudpKey, Window},
+    Config, Peer, RingStats, Route, MAX_XSK_QUEUES,
+};
+#[rustfmt::skip]
+use log::{debug, warn};
+use tokio::{
+    io::unix::AsyncFd,
+    time::{sleep, Duration},
+};
+
+use crate::loader::{self, AttachArgs, Target, XdpMode};
+use xsk::XskSocket;
+
+mod xsk;
+
+/// 用户态回复的ACK通告的窗口，与XDP中一致
+const ACK_WINDOW: u16 = u16::MAX;
+/// AF_XDP模式下有帧在发送时，回收完成环的间隔
+const XSK_RECYCLE_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(Debug, Args)]
+pub struct HardworkerArgs {
+    #[clap(flatten)]
+    attach: AttachArgs,
+}
+
+pub fn run(args: HardworkerArgs) -> anyhow::Result<ExitCode> {
+    loader::block_on(async {
+        let target = args.attach.target(Some(Role::Hardworker)).await?;
+        hardworker(target, args.attach.xdp_mode).await
+    })
+}
+
+pub async fn hardworker(target: Target, xdp_mode: XdpMode) -> anyhow::Result<()> {
+    let Target {
+        consts,
+        node,
+        iface,
+    } = target;
+    let node = &node;
+    let config = Config::from(&consts);
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let ring_bytes = consts.ring_byte_size(page_size);
+
+    let mut ebpf = loader::load(Role::Hardworker, |builder| {
+        builder
+            .set_global("CONFIG", &config, true)
+            .set_max_entries("TARGET_MAP", ring_bytes);
+    })?;
+    let node_routes = loader::install(&mut ebpf, &consts, node)?;
+    loader::attach(&mut ebpf, Role::Hardworker, &iface, node.hook, xdp_mode)?;
+
+    let stats = loader::take_stats(&mut ebpf)?;
+    let flows: HashMap<MapData, FlowKey, Flow> =
+        HashMap::try_from(loader::take_map(&mut ebpf, "FLOWS")?)?;
+    let flows = Arc::new(flows);
+    let peers: HashMap<MapData, RudpKey, Window> =
+        HashMap::try_from(loader::take_map(&mut ebpf, "RUDP_PEERS")?)?;
+    let peers = Arc::new(peers);
+    // 收到SIGUSR1时汇总打印各CPU的计数、各条流与各个UDP sender的状态
+    loader::on_sigusr1({
+        let (stats, flows, peers) = (stats.clone(), flows.clone(), peers.clone());
+        move || {
+            loader::print_stats(&stats);
+            print_flows(&flows);
+            print_peers(&peers);
+        }
+    })?;
+
+    println!(
+        "ring buffer {}字节，至少容纳{}条{}字节的记录",
+        ring_bytes,
+        consts.ring.records,
+        consts.record_size()
+    );
+    let ring_stats: PerCpuArray<MapData, RingStats> =
+        PerCpuArray::try_from(loader::take_map(&mut ebpf, "RING_STATS")?)?;
+
+    if config.ack() {
+        println!("ACK模式：数据段由XDP确认，记录尽力转发给logger");
+    }
+    if let Some(udp) = &consts.udp {
+        println!(
+            "可靠UDP：端口{}的数据报由XDP去重并确认，记录尽力转发给logger",
+            udp.port
+        );
+    }
+    // 两种模式下logger都收不到原始报文
+    let forwarder = if config.ack() || consts.udp.is_some() {
+        Some(Arc::new(Forwarder::new(&node_routes, config.ack())?))
+    } else {
+        None
+    };
+
+    // AF_XDP模式下ring buffer仍然保留，接收没有socket的队列与XDP自己处理的报文
+    let (xsk_shutdown, xsk_rx) = tokio::sync::watch::channel(false);
+    let mut xsk_tasks = Vec::new();
+    if config.xsk() {
+        let mut xsks = XskMap::try_from(
+            ebpf.map_mut("XSKS")
+                .context("找不到XSKS，考虑ebpf程序未正常加载")?,
+        )?;
+        let ifindex =
+            unsafe { libc::if_nametoindex(std::ffi::CString::new(iface.as_str())?.as_ptr()) };
+        if ifindex == 0 {
+            return Err(std::io::Error::last_os_error()).context(format!("找不到网卡{iface}"));
+        }
+        let queues = xsk::rx_queues(&iface)
+            .with_context(|| format!("读取{iface}的队列数失败"))?
+            .min(MAX_XSK_QUEUES);
+        for queue in 0..queues {
+            let socket = XskSocket::bind(ifindex, queue)
+                .with_context(|| format!("在{iface}的队列{queue}上绑定AF_XDP socket失败"))?;
+            xsks.set(queue, socket.as_raw_fd(), 0)?;
+            println!(
+                "AF_XDP：队列{queue}以{}模式绑定",
+                if socket.zero_copy {
+                    "零拷贝"
+                } else {
+                    "拷贝"
+                }
+            );
+            let frames = Frames {
+                routes: node_routes.iter().map(|(_, route)| *route).collect(),
+                forwarder: forwarder.clone(),
+                ack: config.ack(),
+                ifindex,
+                queue,
+                data_size: consts.data.load_u64_count * 8,
+                counters: FrameCounters::default(),
+            };
+            xsk_tasks.push(tokio::spawn(run_xsk(socket, frames, xsk_rx.clone())));
+        }
+    }
+
+    let (shutdown, rx) = tokio::sync::oneshot::channel();
+
+    let handle = tokio::task::spawn(async move {
+        println!("工作线程TID: {}", unsafe {
+            libc::syscall(libc::SYS_gettid)
+        });
+
+        let ring_buffer = RingBuf::try_from(
+            ebpf.map_mut("TARGET_MAP")
+                .expect("找不到Map，考虑ebpf程序未正常加载"),
+        )
+        .expect("无法使用Map");
+        let mut poll = AsyncFd::new(ring_buffer).expect("创建AsyncFd失败");
+
+        #[derive(Debug, Default, Clone, Copy)]
+        struct FaillType {
+            match_fail: u64,
+            decode_fail: u64,
+            guard_fail: u64,
+            forward_fail: u64,
+        }
+
+        let (tx_success, rx_success) = tokio::sync::watch::channel(0 as u64);
+        let (tx_fail, rx_fail) = tokio::sync::watch::channel(FaillType::default());
+
+        let mut handle = async move || {
+            let mut success = 0 as u64;
+            let mut fail = FaillType::default();
+            let mut data: Vec<u8> = Vec::new();
+            loop {
+                while let Ok(mut guard) = poll.readable_mut().await {
+                    if let Some(new_data) = guard.get_inner_mut().next() {
+                        let record =
+                            Record::decode(&new_data).map(|r| (r.header, r.payload.to_vec()));
+                        if let (Some(forwarder), Ok((header, _))) = (&forwarder, &record) {
+                            if !forwarder.send(header, &new_data) {
+                                fail.forward_fail += 1;
+                                tx_fail.send(fail).expect("发送失败次数失败，考虑外部干预");
+                            }
+                        }
+                        drop(new_data);
+                        match record {
+                            Ok((header, val)) => {
+                                debug!(
+                                    "{} -> {} seq {} ifindex {} queue {} at {}ns, {}/{}字节",
+                                    header.source(),
+                                    header.destination(),
+                                    header.seq,
+                                    header.ifindex,
+                                    header.rx_queue,
+                                    header.timestamp_ns,
+                                    header.len,
+                                    header.payload_len
+                                );
+                                if data.is_empty() {
+                                    data = val;
+                                    success += 1;
+                                    tx_success
+                                        .send(success)
+                                        .expect("发送成功次数失败，考虑外部干预");
+                                    println!("工作线程第一次成功");
+                                    // Print the data in hexdump format
+                                    for (i, chunk) in data.chunks(16).enumerate() {
+                                        // Print the offset
+                                        print!("{:08x}  ", i * 16);
+
+                                        // Print hex values
+                                        for &byte in chunk {
+                                            print!("{:02x} ", byte);
+                                        }
+
+                                        // Add padding if needed
+                                        for _ in 0..(16 - chunk.len()) {
+                                            print!("   ");
+                                        }
+
+                                        // Print ASCII representation
+                                        print!(" |");
+                                        for &byte in chunk {
+                                            let c = if byte >= 32 && byte <= 126 {
+                                                byte as char
+                                            } else {
+                                                '.'
+                                            };
+                                            print!("{}", c);
+                                        }
+                                        println!("|");
+                                    }
+                                } else {
+                                    // Check if all bytes match (full comparison)
+                                    if data == val {
+                                        success += 1;
+                                        tx_success
+                                            .send(success)
+                                            .expect("发送成功次数失败，考虑外部干预");
+                                    } else {
+                                        fail.match_fail += 1;
+                                        tx_fail.send(fail).expect("发送失败次数失败，考虑外部干预");
+                                    }
+                                    // if data[0] == val[0] {
+                                    //     // 模糊匹配，我简单认为没必要每个字节都一样
+                                    //     success += 1;
+                                    //     tx_success
+                                    //         .send(success)
+                                    //         .expect("发送成功次数失败，考虑外部干预");
+                                    // } else {
+                                    //     fail.match_fail += 1;
+                                    //     tx_fail.send(fail).expect("发送失败次数失败，考虑外部干预");
+                                    // }
+                                }
+                            }
+                            Err(e) => {
+                                debug!("丢弃无法解析的记录: {}", e);
+                                fail.decode_fail += 1;
+                                tx_fail.send(fail).expect("发送失败次数失败，考虑外部干预");
+                            }
+                        }
+                    } else {
+                        fail.guard_fail += 1;
+                        tx_fail.send(fail).expect("发送失败次数失败，考虑外部干预");
+                        tokio::task::yield_now().await;
+                    }
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = rx => {
+                let (success, fail) = (*rx_success.borrow(), *rx_fail.borrow());
+                println!("成功次数: {}, 失败次数: {:?}", success, fail);
+                match loader::read_ring_stats(&ring_stats) {
+                    Ok(stats) => println!(
+                        "ring buffer写入: {}, 丢弃: {}, 待消费: 最近{} 峰值{}/{}字节",
+                        stats.submitted, stats.dropped, stats.avail_last, stats.avail_max, ring_bytes
+                    ),
+                    Err(e) => warn!("读取RING_STATS失败: {}", e),
+                }
+            }
+            _ = handle() => {
+                println!("工作线程居然退出，考虑外部干预");
+            }
+        };
+    });
+
+    loader::wait_for_shutdown().await?;
+    shutdown
+        .send(())
+        .expect("发送关闭信号失败，考虑子线程出错或外部干预，考虑sudo kill主线程");
+
+    let _ = handle.await;
+    let _ = xsk_shutdown.send(true);
+    for task in xsk_tasks {
+        let _ = task.await;
+    }
+    loader::print_stats(&stats);
+    print_flows(&flows);
+    print_peers(&peers);
+
+    Ok(())
+}
+
+/// 驱动一个AF_XDP socket直到退出，退出时打印本队列的统计
+async fn run_xsk(
+    socket: XskSocket,
+    mut frames: Frames,
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+) {
+    let mut fd = AsyncFd::new(socket).expect("创建AsyncFd失败");
+    loop {
+        let pending = fd.get_ref().outstanding() > 0;
+        tokio::select! {
+            _ = shutdown.changed() => break,
+            guard = fd.readable_mut() => {
+                // 先清除再取帧，取帧期间到达的帧会再次触发可读
+                guard.expect("等待AF_XDP socket失败").clear_ready();
+            }
+            // 没有新帧时也要回收发送完成的帧
+            _ = sleep(XSK_RECYCLE_INTERVAL), if pending => {}
+        }
+        if let Err(e) = fd.get_mut().process(|frame| frames.handle(frame)) {
+            warn!("队列{}处理AF_XDP帧失败: {}", frames.queue, e);
+        }
+    }
+    let c = frames.counters;
+    println!(
+        "AF_XDP队列{}: 帧{}, 上报{}, ACK{}, 转发{}, 丢弃{}, 转发记录失败{}",
+        frames.queue, c.frames, c.records, c.acked, c.rewritten, c.dropped, c.forward_fail
+    );
+    match fd.get_ref().statistics() {
+        Ok(stats) => println!(
+            "  内核: 丢弃{}, RX环满{}, 填充环空{}, 非法描述符{}/{}",
+            stats.rx_dropped,
+            stats.rx_ring_full,
+            stats.rx_fill_ring_empty_descs,
+            stats.rx_invalid_descs,
+            stats.tx_invalid_descs
+        ),
+        Err(e) => warn!("读取AF_XDP统计失败: {}", e),
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct FrameCounters {
+    frames: u64,
+    records: u64,
+    acked: u64,
+    rewritten: u64,
+    /// 解析失败或找不到logger而直接回收的帧
+    dropped: u64,
+    forward_fail: u64,
+}
+
+/// AF_XDP模式下逐帧处理：在UMEM中原地取出负载上报，再完成XDP本来要做的ACK或转发
+struct Frames {
+    routes: Vec<Route>,
+    forwarder: Option<Arc<Forwarder>>,
+    ack: bool,
+    ifindex: u32,
+    queue: u32,
+    data_size: usize,
+    counters: FrameCounters,
+}
+
+impl Frames {
+    /// 返回要发出的帧长度，`None`表示丢弃
+    fn handle(&mut self, frame: &mut [u8]) -> Option<usize> {
+        self.counters.frames += 1;
+        let ret = self.rewrite(frame);
+        if ret.is_none() {
+            self.counters.dropped += 1;
+        }
+        ret
+    }
+
+    fn rewrite(&mut self, frame: &mut [u8]) -> Option<usize> {
+        let start = frame.as_mut_ptr() as usize;
+        let cursor = Cursor::new(start, start + frame.len());
+        let packet = cursor.ipv4().ok()??;
+        let tcp = packet.tcp(&cursor).ok()??;
+        let payload = tcp.payload(&cursor)?;
+        let (ip, hdr) = (packet.ip, tcp.hdr);
+
+        let mut header = RecordHeader::zeroed();
+        let seq = unsafe {
+            header.src_ip = u32::from_be((*ip).src_addr);
+            header.dst_ip = u32::from_be((*ip).dst_addr);
+            header.src_port = u16::from_be((*hdr).source);
+            header.dst_port = u16::from_be((*hdr).dest);
+            u32::from_be((*hdr).seq)
+        };
+        let len = payload.len().min(self.data_size);
+        header.timestamp_ns = monotonic_ns();
+        header.seq = seq;
+        header.ifindex = self.ifindex;
+        header.rx_queue = self.queue;
+        header.len = len as u32;
+        header.payload_len = payload.len() as u32;
+        header.proto = packet.proto;
+        self.counters.records += 1;
+        if let Some(forwarder) = &self.forwarder {
+            if !forwarder.send_parts(&header, &payload[..len]) {
+                self.counters.forward_fail += 1;
+            }
+        }
+
+        // 与XDP中的规则一致，建立与关闭连接的段仍交给logger。
+        // XDP只把连续收到位置正好在段尾的数据段交过来，确认到段尾不会越过缺口
+        if self.ack && tcp.flags & (TCP_SYN | TCP_FIN | TCP_RST) == 0 {
+            let ack = seq.wrapping_add(tcp.payload_len as u32);
+            if let Some(len) = tcp.into_ack(&packet, ack, ACK_WINDOW) {
+                self.counters.acked += 1;
+                return Some(len);
+            }
+        }
+
+        let route = self
+            .routes
+            .iter()
+            .find(|route| route.sensor.ip == header.src_ip)?;
+        let logger = route.logger(header.src_port)?;
+        unsafe {
+            (*packet.eth).src_addr = route.hardworker.mac;
+            (*packet.eth).dst_addr = logger.mac;
+            let old_ip = (*ip).dst_addr;
+            let new_ip = logger.ip.to_be();
+            (*ip).dst_addr = new_ip;
+            (*ip).check = csum::replace32((*ip).check, old_ip, new_ip);
+            (*hdr).check = csum::replace32((*hdr).check, old_ip, new_ip);
+        }
+        self.counters.rewritten += 1;
+        Some(frame.len())
+    }
+}
+
+/// 与`bpf_ktime_get_ns`同一时钟，两条路径的记录时间戳可以直接比较
+fn monotonic_ns() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+/// 逐条打印`FLOWS`中的流状态
+fn print_flows(flows: &HashMap<MapData, FlowKey, Flow>) {
+    for entry in flows.iter() {
+        match entry {
+            Ok((key, flow)) => println!(
+                "{} -> {}: 段{}, 缺口{}, 乱序{}, 重传{}, 期望seq {}, 连续收到{}, 最近ack {}, 未补齐{}字节",
+                key.source(),
+                key.destination(),
+                flow.segments,
+                flow.gaps,
+                flow.reordered,
+                flow.retransmits,
+                flow.next_seq,
+                flow.rcv_nxt,
+                flow.last_ack,
+                flow.hole()
+            ),
+            Err(e) => {
+                warn!("读取FLOWS失败: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+/// 逐个打印`RUDP_PEERS`中sender的去重状态
+fn print_peers(peers: &HashMap<MapData, RudpKey, Window>) {
+    for entry in peers.iter() {
+        match entry {
+            Ok((key, window)) => println!(
+                "UDP sender {}#{}: 上报{}, 重复{}, 最大seq {}",
+                key.source(),
+                key.sensor,
+                window.accepted,
+                window.duplicates,
+                window.highest
+            ),
+            Err(e) => {
+                warn!("读取RUDP_PEERS失败: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+/// ACK模式与可靠UDP下把记录尽力转发给logger
+///
+/// XDP已经确认了数据，logger收不到原始报文，这里用UDP把整条记录发到logger监听的端口，
+/// 由logger写入同一个日志存储，丢失不会影响sensor
+struct Forwarder {
+    socket: UdpSocket,
+    routes: Vec<Route>,
+    /// 只有ACK模式下TCP段不会到达logger，否则logger自己会记录
+    tcp: bool,
+}
+
+impl Forwarder {
+    fn new(routes: &[(u32, Route)], tcp: bool) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0").context("创建转发socket失败")?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            routes: routes.iter().map(|(_, route)| *route).collect(),
+            tcp,
+        })
+    }
+
+    /// 按记录的sensor与源端口选出logger并发送，失败时返回false
+    fn send(&self, header: &RecordHeader, record: &[u8]) -> bool {
+        if !self.wants(header) {
+            return true;
+        }
+        let Some(logger) = self.logger(header) else {
+            return false;
+        };
+        let addr = SocketAddrV4::new(logger.ip.into(), logger.port);
+        self.socket.send_to(record, addr).is_ok()
+    }
+
+    /// 记录头与负载分开给出，负载直接从UMEM发送而不先拼成一条记录
+    fn send_parts(&self, header: &RecordHeader, payload: &[u8]) -> bool {
+        if !self.wants(header) {
+            return true;
+        }
+        let Some(logger) = self.logger(header) else {
+            return false;
+        };
+        let mut addr: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+        addr.sin_family = libc::AF_INET as libc::sa_family_t;
+        addr.sin_port = logger.port.to_be();
+        addr.sin_addr.s_addr = logger.ip.to_be();
+        let mut iov = [
+            libc::iovec {
+                iov_base: header.as_bytes().as_ptr() as *mut libc::c_void,
+                iov_len: RecordHeader::LEN,
+            },
+            libc::iovec {
+                iov_base: payload.as_ptr() as *mut libc::c_void,
+                iov_len: payload.len(),
+            },
+        ];
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_name = &mut addr as *mut _ as *mut libc::c_void;
+        msg.msg_namelen = std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+        msg.msg_iov = iov.as_mut_ptr();
+        msg.msg_iovlen = iov.len() as _;
+        unsafe { libc::sendmsg(self.socket.as_raw_fd(), &msg, 0) >= 0 }
+    }
+
+    /// logger收不到原始报文的记录才需要转发
+    fn wants(&self, header: &RecordHeader) -> bool {
+        self.tcp || header.proto != IPPROTO_TCP
+    }
+
+    fn logger(&self, header: &RecordHeader) -> Option<&Peer> {
+        self.routes
+            .iter()
+            .find(|route| route.sensor.ip == header.src_ip)
+            .and_then(|route| route.logger(header.src_port))
+    }
+}
@@ -0,0 +1,462 @@
+//! AF_XDP收包路径
+//!
+//! 每个收包队列一个socket，各自独占一块UMEM。XDP重定向来的帧直接在UMEM中处理，
+//! 需要发出的帧原地改写后放进TX环，发送完成后经完成环回到填充环。
+//! 先尝试零拷贝绑定，驱动不支持（如veth）时退回拷贝模式
+
+use std::{
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    ptr, slice,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+// linux/if_xdp.h，libc 0.2.159还没有这些定义
+const SOL_XDP: libc::c_int = 283;
+const XDP_MMAP_OFFSETS: libc::c_int = 1;
+const XDP_RX_RING: libc::c_int = 2;
+const XDP_TX_RING: libc::c_int = 3;
+const XDP_UMEM_REG: libc::c_int = 4;
+const XDP_UMEM_FILL_RING: libc::c_int = 5;
+const XDP_UMEM_COMPLETION_RING: libc::c_int = 6;
+const XDP_STATISTICS: libc::c_int = 7;
+const XDP_COPY: u16 = 1 << 1;
+const XDP_ZEROCOPY: u16 = 1 << 2;
+const XDP_PGOFF_RX_RING: libc::off_t = 0;
+const XDP_PGOFF_TX_RING: libc::off_t = 0x80000000;
+const XDP_UMEM_PGOFF_FILL_RING: libc::off_t = 0x100000000;
+const XDP_UMEM_PGOFF_COMPLETION_RING: libc::off_t = 0x180000000;
+
+/// 每帧的字节数，需要放得下一个MTU的以太网帧
+const FRAME_SIZE: usize = 2048;
+/// 每个socket的帧数，四个环也都用这个大小，填充环永远不会满
+const FRAME_COUNT: u32 = 4096;
+
+#[repr(C)]
+struct SockaddrXdp {
+    family: u16,
+    flags: u16,
+    ifindex: u32,
+    queue_id: u32,
+    shared_umem_fd: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct RingOffset {
+    producer: u64,
+    consumer: u64,
+    desc: u64,
+    flags: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct MmapOffsets {
+    rx: RingOffset,
+    tx: RingOffset,
+    fr: RingOffset,
+    cr: RingOffset,
+}
+
+#[repr(C)]
+struct UmemReg {
+    addr: u64,
+    len: u64,
+    chunk_size: u32,
+    headroom: u32,
+    flags: u32,
+    tx_metadata_len: u32,
+}
+
+/// RX与TX环中的描述符，`addr`是帧在UMEM中的偏移
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct XdpDesc {
+    addr: u64,
+    len: u32,
+    options: u32,
+}
+
+/// 内核对一个socket的统计
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct XdpStatistics {
+    /// RX环满以外的原因丢弃的帧
+    pub rx_dropped: u64,
+    pub rx_invalid_descs: u64,
+    pub tx_invalid_descs: u64,
+    /// RX环满丢弃的帧
+    pub rx_ring_full: u64,
+    /// 填充环为空的次数
+    pub rx_fill_ring_empty_descs: u64,
+    pub tx_ring_empty_descs: u64,
+}
+
+/// 与内核共享的单生产者单消费者环
+///
+/// 本端是生产者时只推进`producer`，是消费者时只推进`consumer`，
+/// 另一端的位置缓存在本地，用完了才重新读取
+struct Ring<T> {
+    map: *mut libc::c_void,
+    map_len: usize,
+    producer: *const AtomicU32,
+    consumer: *const AtomicU32,
+    descs: *mut T,
+    size: u32,
+    cached_producer: u32,
+    cached_consumer: u32,
+}
+
+impl<T: Copy> Ring<T> {
+    fn map(fd: RawFd, offset: &RingOffset, size: u32, pgoff: libc::off_t) -> io::Result<Self> {
+        let map_len = offset.desc as usize + size as usize * size_of::<T>();
+        let map = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                map_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                pgoff,
+            )
+        };
+        if map == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let at = |offset: u64| unsafe { map.cast::<u8>().add(offset as usize) };
+        let producer = at(offset.producer) as *const AtomicU32;
+        let consumer = at(offset.consumer) as *const AtomicU32;
+        Ok(Self {
+            map,
+            map_len,
+            producer,
+            consumer,
+            descs: at(offset.desc) as *mut T,
+            size,
+            cached_producer: unsafe { (*producer).load(Ordering::Relaxed) },
+            cached_consumer: unsafe { (*consumer).load(Ordering::Relaxed) },
+        })
+    }
+
+    fn slot(&self, index: u32) -> *mut T {
+        unsafe { self.descs.add((index & (self.size - 1)) as usize) }
+    }
+
+    /// 生产者：写入一项，`submit`之后内核才看得到
+    fn push(&mut self, value: T) -> bool {
+        if self.cached_producer.wrapping_sub(self.cached_consumer) == self.size {
+            self.cached_consumer = unsafe { (*self.consumer).load(Ordering::Acquire) };
+            if self.cached_producer.wrapping_sub(self.cached_consumer) == self.size {
+                return false;
+            }
+        }
+        unsafe { self.slot(self.cached_producer).write(value) };
+        self.cached_producer = self.cached_producer.wrapping_add(1);
+        true
+    }
+
+    fn submit(&self) {
+        unsafe { (*self.producer).store(self.cached_producer, Ordering::Release) };
+    }
+
+    /// 消费者：取出一项，`release`之后内核才能复用这些槽位
+    fn pop(&mut self) -> Option<T> {
+        if self.cached_consumer == self.cached_producer {
+            self.cached_producer = unsafe { (*self.producer).load(Ordering::Acquire) };
+            if self.cached_consumer == self.cached_producer {
+                return None;
+            }
+        }
+        let value = unsafe { self.slot(self.cached_consumer).read() };
+        self.cached_consumer = self.cached_consumer.wrapping_add(1);
+        Some(value)
+    }
+
+    fn release(&self) {
+        unsafe { (*self.consumer).store(self.cached_consumer, Ordering::Release) };
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.map, self.map_len) };
+    }
+}
+
+/// 注册给socket的UMEM
+struct Umem {
+    area: *mut u8,
+    len: usize,
+}
+
+impl Umem {
+    fn new() -> io::Result<Self> {
+        let len = FRAME_COUNT as usize * FRAME_SIZE;
+        let area = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_POPULATE,
+                -1,
+                0,
+            )
+        };
+        if area == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            area: area.cast(),
+            len,
+        })
+    }
+}
+
+impl Drop for Umem {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.area.cast(), self.len) };
+    }
+}
+
+/// 绑定在一个收包队列上的AF_XDP socket
+pub struct XskSocket {
+    // 字段按声明顺序释放：先解除环的映射，再关闭socket，最后释放UMEM
+    fill: Ring<u64>,
+    completion: Ring<u64>,
+    rx: Ring<XdpDesc>,
+    tx: Ring<XdpDesc>,
+    fd: OwnedFd,
+    umem: Umem,
+    /// 已放进TX环、还没从完成环收回的帧数
+    outstanding: u32,
+    /// 是否以零拷贝模式绑定
+    pub zero_copy: bool,
+}
+
+// 环与UMEM只由持有socket的任务访问
+unsafe impl Send for XskSocket {}
+
+impl XskSocket {
+    /// 先尝试零拷贝，失败后以拷贝模式绑定
+    pub fn bind(ifindex: u32, queue: u32) -> io::Result<Self> {
+        Self::bind_with(ifindex, queue, XDP_ZEROCOPY)
+            .or_else(|_| Self::bind_with(ifindex, queue, XDP_COPY))
+    }
+
+    fn bind_with(ifindex: u32, queue: u32, flags: u16) -> io::Result<Self> {
+        let fd = unsafe { libc::socket(libc::AF_XDP, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let raw = fd.as_raw_fd();
+
+        let umem = Umem::new()?;
+        let reg = UmemReg {
+            addr: umem.area as u64,
+            len: umem.len as u64,
+            chunk_size: FRAME_SIZE as u32,
+            headroom: 0,
+            flags: 0,
+            tx_metadata_len: 0,
+        };
+        setsockopt(raw, XDP_UMEM_REG, &reg)?;
+        for ring in [
+            XDP_UMEM_FILL_RING,
+            XDP_UMEM_COMPLETION_RING,
+            XDP_RX_RING,
+            XDP_TX_RING,
+        ] {
+            setsockopt(raw, ring, &FRAME_COUNT)?;
+        }
+
+        let mut offsets = MmapOffsets::default();
+        let mut len = size_of::<MmapOffsets>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                raw,
+                SOL_XDP,
+                XDP_MMAP_OFFSETS,
+                &mut offsets as *mut _ as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut fill = Ring::map(raw, &offsets.fr, FRAME_COUNT, XDP_UMEM_PGOFF_FILL_RING)?;
+        let completion = Ring::map(
+            raw,
+            &offsets.cr,
+            FRAME_COUNT,
+            XDP_UMEM_PGOFF_COMPLETION_RING,
+        )?;
+        let rx = Ring::map(raw, &offsets.rx, FRAME_COUNT, XDP_PGOFF_RX_RING)?;
+        let tx = Ring::map(raw, &offsets.tx, FRAME_COUNT, XDP_PGOFF_TX_RING)?;
+
+        for frame in 0..FRAME_COUNT as u64 {
+            fill.push(frame * FRAME_SIZE as u64);
+        }
+        fill.submit();
+
+        let addr = SockaddrXdp {
+            family: libc::AF_XDP as u16,
+            flags,
+            ifindex,
+            queue_id: queue,
+            shared_umem_fd: 0,
+        };
+        let ret = unsafe {
+            libc::bind(
+                raw,
+                &addr as *const _ as *const libc::sockaddr,
+                size_of::<SockaddrXdp>() as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            fill,
+            completion,
+            rx,
+            tx,
+            fd,
+            umem,
+            outstanding: 0,
+            zero_copy: flags == XDP_ZEROCOPY,
+        })
+    }
+
+    /// 处理RX环中的全部帧，返回处理的帧数
+    ///
+    /// `f`在UMEM中原地读写帧，返回`Some(len)`时把帧的前`len`字节发出，否则直接回收
+    pub fn process(&mut self, mut f: impl FnMut(&mut [u8]) -> Option<usize>) -> io::Result<usize> {
+        self.recycle();
+        let mut received = 0;
+        while let Some(desc) = self.rx.pop() {
+            received += 1;
+            let frame = unsafe {
+                slice::from_raw_parts_mut(self.umem.area.add(desc.addr as usize), desc.len as usize)
+            };
+            match f(frame) {
+                Some(len)
+                    if len <= desc.len as usize
+                        && self.tx.push(XdpDesc {
+                            addr: desc.addr,
+                            len: len as u32,
+                            options: 0,
+                        }) =>
+                {
+                    self.outstanding += 1
+                }
+                _ => {
+                    self.fill.push(frame_base(desc.addr));
+                }
+            }
+        }
+        self.rx.release();
+        self.fill.submit();
+        self.tx.submit();
+
+        if self.outstanding > 0 {
+            // 拷贝模式下要由发送调用驱动TX环，环满或设备忙时下次再试
+            let ret = unsafe {
+                libc::sendto(
+                    self.fd.as_raw_fd(),
+                    ptr::null(),
+                    0,
+                    libc::MSG_DONTWAIT,
+                    ptr::null(),
+                    0,
+                )
+            };
+            if ret < 0 {
+                let e = io::Error::last_os_error();
+                if !matches!(
+                    e.raw_os_error(),
+                    Some(libc::EAGAIN | libc::EBUSY | libc::ENOBUFS | libc::ENETDOWN)
+                ) {
+                    return Err(e);
+                }
+            }
+            self.recycle();
+        }
+        Ok(received)
+    }
+
+    /// 还在发送中的帧数，不为零时即使没有新帧也要定期调用`process`回收
+    pub fn outstanding(&self) -> u32 {
+        self.outstanding
+    }
+
+    pub fn statistics(&self) -> io::Result<XdpStatistics> {
+        let mut stats = XdpStatistics::default();
+        let mut len = size_of::<XdpStatistics>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                self.fd.as_raw_fd(),
+                SOL_XDP,
+                XDP_STATISTICS,
+                &mut stats as *mut _ as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(stats)
+    }
+
+    /// 把发送完成的帧放回填充环
+    fn recycle(&mut self) {
+        let mut recycled = false;
+        while let Some(addr) = self.completion.pop() {
+            self.outstanding -= 1;
+            self.fill.push(frame_base(addr));
+            recycled = true;
+        }
+        if recycled {
+            self.completion.release();
+            self.fill.submit();
+        }
+    }
+}
+
+impl AsRawFd for XskSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/// 对齐模式下帧内偏移落在低位，去掉后即帧的起始地址
+fn frame_base(addr: u64) -> u64 {
+    addr & !(FRAME_SIZE as u64 - 1)
+}
+
+fn setsockopt<T>(fd: RawFd, name: libc::c_int, value: &T) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            SOL_XDP,
+            name,
+            value as *const T as *const libc::c_void,
+            size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// 网卡的收包队列数，读取`/sys/class/net/<iface>/queues`
+pub fn rx_queues(iface: &str) -> io::Result<u32> {
+    let count = std::fs::read_dir(format!("/sys/class/net/{iface}/queues"))?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("rx-"))
+        .count();
+    Ok(count.max(1) as u32)
+}
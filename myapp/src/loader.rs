@@ -0,0 +1,333 @@
+//! sensor、hardworker与logger共用的加载流程
+//!
+//! 三个ebpf程序由构建脚本编译后嵌入二进制，这里负责放开memlock限制、加载并固定分类表、
+//! 写入路由与默认规则、按节点配置挂载XDP或TC，以及统计的读取与退出等待。
+
+use std::{fmt, future::Future, path::PathBuf, process::ExitCode, sync::Arc};
+
+use anyhow::{bail, Context as _};
+use aya::{
+    maps::{HashMap, Map, MapData, PerCpuArray},
+    programs::{tc, SchedClassifier, TcAttachType, Xdp, XdpFlags},
+    Ebpf, EbpfLoader,
+};
+use clap::{Args, ValueEnum};
+use common::{
+    classify::Rules,
+    config::{Consts, Hook, Node, Role},
+    stats::Stats,
+    Data, RingStats, Route,
+};
+use log::{debug, warn};
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::detect;
+
+pub type StatsMap = PerCpuArray<MapData, Stats>;
+
+/// `--config`缺省时读取的配置文件，用绝对路径，不依赖当前目录
+pub const DEFAULT_CONFIG: &str = "/etc/myapp/const.toml";
+
+/// 各子命令共有的选项
+#[derive(Debug, Args)]
+pub struct LoaderArgs {
+    /// 挂载的网卡，省略时按配置中节点的MAC与IP在本机网卡中查找
+    #[clap(short, long)]
+    pub iface: Option<String>,
+    /// const.toml格式的配置文件，拓扑与标记在加载时写入ebpf程序，默认为/etc/myapp/const.toml
+    #[clap(short, long, default_value = DEFAULT_CONFIG, global = true)]
+    pub config: PathBuf,
+    /// 本机在配置中的节点名，省略时按本机网卡识别
+    #[clap(short, long)]
+    pub node: Option<String>,
+}
+
+/// 加载并挂载程序的角色子命令的选项
+#[derive(Debug, Args)]
+pub struct AttachArgs {
+    #[clap(flatten)]
+    pub loader: LoaderArgs,
+    /// XDP挂载模式，auto先尝试驱动模式，不支持时退回通用模式，节点配置为tc时不使用
+    #[clap(long, value_enum, default_value_t = XdpMode::Auto)]
+    pub xdp_mode: XdpMode,
+}
+
+/// XDP程序的挂载模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum XdpMode {
+    /// 驱动模式，需要网卡驱动支持XDP
+    Native,
+    /// 通用模式，在协议栈入口运行，任何网卡都支持，Wi-Fi网卡通常只能用这种
+    Skb,
+    /// 卸载到网卡上运行
+    Hw,
+    /// 先尝试驱动模式，失败时退回通用模式
+    Auto,
+}
+
+impl XdpMode {
+    fn flags(self) -> XdpFlags {
+        match self {
+            XdpMode::Native => XdpFlags::DRV_MODE,
+            XdpMode::Skb => XdpFlags::SKB_MODE,
+            XdpMode::Hw => XdpFlags::HW_MODE,
+            XdpMode::Auto => XdpFlags::default(),
+        }
+    }
+}
+
+impl fmt::Display for XdpMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            XdpMode::Native => "native",
+            XdpMode::Skb => "skb",
+            XdpMode::Hw => "hw",
+            XdpMode::Auto => "auto",
+        })
+    }
+}
+
+impl LoaderArgs {
+    /// 读取配置，确定本机的节点与网卡，`role`为`None`时角色也由本机网卡决定
+    pub async fn target(&self, role: Option<Role>) -> anyhow::Result<Target> {
+        let consts = Consts::from_path(&self.config)?;
+        let (node, iface) =
+            detect::detect(&consts, role, self.node.as_deref(), self.iface.as_deref()).await?;
+        Ok(Target {
+            consts,
+            node,
+            iface,
+        })
+    }
+}
+
+impl AttachArgs {
+    /// 同[`LoaderArgs::target`]，另外要求配置的`[data]`与编入ebpf程序的一致
+    pub async fn target(&self, role: Option<Role>) -> anyhow::Result<Target> {
+        let target = self.loader.target(role).await?;
+        let compiled = Data::from_env(env!("MYAPP_DATA")).expect("构建脚本写入的[data]格式固定");
+        let data = target.consts.data;
+        if data != compiled {
+            bail!(
+                "{}中[data]为mtu = {}、load_u64_count = {}，与编入ebpf程序的mtu = {}、\
+                 load_u64_count = {}不同，需要重新构建",
+                self.loader.config.display(),
+                data.mtu,
+                data.load_u64_count,
+                compiled.mtu,
+                compiled.load_u64_count
+            );
+        }
+        Ok(target)
+    }
+}
+
+/// 本机要运行的节点
+pub struct Target {
+    pub consts: Consts,
+    pub node: Node,
+    pub iface: String,
+}
+
+/// 在多线程运行时上跑完一个角色
+pub fn block_on(future: impl Future<Output = anyhow::Result<()>>) -> anyhow::Result<ExitCode> {
+    tokio::runtime::Runtime::new()?.block_on(future)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// 构建脚本嵌入的ebpf对象，以角色命名
+fn object(role: Role) -> &'static [u8] {
+    match role {
+        Role::Sensor => aya::include_bytes_aligned!(concat!(env!("OUT_DIR"), "/sensor")),
+        Role::Hardworker => aya::include_bytes_aligned!(concat!(env!("OUT_DIR"), "/hardworker")),
+        Role::Logger => aya::include_bytes_aligned!(concat!(env!("OUT_DIR"), "/logger")),
+    }
+}
+
+/// 加载角色的ebpf程序，`configure`在加载前写入全局变量或调整表大小
+///
+/// 分类表固定在bpffs中，加载器退出前后都可以用`myapp rule`增删
+pub fn load<'a>(role: Role, configure: impl FnOnce(&mut EbpfLoader<'a>)) -> anyhow::Result<Ebpf> {
+    // Bump the memlock rlimit. This is needed for older kernels that don't use the
+    // new memcg based accounting, see https://lwn.net/Articles/837122/
+    let rlim = libc::rlimit {
+        rlim_cur: libc::RLIM_INFINITY,
+        rlim_max: libc::RLIM_INFINITY,
+    };
+    let ret = unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &rlim) };
+    if ret != 0 {
+        debug!("remove limit on locked memory failed, ret is: {}", ret);
+    }
+
+    let pin_dir = role.pin_dir();
+    std::fs::create_dir_all(&pin_dir)
+        .with_context(|| format!("创建{}失败，考虑bpffs未挂载", pin_dir.display()))?;
+    let mut loader = EbpfLoader::new();
+    loader.map_pin_path(&pin_dir);
+    configure(&mut loader);
+    let mut ebpf = loader.load(object(role))?;
+    if let Err(e) = aya_log::EbpfLogger::init(&mut ebpf) {
+        warn!("初始化ebpf日志器失败: {}", e);
+    }
+    Ok(ebpf)
+}
+
+/// 取出一张表交给用户态
+pub fn take_map(ebpf: &mut Ebpf, name: &str) -> anyhow::Result<Map> {
+    ebpf.take_map(name)
+        .with_context(|| format!("找不到{name}，考虑ebpf程序未正常加载"))
+}
+
+/// 写入节点参与的路由与默认分类规则，返回写入的路由
+pub fn install(ebpf: &mut Ebpf, consts: &Consts, node: &Node) -> anyhow::Result<Vec<(u32, Route)>> {
+    let mut routes: HashMap<_, u32, Route> = HashMap::try_from(
+        ebpf.map_mut("ROUTES")
+            .context("找不到ROUTES，考虑ebpf程序未正常加载")?,
+    )?;
+    let node_routes = consts.routes(node);
+    for (key, route) in &node_routes {
+        routes.insert(key, route, 0)?;
+    }
+    println!("节点{}载入{}条路由", node.name, node_routes.len());
+    let mut rules = Rules::new(
+        take_map(ebpf, "SRC_NETS")?,
+        take_map(ebpf, "DST_NETS")?,
+        take_map(ebpf, "RULES")?,
+    )?;
+    // 表是复用的，上次加载留下的默认规则换成这次配置生成的，用`myapp rule`添加的规则保留
+    let written = rules.replace_defaults(&consts.rules(node))?;
+    println!(
+        "载入{}条默认分类规则，共{}条，固定在{}",
+        written,
+        rules.list()?.len(),
+        node.role.pin_dir().display()
+    );
+    Ok(node_routes)
+}
+
+/// 按节点配置的挂载点挂载程序
+pub fn attach(
+    ebpf: &mut Ebpf,
+    role: Role,
+    iface: &str,
+    hook: Hook,
+    mode: XdpMode,
+) -> anyhow::Result<()> {
+    match hook {
+        Hook::Xdp => attach_xdp(ebpf, role, iface, mode).map(|_| ()),
+        Hook::Tc => attach_tc(ebpf, role, iface),
+    }
+}
+
+/// 把与角色同名的XDP程序按`mode`挂到网卡上，返回实际生效的模式
+fn attach_xdp(ebpf: &mut Ebpf, role: Role, iface: &str, mode: XdpMode) -> anyhow::Result<XdpMode> {
+    let program: &mut Xdp = ebpf
+        .program_mut(&role.to_string())
+        .with_context(|| format!("找不到{role}程序，考虑ebpf程序未正常加载"))?
+        .try_into()?;
+    program.load()?;
+    let mode = match mode {
+        XdpMode::Auto => match program.attach(iface, XdpMode::Native.flags()) {
+            Ok(_) => XdpMode::Native,
+            Err(e) => {
+                println!("{iface}不支持驱动模式XDP（{e}），退回通用模式");
+                program
+                    .attach(iface, XdpMode::Skb.flags())
+                    .with_context(|| format!("以通用模式挂载XDP到{iface}也失败"))?;
+                XdpMode::Skb
+            }
+        },
+        mode => {
+            program.attach(iface, mode.flags()).with_context(|| {
+                format!("以{mode}模式挂载XDP到{iface}失败，可用--xdp-mode auto自动退回通用模式")
+            })?;
+            mode
+        }
+    };
+    println!("{role}以{mode}模式挂载到{iface}");
+    Ok(mode)
+}
+
+/// 把`<角色>_tc`挂到网卡clsact的ingress
+fn attach_tc(ebpf: &mut Ebpf, role: Role, iface: &str) -> anyhow::Result<()> {
+    // clsact已经存在时会报错，沿用即可
+    let _ = tc::qdisc_add_clsact(iface);
+    classifier(ebpf, &format!("{role}_tc"), iface, TcAttachType::Ingress)?;
+    println!("{role}以tc模式挂载到{iface}的ingress");
+    Ok(())
+}
+
+/// 把sensor的出口标记程序挂到网卡clsact的egress，与入口的挂载点无关
+pub fn attach_egress(ebpf: &mut Ebpf, iface: &str) -> anyhow::Result<()> {
+    let _ = tc::qdisc_add_clsact(iface);
+    classifier(ebpf, "sensor_egress", iface, TcAttachType::Egress)
+}
+
+fn classifier(ebpf: &mut Ebpf, name: &str, iface: &str, kind: TcAttachType) -> anyhow::Result<()> {
+    let program: &mut SchedClassifier = ebpf
+        .program_mut(name)
+        .with_context(|| format!("找不到{name}程序，考虑ebpf程序未正常加载"))?
+        .try_into()?;
+    program.load()?;
+    program
+        .attach(iface, kind)
+        .with_context(|| format!("挂载{name}到{iface}失败"))?;
+    Ok(())
+}
+
+/// 取出各CPU的`STATS`计数
+pub fn take_stats(ebpf: &mut Ebpf) -> anyhow::Result<Arc<StatsMap>> {
+    Ok(Arc::new(PerCpuArray::try_from(take_map(ebpf, "STATS")?)?))
+}
+
+/// 每收到一次SIGUSR1调用一次`report`
+pub fn on_sigusr1(report: impl Fn() + Send + 'static) -> anyhow::Result<()> {
+    let mut sig_usr1 = signal(SignalKind::user_defined1())?;
+    tokio::spawn(async move {
+        while sig_usr1.recv().await.is_some() {
+            report();
+        }
+    });
+    Ok(())
+}
+
+/// 打印进程信息后等到Ctrl-C或SIGTERM
+pub async fn wait_for_shutdown() -> anyhow::Result<()> {
+    println!("主进程PID: {}", std::process::id());
+    println!("主线程TID: {}", unsafe {
+        libc::syscall(libc::SYS_gettid)
+    });
+
+    println!("准备完成，等待Ctrl-C或SIGTERM退出，SIGUSR1打印统计...");
+
+    terminated().await
+}
+
+/// 等到Ctrl-C或SIGTERM，systemd与`kill`停止进程时也能正常卸载
+pub async fn terminated() -> anyhow::Result<()> {
+    let mut sig_term = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => {
+            result?;
+            println!("\nCtrl+c退出...");
+        }
+        _ = sig_term.recv() => println!("\nSIGTERM退出..."),
+    }
+    Ok(())
+}
+
+/// 汇总各CPU上的ring buffer统计
+pub fn read_ring_stats(map: &PerCpuArray<MapData, RingStats>) -> anyhow::Result<RingStats> {
+    let values = map.get(&0, 0)?;
+    Ok(values
+        .iter()
+        .fold(RingStats::default(), |total, stats| total.merge(stats)))
+}
+
+/// 汇总各CPU上的计数并打印
+pub fn print_stats(stats: &StatsMap) {
+    match Stats::read(stats) {
+        Ok(stats) => println!("{stats}"),
+        Err(e) => warn!("读取STATS失败: {}", e),
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    fmt,
+    net::{Ipv4Addr, SocketAddrV4},
+    str::FromStr,
+};
+
+use common::record::Record;
+
+/// 一条结构化日志，由logger的XDP程序经`LOG_RING`上报的记录转换而来
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    /// XDP程序记录的`bpf_ktime_get_ns`，自开机起的单调时间。
+    /// hardworker转发来的记录为本机收到时的单调时间
+    pub mono_ns: u64,
+    /// 按[`Clock`]换算出的Unix时间
+    pub time_ns: u64,
+    pub source: SocketAddrV4,
+    pub destination: SocketAddrV4,
+    /// IP协议号
+    pub proto: u8,
+    /// TCP序列号，UDP为0
+    pub seq: u32,
+    /// 报文中的完整负载长度，可能大于`payload`
+    pub payload_len: u32,
+    /// 负载的前若干字节
+    pub payload: Vec<u8>,
+}
+
+impl LogEntry {
+    /// 编码后负载之前的定长部分
+    pub const ENCODED_LEN: usize = 37;
+
+    pub fn new(record: &Record, clock: &Clock) -> Self {
+        let header = &record.header;
+        Self {
+            mono_ns: header.timestamp_ns,
+            time_ns: clock.unix_ns(header.timestamp_ns),
+            source: header.source(),
+            destination: header.destination(),
+            proto: header.proto,
+            seq: header.seq,
+            payload_len: header.payload_len,
+            payload: record.payload.to_vec(),
+        }
+    }
+
+    /// hardworker经UDP转发来的记录，时间戳是hardworker的单调时钟，改用本机收到的时间
+    pub fn forwarded(record: &Record, clock: &Clock) -> Self {
+        let mono_ns = clock_ns(libc::CLOCK_MONOTONIC);
+        Self {
+            mono_ns,
+            time_ns: clock.unix_ns(mono_ns),
+            ..Self::new(record, clock)
+        }
+    }
+
+    /// 负载是否被截断
+    pub fn truncated(&self) -> bool {
+        self.payload_len as usize > self.payload.len()
+    }
+
+    /// 按存储格式编码到`buf`，小端的定长字段在前，负载在后
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.clear();
+        buf.extend_from_slice(&self.time_ns.to_le_bytes());
+        buf.extend_from_slice(&self.mono_ns.to_le_bytes());
+        buf.extend_from_slice(&u32::from(*self.source.ip()).to_le_bytes());
+        buf.extend_from_slice(&u32::from(*self.destination.ip()).to_le_bytes());
+        buf.extend_from_slice(&self.source.port().to_le_bytes());
+        buf.extend_from_slice(&self.destination.port().to_le_bytes());
+        buf.extend_from_slice(&self.seq.to_le_bytes());
+        buf.extend_from_slice(&self.payload_len.to_le_bytes());
+        buf.push(self.proto);
+        buf.extend_from_slice(&self.payload);
+    }
+
+    /// [`encode`](Self::encode)的逆过程，长度不足时返回`None`
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let (fixed, payload) = buf.split_at_checked(Self::ENCODED_LEN)?;
+        let u64_at = |at: usize| u64::from_le_bytes(fixed[at..at + 8].try_into().unwrap());
+        let u32_at = |at: usize| u32::from_le_bytes(fixed[at..at + 4].try_into().unwrap());
+        let u16_at = |at: usize| u16::from_le_bytes(fixed[at..at + 2].try_into().unwrap());
+        Some(Self {
+            time_ns: u64_at(0),
+            mono_ns: u64_at(8),
+            source: SocketAddrV4::new(Ipv4Addr::from(u32_at(16)), u16_at(24)),
+            destination: SocketAddrV4::new(Ipv4Addr::from(u32_at(20)), u16_at(26)),
+            seq: u32_at(28),
+            payload_len: u32_at(32),
+            proto: fixed[36],
+            payload: payload.to_vec(),
+        })
+    }
+}
+
+/// logfmt格式的一行，负载中不可打印的字节转义输出
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "time={} mono_ns={} src={} dst={} proto={} seq={} len={}",
+            Rfc3339(self.time_ns),
+            self.mono_ns,
+            self.source,
+            self.destination,
+            self.proto,
+            self.seq,
+            self.payload_len
+        )?;
+        if self.truncated() {
+            write!(f, " captured={}", self.payload.len())?;
+        }
+        write!(f, " payload=\"{}\"", self.payload.escape_ascii())
+    }
+}
+
+/// 单调时钟到Unix时间的换算，取启动时两个时钟的差
+///
+/// 之后系统时间被调整不会反映到换算结果中，同一次运行内的日志时间保持单调
+#[derive(Debug, Clone, Copy)]
+pub struct Clock {
+    offset_ns: u64,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        let realtime = clock_ns(libc::CLOCK_REALTIME);
+        let monotonic = clock_ns(libc::CLOCK_MONOTONIC);
+        Self {
+            offset_ns: realtime.saturating_sub(monotonic),
+        }
+    }
+
+    pub fn unix_ns(&self, mono_ns: u64) -> u64 {
+        self.offset_ns + mono_ns
+    }
+}
+
+fn clock_ns(clock: libc::clockid_t) -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(clock, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+/// 以UTC的RFC 3339格式输出Unix纳秒时间，精确到纳秒
+#[derive(Debug, Clone, Copy)]
+pub struct Rfc3339(pub u64);
+
+impl fmt::Display for Rfc3339 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0 / 1_000_000_000;
+        let nanos = self.0 % 1_000_000_000;
+        let (year, month, day) = civil_from_days((secs / 86400) as i64);
+        let rem = secs % 86400;
+        write!(
+            f,
+            "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{nanos:09}Z",
+            rem / 3600,
+            rem / 60 % 60,
+            rem % 60
+        )
+    }
+}
+
+/// 接受`2026-10-18T08:00:00Z`或`2026-10-18T16:00:00.5+08:00`这样带时区的时间
+impl FromStr for Rfc3339 {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("{s}不是RFC 3339时间，如2026-10-18T08:00:00Z");
+        let number = |field: &str| -> Result<i64, String> {
+            if field.is_empty() || !field.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            field.parse().map_err(|_| invalid())
+        };
+        let (date, time) = s.split_once(['T', 't', ' ']).ok_or_else(invalid)?;
+        let mut date = date.splitn(3, '-');
+        let (Some(year), Some(month), Some(day)) = (date.next(), date.next(), date.next()) else {
+            return Err(invalid());
+        };
+        let (year, month, day) = (number(year)?, number(month)?, number(day)?);
+
+        // 时区为Z或±HH:MM，换算为相对UTC的秒数
+        let (time, offset) = if let Some(time) = time.strip_suffix(['Z', 'z']) {
+            (time, 0)
+        } else {
+            let at = time.rfind(['+', '-']).ok_or_else(invalid)?;
+            let (time, zone) = time.split_at(at);
+            let (hours, minutes) = zone[1..].split_once(':').ok_or_else(invalid)?;
+            let offset = number(hours)? * 3600 + number(minutes)? * 60;
+            (
+                time,
+                if zone.starts_with('-') {
+                    -offset
+                } else {
+                    offset
+                },
+            )
+        };
+        let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+        let mut time = time.splitn(3, ':');
+        let (Some(hour), Some(minute), Some(second)) = (time.next(), time.next(), time.next())
+        else {
+            return Err(invalid());
+        };
+        let (hour, minute, second) = (number(hour)?, number(minute)?, number(second)?);
+        if !(1..=12).contains(&month)
+            || !(1..=31).contains(&day)
+            || hour > 23
+            || minute > 59
+            || second > 60
+        {
+            return Err(invalid());
+        }
+        let nanos = if fraction.is_empty() {
+            0
+        } else if fraction.len() > 9 {
+            return Err(format!("{s}的秒小数部分最多9位"));
+        } else {
+            number(fraction)? * 10_i64.pow(9 - fraction.len() as u32)
+        };
+
+        let secs = days_from_civil(year, month as u32, day as u32) * 86400
+            + hour * 3600
+            + minute * 60
+            + second
+            - offset;
+        u64::try_from(secs)
+            .ok()
+            .and_then(|secs| secs.checked_mul(1_000_000_000))
+            .and_then(|ns| ns.checked_add(nanos as u64))
+            .map(Self)
+            .ok_or_else(|| format!("{s}不在1970年到2554年之间"))
+    }
+}
+
+/// 公历日期换算为1970-01-01起的天数，[`civil_from_days`]的逆运算
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// 1970-01-01起的天数换算为公历日期，算法见Howard Hinnant的`civil_from_days`
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
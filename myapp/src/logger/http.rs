@@ -0,0 +1,170 @@
+//! 日志存储的本机HTTP查询接口
+//!
+//! 只有`GET /query`一个路径，查询参数与`myapp logger query`的选项同名，如
+//! `/query?from=2026-10-18T08:00:00Z&sensor=192.168.1.85&format=json`。
+
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context as _};
+use clap::Parser;
+use log::{info, warn};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::watch,
+    time::{timeout, Duration},
+};
+
+use super::query::Query;
+
+/// 请求头的大小上限
+const MAX_REQUEST_LEN: usize = 8192;
+/// 读取请求的超时
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// 请求没有给出`limit`时最多返回的条数，避免一次查询占满内存
+const DEFAULT_LIMIT: u64 = 10_000;
+
+/// 用clap解析查询参数，与命令行共用校验
+#[derive(Debug, Parser)]
+#[clap(name = "query", no_binary_name = true, disable_help_flag = true)]
+struct Params {
+    #[clap(flatten)]
+    query: Query,
+}
+
+/// 在`addr`上提供查询接口直到收到退出信号，只允许本机地址
+pub async fn serve(
+    addr: SocketAddr,
+    dir: PathBuf,
+    mut shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    if !addr.ip().is_loopback() {
+        bail!("HTTP查询接口只能监听本机地址，{addr}不是");
+    }
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("监听{addr}失败"))?;
+    info!("HTTP查询接口监听{addr}");
+    loop {
+        tokio::select! {
+            _ = shutdown.changed() => return Ok(()),
+            accepted = listener.accept() => {
+                let (stream, peer) = accepted?;
+                let dir = dir.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle(stream, &dir).await {
+                        warn!("处理{peer}的查询失败: {e:#}");
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// 处理一个请求，响应后关闭连接
+async fn handle(mut stream: TcpStream, dir: &Path) -> anyhow::Result<()> {
+    let request = timeout(READ_TIMEOUT, read_request(&mut stream))
+        .await
+        .context("读取请求超时")??;
+    let (status, content_type, body) = match route(&request) {
+        Ok(query) => {
+            let dir = dir.to_path_buf();
+            let content_type = query.format.content_type();
+            // 读段文件是阻塞IO
+            let result = tokio::task::spawn_blocking(move || {
+                let mut body = Vec::new();
+                query.run(&dir, &mut body).map(|_| body)
+            })
+            .await?;
+            match result {
+                Ok(body) => ("200 OK", content_type, body),
+                Err(e) => (
+                    "500 Internal Server Error",
+                    "text/plain; charset=utf-8",
+                    format!("{e:#}\n").into_bytes(),
+                ),
+            }
+        }
+        Err((status, message)) => (status, "text/plain; charset=utf-8", message.into_bytes()),
+    };
+    let header = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(&body).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// 读到请求头结束，请求体不需要
+async fn read_request(stream: &mut TcpStream) -> anyhow::Result<String> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+    while !buf.windows(4).any(|window| window == b"\r\n\r\n") {
+        if buf.len() > MAX_REQUEST_LEN {
+            bail!("请求头超过{MAX_REQUEST_LEN}字节");
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            bail!("请求不完整");
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+/// 从请求行解析出查询，失败时给出状态与原因
+fn route(request: &str) -> Result<Query, (&'static str, String)> {
+    let line = request.lines().next().unwrap_or_default();
+    let mut parts = line.split(' ');
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(("400 Bad Request", "请求行无效\n".to_string()));
+    };
+    if method != "GET" {
+        return Err(("405 Method Not Allowed", "只支持GET\n".to_string()));
+    }
+    let (path, params) = target.split_once('?').unwrap_or((target, ""));
+    if path != "/query" {
+        return Err(("404 Not Found", "只有/query\n".to_string()));
+    }
+
+    let mut args = Vec::new();
+    for pair in params.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let bad = |e| ("400 Bad Request", format!("{e}\n"));
+        args.push(format!("--{}", percent_decode(key).map_err(bad)?));
+        args.push(percent_decode(value).map_err(bad)?);
+    }
+    let mut query = Params::try_parse_from(args)
+        .map_err(|e| ("400 Bad Request", e.render().to_string()))?
+        .query;
+    query.limit.get_or_insert(DEFAULT_LIMIT);
+    Ok(query)
+}
+
+/// 解码URL查询参数中的`%XX`与`+`
+fn percent_decode(s: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let byte = rest
+                    .get(..2)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| format!("{s}中的%后需要两位十六进制数"))?;
+                bytes.push(byte);
+                rest = &rest[2..];
+            }
+            _ => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| format!("{s}解码后不是UTF-8"))
+}
@@ -0,0 +1,287 @@
+use std::{
+    io::{self, BufWriter, Write as _},
+    net::{Ipv4Addr, SocketAddr},
+    process::ExitCode,
+};
+
+use anyhow::Context as _;
+use aya::maps::{MapData, PerCpuArray, RingBuf};
+use clap::{Args, Subcommand};
+use common::{
+    config::{Consts, Role},
+    record::Record,
+    RingStats,
+};
+#[rustfmt::skip]
+use log::{debug, warn};
+use tokio::{
+    io::unix::AsyncFd,
+    net::UdpSocket,
+    sync::watch,
+    time::{interval, Duration},
+};
+
+use crate::loader::{self, AttachArgs, Target, XdpMode};
+use entry::{Clock, LogEntry};
+use query::Query;
+use store::LogStore;
+
+mod entry;
+mod http;
+mod query;
+mod store;
+
+/// 日志存储落盘的间隔，掉电最多损失这段时间内的日志
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+/// 按保留策略检查旧段的间隔
+const RETAIN_INTERVAL: Duration = Duration::from_secs(60);
+/// 转发记录的接收缓冲区，足够放下一个UDP数据报
+const INGEST_BUF: usize = 65536;
+
+#[derive(Debug, Args)]
+pub struct LoggerArgs {
+    #[clap(flatten)]
+    attach: AttachArgs,
+    /// 在该本机地址上提供HTTP查询接口，如127.0.0.1:8080，需要配置[store]
+    #[clap(long)]
+    http: Option<SocketAddr>,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// 查询日志存储，不加载ebpf程序，可以与运行中的logger同时使用
+    Query(Query),
+}
+
+pub fn run(args: LoggerArgs) -> anyhow::Result<ExitCode> {
+    if let Some(Command::Query(query)) = &args.command {
+        let consts = Consts::from_path(&args.attach.loader.config)?;
+        run_query(&consts, query)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    loader::block_on(async {
+        let target = args.attach.target(Some(Role::Logger)).await?;
+        logger(target, args.attach.xdp_mode, args.http).await
+    })
+}
+
+/// 加载logger程序，`http`给出时同时提供查询接口
+pub async fn logger(
+    target: Target,
+    xdp_mode: XdpMode,
+    http: Option<SocketAddr>,
+) -> anyhow::Result<()> {
+    let Target {
+        consts,
+        node,
+        iface,
+    } = target;
+    let node = &node;
+
+    if http.is_some() && consts.store.is_none() {
+        anyhow::bail!("HTTP查询接口需要在配置中添加[store]");
+    }
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let ring_bytes = consts.ring_byte_size(page_size);
+
+    let mut ebpf = loader::load(Role::Logger, |builder| {
+        builder.set_max_entries("LOG_RING", ring_bytes);
+    })?;
+    loader::install(&mut ebpf, &consts, node)?;
+    loader::attach(&mut ebpf, Role::Logger, &iface, node.hook, xdp_mode)?;
+
+    let stats = loader::take_stats(&mut ebpf)?;
+    // 收到SIGUSR1时汇总打印各CPU的计数
+    loader::on_sigusr1({
+        let stats = stats.clone();
+        move || loader::print_stats(&stats)
+    })?;
+
+    let ring: RingBuf<MapData> = RingBuf::try_from(loader::take_map(&mut ebpf, "LOG_RING")?)?;
+    let ring_stats: PerCpuArray<MapData, RingStats> =
+        PerCpuArray::try_from(loader::take_map(&mut ebpf, "RING_STATS")?)?;
+    println!(
+        "日志ring buffer {}字节，至少容纳{}条{}字节的记录",
+        ring_bytes,
+        consts.ring.records,
+        consts.record_size()
+    );
+    let store = match &consts.store {
+        Some(config) => {
+            let store = LogStore::open(config)?;
+            println!("{store}");
+            Some(store)
+        }
+        None => None,
+    };
+    // ACK模式与可靠UDP下hardworker确认过的数据不会到达本机，记录由它经UDP转发到本机的端口
+    let ingest = if consts.hardworker.ack || consts.udp.is_some() {
+        let port = consts.peer(node).port;
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))
+            .await
+            .with_context(|| format!("绑定接收转发记录的UDP端口{port}失败"))?;
+        println!("在UDP端口{port}接收hardworker转发的记录");
+        Some(socket)
+    } else {
+        None
+    };
+    let (shutdown, shutdown_rx) = watch::channel(false);
+    let consumer = tokio::spawn(consume(
+        ring,
+        ingest,
+        Clock::new(),
+        store,
+        shutdown_rx.clone(),
+    ));
+    if let (Some(addr), Some(config)) = (http, &consts.store) {
+        let server = http::serve(addr, config.dir.clone(), shutdown_rx);
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                warn!("HTTP查询接口退出: {e:#}");
+            }
+        });
+    }
+
+    loader::wait_for_shutdown().await?;
+
+    let _ = shutdown.send(true);
+    match consumer.await? {
+        Ok(entries) => println!("共生成{entries}条日志"),
+        Err(e) => warn!("消费LOG_RING失败: {}", e),
+    }
+    match loader::read_ring_stats(&ring_stats) {
+        Ok(stats) => println!(
+            "日志ring buffer写入: {}, 丢弃: {}",
+            stats.submitted, stats.dropped
+        ),
+        Err(e) => warn!("读取RING_STATS失败: {}", e),
+    }
+    loader::print_stats(&stats);
+
+    Ok(())
+}
+
+/// 逐条取出`LOG_RING`中的记录与hardworker转发来的记录，转换为结构化日志，
+/// 直到收到退出信号，返回日志条数
+///
+/// 配置了存储时日志写入存储，否则逐条打印
+async fn consume(
+    ring: RingBuf<MapData>,
+    ingest: Option<UdpSocket>,
+    clock: Clock,
+    mut store: Option<LogStore>,
+    mut shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<u64> {
+    let mut ring = AsyncFd::new(ring)?;
+    let mut sync = interval(SYNC_INTERVAL);
+    let mut retain = interval(RETAIN_INTERVAL);
+    let mut buf = vec![0u8; INGEST_BUF];
+    let mut entries = 0;
+    loop {
+        tokio::select! {
+            _ = shutdown.changed() => break,
+            guard = ring.readable_mut() => {
+                let mut guard = guard?;
+                while let Some(item) = guard.get_inner_mut().next() {
+                    let entry = match Record::decode(&item) {
+                        Ok(record) => LogEntry::new(&record, &clock),
+                        Err(e) => {
+                            warn!("解析日志记录失败: {}", e);
+                            continue;
+                        }
+                    };
+                    emit(&mut store, &entry)?;
+                    entries += 1;
+                }
+                guard.clear_ready();
+                if let Some(store) = &mut store {
+                    store.flush().context("写入日志存储失败")?;
+                }
+            }
+            Some(socket) = ready(ingest.as_ref()) => {
+                // 一次取完已经到达的数据报再写出
+                loop {
+                    let len = match socket.try_recv(&mut buf) {
+                        Ok(len) => len,
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(e) => {
+                            warn!("接收转发记录失败: {}", e);
+                            break;
+                        }
+                    };
+                    match Record::decode(&buf[..len]) {
+                        Ok(record) => {
+                            emit(&mut store, &LogEntry::forwarded(&record, &clock))?;
+                            entries += 1;
+                        }
+                        Err(e) => warn!("解析转发记录失败: {}", e),
+                    }
+                }
+                if let Some(store) = &mut store {
+                    store.flush().context("写入日志存储失败")?;
+                }
+            }
+            _ = sync.tick(), if store.is_some() => {
+                if let Some(store) = &mut store {
+                    store.sync().context("日志存储落盘失败")?;
+                }
+            }
+            _ = retain.tick(), if store.is_some() => {
+                if let Some(store) = &mut store {
+                    store.retain().context("清理日志存储失败")?;
+                }
+            }
+        }
+    }
+    if let Some(store) = &mut store {
+        store.sync().context("日志存储落盘失败")?;
+        println!("{store}");
+    }
+    Ok(entries)
+}
+
+/// 写入存储，没有存储时打印
+fn emit(store: &mut Option<LogStore>, entry: &LogEntry) -> anyhow::Result<()> {
+    match store {
+        Some(store) => {
+            debug!("{entry}");
+            store.append(entry).context("写入日志存储失败")
+        }
+        None => {
+            println!("{entry}");
+            Ok(())
+        }
+    }
+}
+
+/// 等到socket可读，没有socket时永远等待
+async fn ready(socket: Option<&UdpSocket>) -> Option<&UdpSocket> {
+    match socket {
+        Some(socket) => {
+            // 出错时交给之后的接收报告
+            let _ = socket.readable().await;
+            Some(socket)
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// 在配置的日志存储上执行一次查询，结果写到标准输出，条数写到标准错误
+fn run_query(consts: &Consts, query: &Query) -> anyhow::Result<()> {
+    let store = consts
+        .store
+        .as_ref()
+        .context("配置中没有[store]，日志未存储")?;
+    let mut out = BufWriter::new(io::stdout().lock());
+    let count = query.run(&store.dir, &mut out)?;
+    // 与查询结果一样，下游提前关闭时不算出错
+    if let Err(e) = out.flush() {
+        if e.kind() != io::ErrorKind::BrokenPipe {
+            return Err(e.into());
+        }
+    }
+    eprintln!("共{count}条");
+    Ok(())
+}
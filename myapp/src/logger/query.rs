@@ -0,0 +1,265 @@
+use std::{
+    fmt,
+    io::{self, Write},
+    net::{Ipv4Addr, SocketAddrV4},
+    path::Path,
+    str::FromStr,
+};
+
+use clap::{Args, ValueEnum};
+
+use super::{
+    entry::{LogEntry, Rfc3339},
+    store,
+};
+
+/// 日志存储的查询条件，各条件同时满足才输出
+#[derive(Debug, Clone, Args)]
+pub struct Query {
+    /// 起始时间（含），RFC 3339格式，如2026-10-18T08:00:00Z
+    #[clap(long)]
+    pub from: Option<Rfc3339>,
+    /// 结束时间（含）
+    #[clap(long)]
+    pub to: Option<Rfc3339>,
+    /// sensor的IP，即报文的源地址
+    #[clap(long)]
+    pub sensor: Option<Ipv4Addr>,
+    /// 流，`源IP:端口-目的IP:端口`
+    #[clap(long)]
+    pub flow: Option<Flow>,
+    /// TCP序列号范围，`起-止`（含两端）或单个序列号
+    #[clap(long)]
+    pub seq: Option<SeqRange>,
+    /// 负载中要包含的字节，可用\xNN、\n、\t等转义
+    #[clap(long)]
+    pub payload: Option<Pattern>,
+    /// 最多输出多少条
+    #[clap(long)]
+    pub limit: Option<u64>,
+    #[clap(long, value_enum, default_value_t = Format::Table)]
+    pub format: Format,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// 对齐的表格，负载转义输出
+    Table,
+    /// 每行一个JSON对象，负载为十六进制
+    Json,
+    /// 带表头的CSV，负载为十六进制
+    Csv,
+}
+
+impl Format {
+    /// HTTP响应的Content-Type
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Table => "text/plain; charset=utf-8",
+            Self::Json => "application/x-ndjson",
+            Self::Csv => "text/csv",
+        }
+    }
+}
+
+impl Query {
+    fn matches(&self, entry: &LogEntry) -> bool {
+        self.sensor.is_none_or(|ip| *entry.source.ip() == ip)
+            && self.flow.as_ref().is_none_or(|flow| {
+                entry.source == flow.source && entry.destination == flow.destination
+            })
+            && self
+                .seq
+                .as_ref()
+                .is_none_or(|seq| (seq.start..=seq.end).contains(&entry.seq))
+            && self
+                .payload
+                .as_ref()
+                .is_none_or(|pattern| pattern.found_in(&entry.payload))
+    }
+
+    /// 在`dir`的日志存储上执行查询，按`format`写入`out`，返回输出的条数
+    pub fn run(&self, dir: &Path, out: &mut impl Write) -> anyhow::Result<u64> {
+        match self.format {
+            Format::Table => writeln!(
+                out,
+                "{:<30} {:>16} {:<21} {:<21} {:>5} {:>10} {:>6} PAYLOAD",
+                "TIME", "MONO_NS", "SRC", "DST", "PROTO", "SEQ", "LEN"
+            )?,
+            Format::Csv => writeln!(out, "time,mono_ns,src,dst,proto,seq,len,captured,payload")?,
+            Format::Json => {}
+        }
+        let mut count = 0;
+        let mut result = Ok(());
+        store::read(
+            dir,
+            self.from.map(|time| time.0),
+            self.to.map(|time| time.0),
+            |entry| {
+                if !self.matches(&entry) {
+                    return true;
+                }
+                result = self.write(out, &entry);
+                count += 1;
+                result.is_ok() && self.limit.is_none_or(|limit| count < limit)
+            },
+        )?;
+        match result {
+            // 下游如`head`提前关闭时不算出错
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e.into()),
+            _ => Ok(count),
+        }
+    }
+
+    fn write(&self, out: &mut impl Write, entry: &LogEntry) -> io::Result<()> {
+        let time = Rfc3339(entry.time_ns);
+        match self.format {
+            Format::Table => writeln!(
+                out,
+                "{:<30} {:>16} {:<21} {:<21} {:>5} {:>10} {:>6} {}",
+                time.to_string(),
+                entry.mono_ns,
+                entry.source.to_string(),
+                entry.destination.to_string(),
+                entry.proto,
+                entry.seq,
+                entry.payload_len,
+                entry.payload.escape_ascii()
+            ),
+            Format::Json => writeln!(
+                out,
+                "{{\"time\":\"{time}\",\"mono_ns\":{},\"src\":\"{}\",\"dst\":\"{}\",\"proto\":{},\"seq\":{},\"len\":{},\"captured\":{},\"payload\":\"{}\"}}",
+                entry.mono_ns,
+                entry.source,
+                entry.destination,
+                entry.proto,
+                entry.seq,
+                entry.payload_len,
+                entry.payload.len(),
+                Hex(&entry.payload)
+            ),
+            Format::Csv => writeln!(
+                out,
+                "{time},{},{},{},{},{},{},{},{}",
+                entry.mono_ns,
+                entry.source,
+                entry.destination,
+                entry.proto,
+                entry.seq,
+                entry.payload_len,
+                entry.payload.len(),
+                Hex(&entry.payload)
+            ),
+        }
+    }
+}
+
+/// 一个方向上的流
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Flow {
+    pub source: SocketAddrV4,
+    pub destination: SocketAddrV4,
+}
+
+impl FromStr for Flow {
+    type Err = String;
+
+    /// 接受`10.0.0.1:40000-10.0.0.2:12345`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("{s}不是流，格式为源IP:端口-目的IP:端口");
+        let (source, destination) = s.split_once('-').ok_or_else(invalid)?;
+        Ok(Self {
+            source: source.trim().parse().map_err(|_| invalid())?,
+            destination: destination.trim().parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// 含两端的序列号范围
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeqRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl FromStr for SeqRange {
+    type Err = String;
+
+    /// 接受`100-200`或`100`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s.split_once('-').unwrap_or((s, s));
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<u32>()
+                .map_err(|_| format!("{s}不是序列号范围，格式为起-止"))
+        };
+        let (start, end) = (parse(start)?, parse(end)?);
+        if start > end {
+            return Err(format!("{s}的起点大于终点"));
+        }
+        Ok(Self { start, end })
+    }
+}
+
+/// 负载中要查找的字节串
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern(Vec<u8>);
+
+impl Pattern {
+    fn found_in(&self, payload: &[u8]) -> bool {
+        payload
+            .windows(self.0.len())
+            .any(|window| window == self.0.as_slice())
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    /// 与表格输出中负载的转义相同：\xNN、\n、\r、\t、\0、\\、\"、\'
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bytes = Vec::with_capacity(s.len());
+        let mut rest = s.as_bytes();
+        while let Some((&b, tail)) = rest.split_first() {
+            rest = tail;
+            if b != b'\\' {
+                bytes.push(b);
+                continue;
+            }
+            let Some((&escape, tail)) = rest.split_first() else {
+                return Err(format!("{s}以不完整的转义结尾"));
+            };
+            rest = tail;
+            bytes.push(match escape {
+                b'n' => b'\n',
+                b'r' => b'\r',
+                b't' => b'\t',
+                b'0' => 0,
+                b'\\' | b'"' | b'\'' => escape,
+                b'x' => {
+                    let hex = rest
+                        .get(..2)
+                        .and_then(|hex| std::str::from_utf8(hex).ok())
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                        .ok_or_else(|| format!("{s}中的\\x后需要两位十六进制数"))?;
+                    rest = &rest[2..];
+                    hex
+                }
+                _ => return Err(format!("{s}中有未知转义\\{}", escape as char)),
+            });
+        }
+        if bytes.is_empty() {
+            return Err("负载模式不能为空".to_string());
+        }
+        Ok(Self(bytes))
+    }
+}
+
+/// 以小写十六进制输出字节
+struct Hex<'a>(&'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
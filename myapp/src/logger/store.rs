@@ -0,0 +1,793 @@
+//! logger的持久化日志存储
+//!
+//! 日志按段追加写入`<id>.log`，段写满后滚动到编号加一的新段。段文件以16字节的文件头开始，
+//! 之后每条记录是`[长度 u32][CRC32 u32][LogEntry编码]`，均为小端。每个段配一个稀疏的时间索引
+//! `<id>.idx`，每隔约4KiB记一项`[time_ns u64][偏移 u64]`，按时间查询时据此定位起读位置。
+//!
+//! 启动时活动段（编号最大的段）整段校验，截掉第一条损坏记录及其后的内容并重建索引；
+//! 已封存的段在滚动时已落盘，只从最后一个索引项扫到段尾。
+//!
+//! [`read`]只读打开各段，可以与正在写入的logger同时使用。
+
+use std::{
+    fmt, fs,
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    mem,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context as _};
+use common::config;
+use log::{info, warn};
+
+use super::entry::LogEntry;
+
+const MAGIC: &[u8; 8] = b"MYAPPLOG";
+const VERSION: u32 = 1;
+/// 段文件头：魔数、版本、保留的4字节
+const HEADER_LEN: u64 = 16;
+/// 记录头：编码长度与CRC32
+const FRAME_HEADER_LEN: u64 = 8;
+/// 编码长度的合理上限，超过即视为损坏
+const MAX_BODY_LEN: u32 = 1 << 20;
+/// 相邻索引项之间至少间隔的字节数
+const INDEX_INTERVAL: u64 = 4096;
+const INDEX_ENTRY_LEN: usize = 16;
+
+/// 段的元信息，时间是段内记录`time_ns`的最小与最大值
+#[derive(Debug)]
+struct Segment {
+    id: u64,
+    size: u64,
+    /// 索引文件的字节数
+    index_size: u64,
+    first_time: Option<u64>,
+    last_time: Option<u64>,
+}
+
+impl Segment {
+    fn observe(&mut self, time_ns: u64) {
+        self.first_time = Some(self.first_time.map_or(time_ns, |t| t.min(time_ns)));
+        self.last_time = Some(self.last_time.map_or(time_ns, |t| t.max(time_ns)));
+    }
+}
+
+/// 正在写入的段
+struct Active {
+    segment: Segment,
+    log: BufWriter<File>,
+    idx: BufWriter<File>,
+    /// 最后一个索引项指向的偏移
+    last_indexed: Option<u64>,
+}
+
+pub struct LogStore {
+    dir: PathBuf,
+    segment_bytes: u64,
+    max_bytes: Option<u64>,
+    max_age_ns: Option<u64>,
+    /// 已封存的段，按编号从旧到新
+    sealed: Vec<Segment>,
+    active: Active,
+    /// 本次运行写入的记录数
+    appended: u64,
+    buf: Vec<u8>,
+}
+
+impl LogStore {
+    /// 打开或创建存储目录，恢复已有的段并按保留策略清理
+    pub fn open(config: &config::Store) -> anyhow::Result<Self> {
+        let dir = config.dir.clone();
+        fs::create_dir_all(&dir).with_context(|| format!("创建{}失败", dir.display()))?;
+        let mut ids = segment_ids(&dir)?;
+        let active_id = ids.pop();
+        let sealed = ids
+            .into_iter()
+            .map(|id| recover(&dir, id, false))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let active = match active_id {
+            Some(id) => {
+                let segment = recover(&dir, id, true)?;
+                let last_indexed = read_index(&index_path(&dir, id), segment.size)?
+                    .last()
+                    .map(|&(_, offset)| offset);
+                Active {
+                    log: BufWriter::new(append(&segment_path(&dir, id))?),
+                    idx: BufWriter::new(append(&index_path(&dir, id))?),
+                    segment,
+                    last_indexed,
+                }
+            }
+            None => create(&dir, 0)?,
+        };
+        let mut store = Self {
+            dir,
+            segment_bytes: config.segment_bytes(),
+            max_bytes: config.max_bytes(),
+            max_age_ns: config.max_age().map(|age| age.as_nanos() as u64),
+            sealed,
+            active,
+            appended: 0,
+            buf: Vec::new(),
+        };
+        store.retain()?;
+        Ok(store)
+    }
+
+    /// 追加一条日志，当前段写满时先滚动到新段
+    pub fn append(&mut self, entry: &LogEntry) -> anyhow::Result<()> {
+        entry.encode(&mut self.buf);
+        let frame_len = FRAME_HEADER_LEN + self.buf.len() as u64;
+        let size = self.active.segment.size;
+        if size > HEADER_LEN && size + frame_len > self.segment_bytes {
+            self.roll()?;
+        }
+
+        let active = &mut self.active;
+        let offset = active.segment.size;
+        active
+            .log
+            .write_all(&(self.buf.len() as u32).to_le_bytes())?;
+        active.log.write_all(&crc32(&self.buf).to_le_bytes())?;
+        active.log.write_all(&self.buf)?;
+        if should_index(active.last_indexed, offset) {
+            active.idx.write_all(&entry.time_ns.to_le_bytes())?;
+            active.idx.write_all(&offset.to_le_bytes())?;
+            active.last_indexed = Some(offset);
+            active.segment.index_size += INDEX_ENTRY_LEN as u64;
+        }
+        active.segment.size += frame_len;
+        active.segment.observe(entry.time_ns);
+        self.appended += 1;
+        Ok(())
+    }
+
+    /// 把缓冲的写入交给内核，进程崩溃不丢，掉电仍可能丢
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        // 先写日志再写索引，索引项不会指向尚未写出的记录
+        self.active.log.flush()?;
+        self.active.idx.flush()?;
+        Ok(())
+    }
+
+    /// 落盘，掉电后最多损失上次`sync`之后的记录
+    pub fn sync(&mut self) -> anyhow::Result<()> {
+        self.flush()?;
+        self.active.log.get_ref().sync_data()?;
+        self.active.idx.get_ref().sync_data()?;
+        Ok(())
+    }
+
+    /// 按总大小与保留时长删除最旧的已封存段，活动段不删，返回删除的段数
+    pub fn retain(&mut self) -> anyhow::Result<usize> {
+        let mut removed = 0;
+        if let Some(max_bytes) = self.max_bytes {
+            while !self.sealed.is_empty() && self.bytes() > max_bytes {
+                self.remove_oldest()?;
+                removed += 1;
+            }
+        }
+        if let Some(max_age_ns) = self.max_age_ns {
+            let cutoff = unix_now_ns().saturating_sub(max_age_ns);
+            while self
+                .sealed
+                .first()
+                .is_some_and(|segment| segment.last_time.unwrap_or(0) < cutoff)
+            {
+                self.remove_oldest()?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// 所有段连同索引的总字节数
+    fn bytes(&self) -> u64 {
+        self.sealed
+            .iter()
+            .chain([&self.active.segment])
+            .map(|segment| segment.size + segment.index_size)
+            .sum()
+    }
+
+    /// 封存当前段并创建下一个段
+    fn roll(&mut self) -> anyhow::Result<()> {
+        self.sync()?;
+        let next = create(&self.dir, self.active.segment.id + 1)?;
+        let sealed = mem::replace(&mut self.active, next);
+        info!(
+            "日志段{}已写满，滚动到段{}",
+            sealed.segment.id, self.active.segment.id
+        );
+        self.sealed.push(sealed.segment);
+        self.retain()?;
+        Ok(())
+    }
+
+    fn remove_oldest(&mut self) -> anyhow::Result<()> {
+        let segment = self.sealed.remove(0);
+        let path = segment_path(&self.dir, segment.id);
+        fs::remove_file(&path).with_context(|| format!("删除{}失败", path.display()))?;
+        if let Err(e) = fs::remove_file(index_path(&self.dir, segment.id)) {
+            if e.kind() != io::ErrorKind::NotFound {
+                return Err(e.into());
+            }
+        }
+        info!("按保留策略删除日志段{}", segment.id);
+        Ok(())
+    }
+}
+
+impl fmt::Display for LogStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "日志存储{}: {}个段，共{}字节，本次写入{}条",
+            self.dir.display(),
+            self.sealed.len() + 1,
+            self.bytes(),
+            self.appended
+        )
+    }
+}
+
+/// 按时间范围（均含）从旧到新读取`dir`中的日志，`visit`返回`false`时停止
+///
+/// 活动段末尾尚未写完的记录被忽略。同一段内的记录大致按时间排列，起读位置由索引中时间早于
+/// `from`的最后一项确定，范围之外的记录由这里过滤掉。
+pub fn read(
+    dir: &Path,
+    from: Option<u64>,
+    to: Option<u64>,
+    mut visit: impl FnMut(LogEntry) -> bool,
+) -> anyhow::Result<()> {
+    let mut segments = Vec::new();
+    for id in segment_ids(dir)? {
+        // 列出之后可能被logger按保留策略删除
+        let size = match fs::metadata(segment_path(dir, id)) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        segments.push((id, read_index(&index_path(dir, id), size)?));
+    }
+
+    let in_range =
+        |time_ns: u64| from.is_none_or(|from| time_ns >= from) && to.is_none_or(|to| time_ns <= to);
+    let mut buf = Vec::new();
+    for (i, (id, index)) in segments.iter().enumerate() {
+        let next_first = segments
+            .get(i + 1)
+            .and_then(|(_, index)| index.first())
+            .map(|&(time_ns, _)| time_ns);
+        if let (Some(from), Some(next_first)) = (from, next_first) {
+            // 下一段的第一条就早于起始时间，本段整段都在范围之前
+            if next_first < from {
+                continue;
+            }
+        }
+        if let (Some(to), Some(&(first, _))) = (to, index.first()) {
+            if first > to {
+                break;
+            }
+        }
+        let start = match from {
+            Some(from) => {
+                let after = index.partition_point(|&(time_ns, _)| time_ns < from);
+                after.checked_sub(1).map_or(HEADER_LEN, |at| index[at].1)
+            }
+            None => HEADER_LEN,
+        };
+
+        let path = segment_path(dir, *id);
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("打开{}失败", path.display())),
+        };
+        if file.metadata()?.len() < HEADER_LEN {
+            continue;
+        }
+        check_header(&mut file, &path)?;
+        file.seek(SeekFrom::Start(start))?;
+        let mut reader = BufReader::new(file);
+        loop {
+            match read_frame(&mut reader, &mut buf)? {
+                Frame::Entry(entry) => {
+                    if in_range(entry.time_ns) && !visit(entry) {
+                        return Ok(());
+                    }
+                }
+                Frame::End => break,
+                Frame::Corrupt(reason) => {
+                    if i + 1 < segments.len() {
+                        warn!("{}中有记录{reason}，跳过该段其余部分", path.display());
+                    }
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 校验段文件头，读完后文件位于第一条记录处
+fn check_header(file: &mut File, path: &Path) -> anyhow::Result<()> {
+    let mut buf = [0; HEADER_LEN as usize];
+    file.read_exact(&mut buf)?;
+    if buf[..8] != MAGIC[..] {
+        bail!("{}不是日志段文件", path.display());
+    }
+    let version = u32::from_le_bytes(buf[8..12].try_into().unwrap());
+    if version != VERSION {
+        bail!("{}的版本为{version}，只支持{VERSION}", path.display());
+    }
+    Ok(())
+}
+
+/// 段的读取结果
+enum Frame {
+    Entry(LogEntry),
+    /// 恰好读到段尾
+    End,
+    /// 记录不完整或校验失败，附原因
+    Corrupt(&'static str),
+}
+
+/// 从当前位置读一条记录，`buf`复用为读缓冲
+fn read_frame(reader: &mut impl Read, buf: &mut Vec<u8>) -> io::Result<Frame> {
+    let mut header = [0; FRAME_HEADER_LEN as usize];
+    match read_full(reader, &mut header)? {
+        0 => return Ok(Frame::End),
+        n if n < header.len() => return Ok(Frame::Corrupt("记录头不完整")),
+        _ => {}
+    }
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap());
+    let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+    if len > MAX_BODY_LEN {
+        return Ok(Frame::Corrupt("记录长度超出上限"));
+    }
+    buf.resize(len as usize, 0);
+    if read_full(reader, buf)? < buf.len() {
+        return Ok(Frame::Corrupt("记录不完整"));
+    }
+    if crc32(buf) != crc {
+        return Ok(Frame::Corrupt("CRC不符"));
+    }
+    Ok(match LogEntry::decode(buf) {
+        Some(entry) => Frame::Entry(entry),
+        None => Frame::Corrupt("记录无法解析"),
+    })
+}
+
+/// 尽量读满`buf`，返回实际读到的字节数，只有到达文件末尾时才会少于`buf`的长度
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// 恢复一个段：校验文件头，扫描记录直到段尾或第一条损坏的记录，截掉损坏部分并补全索引
+///
+/// `full`时整段扫描并重建索引，否则信任已有索引，只从最后一个索引项扫起
+fn recover(dir: &Path, id: u64, full: bool) -> anyhow::Result<Segment> {
+    let path = segment_path(dir, id);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("打开{}失败", path.display()))?;
+    let size = file.metadata()?.len();
+    if size < HEADER_LEN {
+        // 创建段时中断，段内还没有记录
+        warn!("{}的文件头不完整，重写", path.display());
+        file.set_len(0)?;
+        file.write_all(&header())?;
+        file.sync_data()?;
+    } else {
+        check_header(&mut file, &path)?;
+    }
+    let size = size.max(HEADER_LEN);
+
+    let idx_path = index_path(dir, id);
+    let mut index = if full {
+        Vec::new()
+    } else {
+        read_index(&idx_path, size)?
+    };
+    let mut segment = Segment {
+        id,
+        size: HEADER_LEN,
+        index_size: 0,
+        first_time: None,
+        last_time: None,
+    };
+    for &(time_ns, _) in &index {
+        segment.observe(time_ns);
+    }
+    let mut last_indexed = index.last().map(|&(_, offset)| offset);
+    let mut offset = last_indexed.unwrap_or(HEADER_LEN);
+
+    file.seek(SeekFrom::Start(offset))?;
+    let mut reader = BufReader::new(&mut file);
+    let mut buf = Vec::new();
+    let mut records = 0;
+    let corrupt = loop {
+        match read_frame(&mut reader, &mut buf)? {
+            Frame::Entry(entry) => {
+                if should_index(last_indexed, offset) {
+                    index.push((entry.time_ns, offset));
+                    last_indexed = Some(offset);
+                }
+                segment.observe(entry.time_ns);
+                offset += FRAME_HEADER_LEN + buf.len() as u64;
+                records += 1;
+            }
+            Frame::End => break None,
+            Frame::Corrupt(reason) => break Some(reason),
+        }
+    };
+    segment.size = offset;
+    if let Some(reason) = corrupt {
+        warn!(
+            "{}在偏移{offset}处{reason}，截掉其后的{}字节",
+            path.display(),
+            size - offset
+        );
+        file.set_len(offset)?;
+        file.sync_data()?;
+    }
+    write_index(&idx_path, &index)?;
+    segment.index_size = (index.len() * INDEX_ENTRY_LEN) as u64;
+    if full {
+        info!("恢复日志段{id}: {records}条记录，{offset}字节");
+    }
+    Ok(segment)
+}
+
+/// 读取索引，忽略末尾不完整的项以及越过段尾或不递增的偏移
+fn read_index(path: &Path, size: u64) -> anyhow::Result<Vec<(u64, u64)>> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("读取{}失败", path.display())),
+    };
+    let mut index: Vec<(u64, u64)> = Vec::new();
+    for item in bytes.chunks_exact(INDEX_ENTRY_LEN) {
+        let time_ns = u64::from_le_bytes(item[..8].try_into().unwrap());
+        let offset = u64::from_le_bytes(item[8..].try_into().unwrap());
+        let after_last = index.last().map_or(HEADER_LEN, |&(_, last)| last + 1);
+        if offset < after_last || offset >= size {
+            break;
+        }
+        index.push((time_ns, offset));
+    }
+    Ok(index)
+}
+
+fn write_index(path: &Path, index: &[(u64, u64)]) -> anyhow::Result<()> {
+    let mut bytes = Vec::with_capacity(index.len() * INDEX_ENTRY_LEN);
+    for &(time_ns, offset) in index {
+        bytes.extend_from_slice(&time_ns.to_le_bytes());
+        bytes.extend_from_slice(&offset.to_le_bytes());
+    }
+    let mut file = File::create(path).with_context(|| format!("写入{}失败", path.display()))?;
+    file.write_all(&bytes)?;
+    file.sync_data()?;
+    Ok(())
+}
+
+/// 段内第一条记录必记索引，之后与上一个索引项相隔至少`INDEX_INTERVAL`字节才记
+fn should_index(last_indexed: Option<u64>, offset: u64) -> bool {
+    match last_indexed {
+        None => true,
+        Some(last) => offset - last >= INDEX_INTERVAL,
+    }
+}
+
+/// 创建一个空段，并把新文件名落盘到目录中
+fn create(dir: &Path, id: u64) -> anyhow::Result<Active> {
+    let path = segment_path(dir, id);
+    let mut log = OpenOptions::new()
+        .append(true)
+        .create_new(true)
+        .open(&path)
+        .with_context(|| format!("创建{}失败", path.display()))?;
+    log.write_all(&header())?;
+    log.sync_data()?;
+    let idx = File::create(index_path(dir, id))?;
+    File::open(dir)?.sync_all()?;
+    Ok(Active {
+        segment: Segment {
+            id,
+            size: HEADER_LEN,
+            index_size: 0,
+            first_time: None,
+            last_time: None,
+        },
+        log: BufWriter::new(log),
+        idx: BufWriter::new(idx),
+        last_indexed: None,
+    })
+}
+
+fn append(path: &Path) -> anyhow::Result<File> {
+    OpenOptions::new()
+        .append(true)
+        .open(path)
+        .with_context(|| format!("打开{}失败", path.display()))
+}
+
+fn header() -> [u8; HEADER_LEN as usize] {
+    let mut header = [0; HEADER_LEN as usize];
+    header[..8].copy_from_slice(MAGIC);
+    header[8..12].copy_from_slice(&VERSION.to_le_bytes());
+    header
+}
+
+/// 目录中已有的段编号，从小到大
+fn segment_ids(dir: &Path) -> anyhow::Result<Vec<u64>> {
+    let mut ids = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("读取{}失败", dir.display()))? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "log") {
+            if let Some(id) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+            {
+                ids.push(id);
+            }
+        }
+    }
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+fn segment_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{id:020}.log"))
+}
+
+fn index_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{id:020}.idx"))
+}
+
+fn unix_now_ns() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_nanos() as u64)
+}
+
+/// CRC-32（IEEE 802.3），与zlib的`crc32`相同
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &b| {
+        CRC_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddrV4, process};
+
+    use super::*;
+
+    /// 测试用的存储目录，结束时删除
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("myapp-store-{name}-{}", process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            Self(dir)
+        }
+
+        fn config(&self) -> config::Store {
+            config::Store {
+                dir: self.0.clone(),
+                segment_mb: 1,
+                max_age_hours: 0,
+                max_mb: 0,
+            }
+        }
+
+        /// 目录中所有文件的总字节数
+        fn bytes(&self) -> u64 {
+            fs::read_dir(&self.0)
+                .unwrap()
+                .map(|entry| entry.unwrap().metadata().unwrap().len())
+                .sum()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn entry(time_ns: u64) -> LogEntry {
+        LogEntry {
+            mono_ns: time_ns,
+            time_ns,
+            source: "10.0.0.1:40000".parse::<SocketAddrV4>().unwrap(),
+            destination: "10.0.0.2:12345".parse::<SocketAddrV4>().unwrap(),
+            proto: 6,
+            seq: time_ns as u32,
+            payload_len: 100,
+            payload: vec![time_ns as u8; 100],
+        }
+    }
+
+    fn write(dir: &TempDir, times: impl IntoIterator<Item = u64>) {
+        let mut store = LogStore::open(&dir.config()).unwrap();
+        for time_ns in times {
+            store.append(&entry(time_ns)).unwrap();
+        }
+        store.sync().unwrap();
+    }
+
+    fn read_all(dir: &TempDir, from: Option<u64>) -> Vec<LogEntry> {
+        let mut entries = Vec::new();
+        read(&dir.0, from, None, |entry| {
+            entries.push(entry);
+            true
+        })
+        .unwrap();
+        entries
+    }
+
+    #[test]
+    fn crc_round_trip() {
+        // zlib的标准校验值
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        let dir = TempDir::new("crc");
+        write(&dir, 1..=3);
+        assert_eq!(read_all(&dir, None), (1..=3).map(entry).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn truncates_torn_tail() {
+        let dir = TempDir::new("torn");
+        write(&dir, 1..=3);
+        let path = segment_path(&dir.0, 0);
+        let size = fs::metadata(&path).unwrap().len();
+        // 写到一半的记录头
+        let mut file = append(&path).unwrap();
+        file.write_all(&[0x25, 0, 0]).unwrap();
+        drop(file);
+
+        write(&dir, [4]);
+        assert_eq!(fs::metadata(&path).unwrap().len(), size + 145);
+        assert_eq!(read_all(&dir, None), (1..=4).map(entry).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn truncates_corrupt_tail() {
+        let dir = TempDir::new("corrupt");
+        write(&dir, 1..=3);
+        let path = segment_path(&dir.0, 0);
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        let store = LogStore::open(&dir.config()).unwrap();
+        assert_eq!(store.active.segment.size, HEADER_LEN + 2 * 145);
+        drop(store);
+        assert_eq!(fs::metadata(&path).unwrap().len(), HEADER_LEN + 2 * 145);
+        assert_eq!(read_all(&dir, None), (1..=2).map(entry).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn rebuilds_index() {
+        let dir = TempDir::new("index");
+        write(&dir, 1..=200);
+        let idx = index_path(&dir.0, 0);
+        let written = fs::read(&idx).unwrap();
+        assert!(written.len() > INDEX_ENTRY_LEN);
+
+        fs::remove_file(&idx).unwrap();
+        let store = LogStore::open(&dir.config()).unwrap();
+        assert_eq!(fs::read(&idx).unwrap(), written);
+        assert_eq!(store.active.segment.index_size, written.len() as u64);
+    }
+
+    #[test]
+    fn retains_by_size() {
+        let dir = TempDir::new("size");
+        let mut store = LogStore::open(&dir.config()).unwrap();
+        store.segment_bytes = 1024;
+        for time_ns in 1..=50 {
+            store.append(&entry(time_ns)).unwrap();
+        }
+        store.sync().unwrap();
+        assert!(store.sealed.len() > 3);
+        assert_eq!(store.bytes(), dir.bytes());
+
+        store.max_bytes = Some(3 * 1024);
+        assert!(store.retain().unwrap() > 0);
+        assert!(store.bytes() <= 3 * 1024);
+        assert_eq!(store.bytes(), dir.bytes());
+        // 删掉的是最旧的段
+        let kept = read_all(&dir, None);
+        assert_eq!(kept.last().unwrap().time_ns, 50);
+        assert!(kept.first().unwrap().time_ns > 1);
+    }
+
+    #[test]
+    fn retains_by_age() {
+        let dir = TempDir::new("age");
+        let mut store = LogStore::open(&dir.config()).unwrap();
+        store.segment_bytes = 1024;
+        for time_ns in 1..=20 {
+            store.append(&entry(time_ns)).unwrap();
+        }
+        // 新旧记录不落在同一段
+        store.roll().unwrap();
+        let now = unix_now_ns();
+        for time_ns in now..now + 20 {
+            store.append(&entry(time_ns)).unwrap();
+        }
+        store.sync().unwrap();
+
+        store.max_age_ns = Some(3600 * 1_000_000_000);
+        assert!(store.retain().unwrap() > 0);
+        assert!(store
+            .sealed
+            .iter()
+            .all(|segment| segment.last_time.unwrap() >= now));
+        assert!(read_all(&dir, None)
+            .iter()
+            .all(|entry| entry.time_ns >= now));
+    }
+
+    #[test]
+    fn read_seeks_from_index() {
+        let dir = TempDir::new("seek");
+        write(&dir, 1..=200);
+        let index = read_index(&index_path(&dir.0, 0), u64::MAX).unwrap();
+        let (from, offset) = index[2];
+        assert!(offset > HEADER_LEN);
+
+        // 破坏第一条记录：从头读到这里就停下，按索引起读时跳过了它
+        let path = segment_path(&dir.0, 0);
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[HEADER_LEN as usize + FRAME_HEADER_LEN as usize] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        assert!(read_all(&dir, None).is_empty());
+        assert_eq!(
+            read_all(&dir, Some(from)),
+            (from..=200).map(entry).collect::<Vec<_>>()
+        );
+    }
+}
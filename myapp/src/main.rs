@@ -0,0 +1,61 @@
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use env_logger::Env;
+
+mod config;
+mod daemon;
+mod detect;
+mod hardworker;
+mod loader;
+mod logger;
+mod rule;
+mod run;
+mod send;
+mod sensor;
+
+#[derive(Debug, Parser)]
+struct Opt {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// 配置文件工具
+    #[command(subcommand)]
+    Config(config::ConfigCommand),
+    /// 常驻运行，固定本机到各对端的邻居表项并监视其变化
+    Daemon(daemon::DaemonArgs),
+    /// 加载hardworker程序，捕获sensor的数据并转发给logger
+    Hardworker(hardworker::HardworkerArgs),
+    /// 加载logger程序，把收到的报文记为日志，或查询日志存储
+    Logger(logger::LoggerArgs),
+    /// 增删运行中程序的分类规则
+    #[command(subcommand)]
+    Rule(rule::RuleCommand),
+    /// 按本机网卡的MAC与IP识别节点，自动选择角色与网卡并加载
+    Run(run::RunArgs),
+    /// 以sensor身份通过可靠UDP向hardworker发送测试数据报
+    Send(send::SendArgs),
+    /// 加载sensor程序，把logger的回包还原为来自hardworker
+    Sensor(sensor::SensorArgs),
+}
+
+fn main() -> anyhow::Result<ExitCode> {
+    let opt = Opt::parse();
+
+    // 默认显示info及以上，daemon的告警等不需要设置RUST_LOG也能看到
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    match opt.command {
+        Command::Config(command) => config::run(command),
+        Command::Daemon(args) => daemon::run(args),
+        Command::Hardworker(args) => hardworker::run(args),
+        Command::Logger(args) => logger::run(args),
+        Command::Rule(command) => rule::run(command),
+        Command::Run(args) => run::run(args),
+        Command::Send(args) => send::run(args),
+        Command::Sensor(args) => sensor::run(args),
+    }
+}
@@ -0,0 +1,96 @@
+use std::process::ExitCode;
+
+use clap::{Args, Subcommand};
+use common::{
+    classify::{parse_proto, Action, Prefix, Rule, Rules},
+    config::Role,
+};
+
+#[derive(Debug, Subcommand)]
+pub enum RuleCommand {
+    /// 添加一条规则，匹配条件相同的规则被覆盖，网段不能与已有规则的网段重叠。
+    /// 覆盖默认规则后它不再随配置变化
+    Add {
+        #[clap(flatten)]
+        filter: Filter,
+        /// capture、forward、pass或drop
+        #[clap(short, long)]
+        action: Action,
+    },
+    /// 按添加时的匹配条件删除一条规则
+    Remove {
+        #[clap(flatten)]
+        filter: Filter,
+    },
+    /// 列出程序当前的全部规则
+    List {
+        /// 规则所属的程序：sensor、hardworker或logger
+        #[clap(short, long)]
+        role: Role,
+    },
+}
+
+/// 规则的匹配条件，省略的网段与DSCP表示任意
+#[derive(Debug, Args)]
+pub struct Filter {
+    /// 规则所属的程序：sensor、hardworker或logger
+    #[clap(short, long)]
+    role: Role,
+    /// 源地址前缀，如10.0.0.0/8
+    #[clap(long)]
+    src: Option<Prefix>,
+    /// 目的地址前缀
+    #[clap(long)]
+    dst: Option<Prefix>,
+    /// tcp、udp或协议号
+    #[clap(long, default_value = "tcp", value_parser = parse_proto)]
+    proto: u8,
+    /// 服务端口，hardworker与logger按目的端口匹配，sensor按源端口匹配
+    #[clap(short, long)]
+    port: u16,
+    /// TOS的高6位，标记TOS为104时是26
+    #[clap(long, value_parser = clap::value_parser!(u8).range(0..64))]
+    dscp: Option<u8>,
+}
+
+impl Filter {
+    fn rule(&self, action: Action) -> Rule {
+        Rule {
+            src: self.src,
+            dst: self.dst,
+            proto: self.proto,
+            port: self.port,
+            dscp: self.dscp,
+            action,
+        }
+    }
+}
+
+/// 直接改写加载器固定在bpffs中的表，运行中的程序立即生效
+pub fn run(command: RuleCommand) -> anyhow::Result<ExitCode> {
+    match command {
+        RuleCommand::Add { filter, action } => {
+            let rule = filter.rule(action);
+            Rules::open(&filter.role.pin_dir())?.insert(&rule)?;
+            println!("{}添加规则: {rule}", filter.role);
+        }
+        RuleCommand::Remove { filter } => {
+            // 删除只按匹配条件查找，动作不参与
+            let rule = filter.rule(Action::Pass);
+            Rules::open(&filter.role.pin_dir())?.remove(&rule)?;
+            println!("{}删除规则", filter.role);
+        }
+        RuleCommand::List { role } => {
+            let rules = Rules::open(&role.pin_dir())?.list()?;
+            println!("{role}共{}条规则", rules.len());
+            for (rule, default) in &rules {
+                if *default {
+                    println!("  {rule} (默认)");
+                } else {
+                    println!("  {rule}");
+                }
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,32 @@
+use std::process::ExitCode;
+
+use clap::Args;
+use common::config::Role;
+
+use crate::{
+    hardworker,
+    loader::{self, AttachArgs},
+    logger, sensor,
+};
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    #[clap(flatten)]
+    attach: AttachArgs,
+    /// 本机的角色：sensor、hardworker或logger，省略时按本机网卡识别
+    #[clap(short, long)]
+    role: Option<Role>,
+}
+
+/// 按本机网卡匹配配置中的节点，以该节点的角色运行
+pub fn run(args: RunArgs) -> anyhow::Result<ExitCode> {
+    loader::block_on(async {
+        let target = args.attach.target(args.role).await?;
+        let xdp_mode = args.attach.xdp_mode;
+        match target.node.role {
+            Role::Sensor => sensor::sensor(target, xdp_mode).await,
+            Role::Hardworker => hardworker::hardworker(target, xdp_mode).await,
+            Role::Logger => logger::logger(target, xdp_mode, None).await,
+        }
+    })
+}
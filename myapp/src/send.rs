@@ -0,0 +1,81 @@
+use std::{net::SocketAddrV4, path::PathBuf, process::ExitCode};
+
+use anyhow::{bail, Context as _};
+use clap::Args;
+use common::{
+    config::{Consts, Role},
+    rudp::{RudpHdr, Sender},
+};
+
+use crate::loader::DEFAULT_CONFIG;
+
+/// IPv4与UDP的固定头部长度
+const HEADERS_LEN: usize = 20 + 8;
+
+#[derive(Debug, Args)]
+pub struct SendArgs {
+    /// const.toml格式的配置文件，默认为/etc/myapp/const.toml
+    #[clap(short, long, default_value = DEFAULT_CONFIG)]
+    config: PathBuf,
+    /// 本机在配置中的sensor节点名，配置里只有一个sensor时可省略
+    #[clap(short, long)]
+    node: Option<String>,
+    /// 发送的数据报个数
+    #[clap(long, default_value_t = 10)]
+    count: u32,
+    /// 每个数据报的负载字节数，不含可靠UDP头部
+    #[clap(long, default_value_t = 64)]
+    size: usize,
+}
+
+/// 以本机sensor的身份向它的hardworker逐个发送数据报，打印重传与失败情况
+pub fn run(args: SendArgs) -> anyhow::Result<ExitCode> {
+    let consts = Consts::from_path(&args.config)?;
+    let node = consts.node(Role::Sensor, args.node.as_deref())?;
+    let udp = consts.udp.context("配置中没有[udp]，可靠UDP未启用")?;
+    let hardworker = node
+        .hardworker
+        .as_deref()
+        .and_then(|name| consts.get(name))
+        .context("sensor没有对应的hardworker")?;
+    let len = HEADERS_LEN + RudpHdr::LEN + args.size;
+    if len > consts.data.mtu {
+        bail!(
+            "负载{}字节加上头部共{len}字节，超过mtu {}",
+            args.size,
+            consts.data.mtu
+        );
+    }
+
+    let target = SocketAddrV4::new(hardworker.ip, udp.port);
+    let sensor = consts.sensor_id(node);
+    let mut sender = Sender::connect(target, consts.mark.tos, sensor, udp.timeout(), udp.retries)
+        .with_context(|| format!("连接{target}失败"))?;
+    println!(
+        "{} (id {sensor}) -> {} {target}, 超时{}ms, 重传{}次",
+        node.name, hardworker.name, udp.timeout_ms, udp.retries
+    );
+
+    let mut failed = 0;
+    for i in 0..args.count {
+        let seq = sender.seq();
+        let payload = vec![i as u8; args.size];
+        match sender.send(&payload) {
+            Ok(0) => {}
+            Ok(retransmits) => println!("seq {seq}重传{retransmits}次后确认"),
+            Err(e) => {
+                eprintln!("seq {seq}发送失败: {e}");
+                failed += 1;
+            }
+        }
+    }
+    println!(
+        "发送{}个，失败{failed}个，共重传{}次",
+        args.count, sender.retransmits
+    );
+    Ok(if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
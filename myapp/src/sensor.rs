@@ -0,0 +1,77 @@
+use std::{os::unix::fs::MetadataExt, process::ExitCode};
+
+use anyhow::Context as _;
+use clap::Args;
+use common::{
+    config::{Consts, Node, Role},
+    Marking,
+};
+
+use crate::loader::{self, AttachArgs, Target, XdpMode};
+
+#[derive(Debug, Args)]
+pub struct SensorArgs {
+    #[clap(flatten)]
+    attach: AttachArgs,
+}
+
+pub fn run(args: SensorArgs) -> anyhow::Result<ExitCode> {
+    loader::block_on(async {
+        let target = args.attach.target(Some(Role::Sensor)).await?;
+        sensor(target, args.attach.xdp_mode).await
+    })
+}
+
+pub async fn sensor(target: Target, xdp_mode: XdpMode) -> anyhow::Result<()> {
+    let Target {
+        consts,
+        node,
+        iface,
+    } = target;
+
+    let marking = marking(&consts, &node)?;
+    let mut ebpf = loader::load(Role::Sensor, |builder| {
+        builder.set_global("MARKING", &marking, true);
+    })?;
+    loader::install(&mut ebpf, &consts, &node)?;
+    loader::attach(&mut ebpf, Role::Sensor, &iface, node.hook, xdp_mode)?;
+    if marking.is_empty() {
+        println!("没有配置出口标记，发往hardworker的报文需要应用自己设置IP_TOS");
+    } else {
+        loader::attach_egress(&mut ebpf, &iface)?;
+        let cgroup = match &consts.sensor.cgroup {
+            Some(cgroup) => format!("，{}中的进程不论端口", cgroup.display()),
+            None => String::new(),
+        };
+        println!(
+            "在{iface}的egress为发往hardworker端口{:?}的报文打标记{cgroup}",
+            consts.sensor.ports
+        );
+    }
+
+    let stats = loader::take_stats(&mut ebpf)?;
+    // 收到SIGUSR1时汇总打印各CPU的计数
+    loader::on_sigusr1({
+        let stats = stats.clone();
+        move || loader::print_stats(&stats)
+    })?;
+
+    loader::wait_for_shutdown().await?;
+
+    loader::print_stats(&stats);
+
+    Ok(())
+}
+
+/// 出口标记规则，配置了cgroup时读出它的id，cgroup v2的id就是目录的inode号
+fn marking(consts: &Consts, node: &Node) -> anyhow::Result<Marking> {
+    let cgroup = match (&consts.sensor.cgroup, consts.sensor.cgroup_level()) {
+        (Some(path), Some(level)) => {
+            let metadata = std::fs::metadata(path)
+                .with_context(|| format!("读取cgroup {}失败，考虑目录不存在", path.display()))?;
+            Some((metadata.ino(), level))
+        }
+        _ => None,
+    };
+    Ok(consts.marking(node, cgroup))
+}
@@ -0,0 +1,229 @@
+#!/usr/bin/env bash
+# 在三个网络命名空间里跑通sensor、hardworker、logger的完整三角：
+# sensor连接hardworker的服务端口，hardworker把段转发给logger，logger把目的地址与端口
+# 还原为自己的监听端口，回包直接发回sensor，由sensor还原成来自hardworker。
+# 握手、双向数据与双方的FIN关闭都成功才算通过。
+# 之后以ACK模式再跑一次：hardworker在XDP中确认数据段，一次大的写入拆成的段大多不带PSH，
+# 这些段也必须全部记入logger的存储。
+#
+# 需要root、iproute2、ethtool、python3，以及myapp的release构建：
+#   cargo build --release
+# 构建时编入的[data]取自仓库的const.toml，这里生成的配置沿用同一节
+set -euo pipefail
+
+ROOT=$(cd "$(dirname "$0")/.." && pwd)
+PREFIX=${PREFIX:-myapp}
+SIZE=${SIZE:-200000}
+SERVICE_PORT=12345
+LISTEN_PORT=23456
+TOS=104
+
+declare -A IPS=([sensor]=10.99.0.1 [hardworker]=10.99.0.2 [logger]=10.99.0.3)
+declare -A MACS=([sensor]=02:99:00:00:00:01 [hardworker]=02:99:00:00:00:02 [logger]=02:99:00:00:00:03)
+ROLES=(sensor hardworker logger)
+MYAPP=$ROOT/target/release/myapp
+
+WORK=$(mktemp -d)
+PIDS=()
+
+fail() {
+    echo "失败: $*" >&2
+    for role in "${ROLES[@]}"; do
+        if [[ -f $WORK/$role.log ]]; then
+            echo "--- $role输出 ---" >&2
+            tail -n 30 "$WORK/$role.log" >&2
+        fi
+    done
+    exit 1
+}
+
+# 停止全部后台进程，myapp收到SIGINT后卸载程序、落盘并打印统计
+stop() {
+    for pid in "${PIDS[@]}"; do
+        kill -INT "$pid" 2>/dev/null || true
+    done
+    wait 2>/dev/null || true
+    PIDS=()
+}
+
+cleanup() {
+    stop
+    for ns in "${ROLES[@]}" br; do
+        ip netns del "$PREFIX-$ns" 2>/dev/null || true
+    done
+    rm -rf "$WORK"
+}
+trap cleanup EXIT
+
+[[ $EUID -eq 0 ]] || fail "需要root运行"
+[[ -x $MYAPP ]] || fail "找不到target/release/myapp，先构建"
+for tool in ip ethtool python3; do
+    command -v "$tool" >/dev/null || fail "找不到$tool"
+done
+
+# 每个角色一个命名空间，经另一个命名空间里的网桥二层互通
+ip netns add "$PREFIX-br"
+ip -n "$PREFIX-br" link add br0 type bridge
+ip -n "$PREFIX-br" link set br0 up
+for role in "${ROLES[@]}"; do
+    ns=$PREFIX-$role
+    ip netns add "$ns"
+    ip link add eth0 netns "$ns" type veth peer name "$role" netns "$PREFIX-br"
+    ip -n "$ns" link set eth0 address "${MACS[$role]}"
+    ip -n "$ns" addr add "${IPS[$role]}/24" dev eth0
+    ip -n "$ns" link set eth0 up
+    ip -n "$ns" link set lo up
+    ip -n "$PREFIX-br" link set "$role" master br0 up
+    # veth上XDP_TX发出的帧要由对端的NAPI接收，打开GRO即可
+    ip netns exec "$PREFIX-br" ethtool -K "$role" gro on >/dev/null
+done
+
+# 生成配置，$1为hardworker.ack，ACK模式下logger把记录写入存储以便核对
+write_config() {
+    local ack=$1
+    CONFIG=$WORK/const-$ack.toml
+    {
+        printf '[mark]\ntos = %d\nport = %d\n\n' "$TOS" "$SERVICE_PORT"
+        # 沿用构建时的[data]
+        awk '/^\[data\]/{p=1; print; next} /^\[/{p=0} p && NF && !/^#/' "$ROOT/const.toml"
+        printf '\n[hardworker]\nack = %s\ncapture = "ring"\n' "$ack"
+        if [[ $ack == true ]]; then
+            printf '\n[store]\ndir = "%s"\n' "$WORK/store"
+        fi
+        printf '\n[[node]]\nname = "logger"\nrole = "logger"\nmac = "%s"\nip = "%s"\nport = %d\n' \
+            "${MACS[logger]}" "${IPS[logger]}" "$LISTEN_PORT"
+        printf '\n[[node]]\nname = "hardworker"\nrole = "hardworker"\nmac = "%s"\nip = "%s"\n' \
+            "${MACS[hardworker]}" "${IPS[hardworker]}"
+        printf '\n[[node]]\nname = "sensor"\nrole = "sensor"\nmac = "%s"\nip = "%s"\nhardworker = "hardworker"\nloggers = ["logger"]\n' \
+            "${MACS[sensor]}" "${IPS[sensor]}"
+    } >"$CONFIG"
+}
+
+# 在各自的命名空间里加载三个程序，等到都准备完成
+start() {
+    # ip netns exec会重新挂载/sys，每个程序在自己的挂载命名空间里挂一个新的bpffs存放固定的表
+    for role in "${ROLES[@]}"; do
+        ip netns exec "$PREFIX-$role" sh -c \
+            'mount -t bpf bpf /sys/fs/bpf && exec "$0" "$1" --iface eth0 --config "$2"' \
+            "$MYAPP" "$role" "$CONFIG" >"$WORK/$role.log" 2>&1 &
+        PIDS+=($!)
+    done
+    for role in "${ROLES[@]}"; do
+        for _ in $(seq 50); do
+            grep -q "准备完成" "$WORK/$role.log" && continue 2
+            sleep 0.2
+        done
+        fail "$role没有启动"
+    done
+    echo "三个程序已加载"
+}
+
+# logger监听自己的端口：收完数据后回复长度与摘要，再关闭
+serve() {
+    ip netns exec "$PREFIX-logger" python3 - "$LISTEN_PORT" >"$WORK/server.log" 2>&1 <<'EOF' &
+import hashlib, socket, sys
+server = socket.socket()
+server.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
+server.bind(("0.0.0.0", int(sys.argv[1])))
+server.listen(1)
+server.settimeout(20)
+conn, peer = server.accept()
+print(f"接受来自{peer[0]}:{peer[1]}的连接", flush=True)
+conn.settimeout(20)
+digest, total = hashlib.sha256(), 0
+while data := conn.recv(65536):
+    digest.update(data)
+    total += len(data)
+conn.sendall(f"{total} {digest.hexdigest()}".encode())
+conn.close()
+EOF
+    PIDS+=($!)
+    sleep 0.5
+}
+
+write_config false
+start
+serve
+
+# sensor带标记TOS连接hardworker的服务端口，发完后半关闭，读到logger的回复与FIN
+ip netns exec "$PREFIX-sensor" python3 - "${IPS[hardworker]}" "$SERVICE_PORT" "$TOS" "$SIZE" <<'EOF' \
+    || fail "sensor端的连接没有正常完成"
+import hashlib, os, socket, sys
+host, port, tos, size = sys.argv[1], int(sys.argv[2]), int(sys.argv[3]), int(sys.argv[4])
+sock = socket.socket()
+sock.setsockopt(socket.IPPROTO_IP, socket.IP_TOS, tos)
+sock.settimeout(20)
+sock.connect((host, port))
+print(f"与{host}:{port}握手完成，本地{sock.getsockname()[1]}", flush=True)
+payload = os.urandom(size)
+sock.sendall(payload)
+sock.shutdown(socket.SHUT_WR)
+reply = b""
+while data := sock.recv(4096):
+    reply += data
+sock.close()
+expected = f"{size} {hashlib.sha256(payload).hexdigest()}"
+if reply.decode() != expected:
+    sys.exit(f"logger的回复为{reply!r}，应为{expected}")
+print(f"发送{size}字节，logger确认一致，连接已关闭")
+EOF
+
+grep -q "接受来自${IPS[sensor]}:" "$WORK/server.log" || fail "logger看到的对端不是sensor: $(cat "$WORK/server.log")"
+grep -q "src=${IPS[sensor]}:" "$WORK/logger.log" || fail "logger没有生成日志"
+echo "通过: 握手、数据与FIN关闭均经三个程序完成，logger看到的对端为sensor，sensor看到的对端为hardworker"
+stop
+
+# ACK模式：数据段由hardworker确认后丢弃，只有记录经UDP转发到logger。
+# 网桥端口对挂了XDP的veth做软件分段，一次写入拆成的段只有最后一个带PSH
+write_config true
+start
+serve
+
+port=$(ip netns exec "$PREFIX-sensor" python3 - "${IPS[hardworker]}" "$SERVICE_PORT" "$TOS" "$SIZE" <<'EOF'
+import fcntl, os, socket, struct, sys, termios, time
+host, port, tos, size = sys.argv[1], int(sys.argv[2]), int(sys.argv[3]), int(sys.argv[4])
+sock = socket.socket()
+sock.setsockopt(socket.IPPROTO_IP, socket.IP_TOS, tos)
+sock.settimeout(20)
+sock.connect((host, port))
+sock.sendall(os.urandom(size))
+# 等到发送队列清空，即所有数据都已被hardworker确认
+deadline = time.monotonic() + 20
+while (queued := struct.unpack("i", fcntl.ioctl(sock, termios.TIOCOUTQ, b"\0" * 4))[0]) > 0:
+    if time.monotonic() > deadline:
+        sys.exit(f"还有{queued}字节没有被确认")
+    time.sleep(0.05)
+print(sock.getsockname()[1])
+# logger没有收到数据，FIN无法正常完成，直接复位
+sock.setsockopt(socket.SOL_SOCKET, socket.SO_LINGER, struct.pack("ii", 1, 0))
+sock.close()
+EOF
+) || fail "ACK模式下sensor的数据没有全部被确认"
+sleep 0.5
+stop
+
+psh=$(grep -o 'PSH: [0-9]*' "$WORK/hardworker.log" | tail -n 1 | cut -d' ' -f2)
+"$MYAPP" logger query --config "$CONFIG" --sensor "${IPS[sensor]}" --format csv >"$WORK/records.csv" \
+    || fail "查询logger的存储失败"
+python3 - "$WORK/records.csv" "${IPS[sensor]}:$port" "$SIZE" "${psh:-0}" <<'EOF' \
+    || fail "ACK模式下logger的记录与发送的数据不符"
+import csv, sys
+path, src, size, psh = sys.argv[1], sys.argv[2], int(sys.argv[3]), int(sys.argv[4])
+rows = [row for row in csv.DictReader(open(path)) if row["src"] == src and int(row["len"]) > 0]
+if not rows:
+    sys.exit("没有这条流的记录")
+# 以第一条记录为基准换算相对序列号，合并后应恰好覆盖发送的全部字节
+base = int(min(rows, key=lambda row: int(row["mono_ns"]))["seq"])
+spans = sorted(((int(row["seq"]) - base) % 2**32, int(row["len"])) for row in rows)
+end = 0
+for start, length in spans:
+    if start > end:
+        sys.exit(f"缺少相对序列号[{end}, {start})的数据")
+    end = max(end, start + length)
+if end != size:
+    sys.exit(f"记录覆盖{end}字节，应为{size}")
+if len(rows) <= psh:
+    sys.exit(f"{len(rows)}条记录不多于{psh}个PSH段，没有覆盖不带PSH的段")
+print(f"ACK模式: {len(rows)}条记录覆盖全部{size}字节，其中只有{psh}个段带PSH")
+EOF
+echo "通过: ACK模式下不带PSH的数据段也全部记入logger"
@@ -7,8 +7,9 @@ def send_max_tcp(ifname, ip, port, tos, size):
         # 创建 TCP socket
         sock = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
         
-        # 设置 TOS 字段 (需要 root 权限)
-        sock.setsockopt(socket.IPPROTO_IP, socket.IP_TOS, tos)
+        # 设置 TOS 字段 (需要 root 权限)，为0时不设置，由sensor的出口程序打标记
+        if tos:
+            sock.setsockopt(socket.IPPROTO_IP, socket.IP_TOS, tos)
         
         # 绑定到指定网卡 (可选)
         # sock.setsockopt(socket.SOL_SOCKET, socket.SO_BINDTODEVICE, ifname.encode())
@@ -33,7 +34,7 @@ if __name__ == "__main__":
     parser.add_argument("--ip", default="192.168.1.79", help="目标IP地址 (默认: 192.168.1.79)")
     parser.add_argument("--port", type=int, default=12345, help="目标端口 (默认: 12345)")
     parser.add_argument("--tos", type=lambda x: int(x, 0), default=0x6c, 
-                       help="TOS值，可以是十进制、十六进制(0x6c)或二进制(0b01101000) (默认: 0x6c)")
+                       help="TOS值，可以是十进制、十六进制(0x6c)或二进制(0b01101000)，0表示不设置 (默认: 0x6c)")
     parser.add_argument("--size", type=int, default=1200, help="发送的数据大小(字节) (默认: 1200)")
     
     args = parser.parse_args()